@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use mavlink_network_node::adr::{AdaptiveDataRate, AdaptiveDataRateDriver, AdrOptionalConfig};
+use mavlink_network_node::channel_access::ChannelAccessDriver;
+use mavlink_network_node::half_duplex_network::HalfDuplexNetwork;
+use mavlink_network_node::lora_airtime::LoRaModulation;
+use mavlink_network_node::mavlink_utils::MavlinkHeaderGenerator;
+use mavlink_network_node::simulated_air::SimulatedAir;
+use mavlink_network_node::simulated_lora_driver::{SimulatedLoRaDriver, SimulatedLoRaOptionalInitConfig};
+use mavlink_network_node::{Driver, LoRaRadio, NetworkInterface};
+use tokio::time::Instant;
+
+const HEARTBEAT_INTERVAL_MS: u64 = 500;
+// SNR at 125 kHz the nodes hear each other with, and for how long
+const PHASES: [(&str, i16, u64); 3] = [("close", 6, 14), ("moving away", -2, 8), ("out of range", -15, 8)];
+
+/// Runs a gateway and a UAV over a simulated LoRa channel starting at SF9 and 125 kHz, moves them
+/// apart in phases and prints the modulation changes the adaptive data rate of the gateway proposes,
+/// faster while close, more robust while moving away and back to SF9 once contact is lost.
+/// Usage: adr
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    std::env::set_var("NODE_TYPE", "Uav");
+
+    let air = SimulatedAir::new();
+    let node = |controller: bool| {
+        let radio = SimulatedLoRaDriver::new(
+            air.clone(),
+            Some(SimulatedLoRaOptionalInitConfig {
+                spreading_factor: Some(9),
+                bandwidth_hz: Some(125_000),
+                rssi_dbm: Some(-100),
+                snr_db: Some(PHASES[0].1),
+                ..Default::default()
+            }),
+        );
+        let adr = AdaptiveDataRate::new(
+            radio.modulation().unwrap(),
+            Some(AdrOptionalConfig {
+                controller: Some(controller),
+                window: Some(4),
+                hold_ms: Some(1000),
+                proposal_timeout_ms: Some(1300),
+                fallback_timeout_ms: Some(4000),
+                ..Default::default()
+            }),
+        );
+        let radio = Arc::new(radio.with_link_layer(adr.link_layer()));
+        // Listens before talking, so a proposal or its acknowledgement does not go out while the peer transmits
+        let driver = Arc::new(ChannelAccessDriver::new(radio.clone(), None));
+        (radio, Arc::new(AdaptiveDataRateDriver::new(driver, adr)))
+    };
+    let (gateway_radio, gateway_driver) = node(true);
+    let (uav_radio, uav_driver) = node(false);
+    let (gateway, gateway_tx, mut gateway_rx) = HalfDuplexNetwork::new(gateway_driver, 100);
+    let (uav, uav_tx, mut uav_rx) = HalfDuplexNetwork::new(uav_driver, 100);
+    gateway.run().await;
+    uav.run().await;
+    tokio::spawn(async move { while gateway_rx.recv().await.is_some() {} });
+    tokio::spawn(async move { while uav_rx.recv().await.is_some() {} });
+
+    // The UAV sends half an interval after the gateway, so their heartbeats do not collide
+    for (tx, offset_ms) in [(gateway_tx, 0), (uav_tx, HEARTBEAT_INTERVAL_MS / 2)] {
+        let generator = MavlinkHeaderGenerator::new();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(offset_ms)).await;
+            let mut interval = tokio::time::interval(Duration::from_millis(HEARTBEAT_INTERVAL_MS));
+            loop {
+                interval.tick().await;
+                tx.send(generator.create_mavlink_heartbeat_frame()).await.unwrap();
+            }
+        });
+    }
+
+    let start = Instant::now();
+    let mut modulations = (gateway_radio.modulation(), uav_radio.modulation());
+    for (name, snr_db, duration_s) in PHASES {
+        println!("{:>5.1}s {} at {} dB SNR", start.elapsed().as_secs_f64(), name, snr_db);
+        gateway_radio.set_signal(-100, snr_db);
+        uav_radio.set_signal(-100, snr_db);
+        let phase_end = Instant::now() + Duration::from_secs(duration_s);
+        while Instant::now() < phase_end {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let current = (gateway_radio.modulation(), uav_radio.modulation());
+            if current != modulations {
+                modulations = current;
+                println!(
+                    "{:>5.1}s gateway {}, uav {}",
+                    start.elapsed().as_secs_f64(),
+                    describe(current.0),
+                    describe(current.1)
+                );
+            }
+        }
+    }
+}
+
+fn describe(modulation: Option<LoRaModulation>) -> String {
+    modulation.map_or("none".to_string(), |modulation| {
+        format!(
+            "SF{}/{} kHz",
+            modulation.spreading_factor,
+            modulation.bandwidth_hz / 1000
+        )
+    })
+}
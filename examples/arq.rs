@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mavlink::ardupilotmega::{MavMessage, COMMAND_LONG_DATA};
+use mavlink::{MavHeader, MavlinkVersion};
+use mavlink_network_node::arq::ArqLayer;
+use mavlink_network_node::channel_access::ChannelAccessDriver;
+use mavlink_network_node::half_duplex_network::HalfDuplexNetwork;
+use mavlink_network_node::mavlink_utils::MavlinkHeaderGenerator;
+use mavlink_network_node::simulated_air::SimulatedAir;
+use mavlink_network_node::simulated_lora_driver::{SimulatedLoRaDriver, SimulatedLoRaOptionalInitConfig};
+use mavlink_network_node::types::{MavFramePacket, NodeType};
+use mavlink_network_node::{LoRaRadio, NetworkInterface};
+
+const COMMAND_INTERVAL_MS: u64 = 500;
+const HEARTBEAT_INTERVAL_MS: u64 = 250;
+const RUN_S: u64 = 20;
+const DRAIN_S: u64 = 10;
+
+/// Sends commands from a gateway and heartbeats from a UAV over a lossy simulated LoRa channel, with
+/// the ARQ layer or, with `best_effort`, without it, and prints the share of each that arrived.
+/// Usage: arq <packet loss between 0 and 1> [best_effort]
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let packet_loss = args.get(1).map_or(0.3, |loss| loss.parse().unwrap());
+    let best_effort = args.get(2).is_some_and(|mode| mode == "best_effort");
+    std::env::set_var("NODE_TYPE", "Uav");
+
+    let air = SimulatedAir::new();
+    let lossy_driver = |node_type| {
+        let driver = SimulatedLoRaDriver::new(
+            air.clone(),
+            Some(SimulatedLoRaOptionalInitConfig {
+                packet_loss: Some(packet_loss),
+                ..Default::default()
+            }),
+        );
+        let driver = if best_effort {
+            driver
+        } else {
+            driver.with_link_layer(ArqLayer::new(node_type, None))
+        };
+        // Listens before talking, so an acknowledgement does not go out while the sender still transmits
+        Arc::new(ChannelAccessDriver::new(Arc::new(driver), None))
+    };
+    let (gateway, gateway_tx, mut gateway_rx) = HalfDuplexNetwork::new(lossy_driver(NodeType::Gateway), 100);
+    let (uav, uav_tx, mut uav_rx) = HalfDuplexNetwork::new(lossy_driver(NodeType::Uav), 100);
+    gateway.run().await;
+    uav.run().await;
+
+    let commands_received = Arc::new(Mutex::new(HashSet::new()));
+    let duplicates = Arc::new(AtomicU64::new(0));
+    {
+        let commands_received = commands_received.clone();
+        let duplicates = duplicates.clone();
+        tokio::spawn(async move {
+            while let Some(packet) = uav_rx.recv().await {
+                if let MavMessage::COMMAND_LONG(command) = packet.msg {
+                    if !commands_received.lock().unwrap().insert(command.param1 as u64) {
+                        duplicates.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        });
+    }
+    let heartbeats_received = Arc::new(AtomicU64::new(0));
+    {
+        let heartbeats_received = heartbeats_received.clone();
+        tokio::spawn(async move {
+            while let Some(packet) = gateway_rx.recv().await {
+                if matches!(packet.msg, MavMessage::HEARTBEAT(_)) {
+                    heartbeats_received.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+    }
+
+    let commands_sent = Arc::new(AtomicU64::new(0));
+    let gateway_sender = {
+        let commands_sent = commands_sent.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(COMMAND_INTERVAL_MS));
+            for sequence in 0u64.. {
+                interval.tick().await;
+                gateway_tx.send(command_frame(sequence)).await.unwrap();
+                commands_sent.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+    };
+    let heartbeats_sent = Arc::new(AtomicU64::new(0));
+    let uav_sender = {
+        let heartbeats_sent = heartbeats_sent.clone();
+        tokio::spawn(async move {
+            let generator = MavlinkHeaderGenerator::new();
+            // Offset from the commands, so the two nodes do not transmit at the same time on every command
+            let period = Duration::from_millis(HEARTBEAT_INTERVAL_MS);
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period / 2, period);
+            loop {
+                interval.tick().await;
+                uav_tx.send(generator.create_mavlink_heartbeat_frame()).await.unwrap();
+                heartbeats_sent.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+    };
+
+    tokio::time::sleep(Duration::from_secs(RUN_S)).await;
+    gateway_sender.abort();
+    uav_sender.abort();
+    // Lets the last commands be retransmitted
+    tokio::time::sleep(Duration::from_secs(DRAIN_S)).await;
+
+    let percent = |received: u64, sent: u64| 100.0 * received as f64 / sent as f64;
+    let commands_sent = commands_sent.load(Ordering::Relaxed);
+    let heartbeats_sent = heartbeats_sent.load(Ordering::Relaxed);
+    println!(
+        "{} at {:.0}% packet loss: {:.1}% of {} commands received, {} duplicates, {:.1}% of {} heartbeats received",
+        if best_effort { "Best effort" } else { "ARQ" },
+        packet_loss * 100.0,
+        percent(commands_received.lock().unwrap().len() as u64, commands_sent),
+        commands_sent,
+        duplicates.load(Ordering::Relaxed),
+        percent(heartbeats_received.load(Ordering::Relaxed), heartbeats_sent),
+        heartbeats_sent
+    );
+}
+
+/// COMMAND_LONG from the gateway to the UAV, numbered through its first parameter
+fn command_frame(sequence: u64) -> MavFramePacket {
+    MavFramePacket {
+        header: MavHeader {
+            system_id: 101,
+            component_id: 1,
+            sequence: sequence as u8,
+        },
+        msg: MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+            param1: sequence as f32,
+            target_system: 201,
+            target_component: 1,
+            ..Default::default()
+        }),
+        protocol_version: MavlinkVersion::V2,
+    }
+}
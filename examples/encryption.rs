@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mavlink::ardupilotmega::{MavMessage, COMMAND_LONG_DATA};
+use mavlink::{MavHeader, MavlinkVersion};
+use mavlink_network_node::encryption::{EncryptionLayer, EncryptionOptionalConfig, ENCRYPTED_PAYLOADS_REJECTED_METRIC};
+use mavlink_network_node::half_duplex_network::HalfDuplexNetwork;
+use mavlink_network_node::mavlink_utils::{deserialize_frame, serialize_frame};
+use mavlink_network_node::metrics::metrics;
+use mavlink_network_node::simulated_air::SimulatedAir;
+use mavlink_network_node::simulated_lora_driver::SimulatedLoRaDriver;
+use mavlink_network_node::types::{MavFramePacket, NodeType};
+use mavlink_network_node::{Driver, LoRaRadio, NetworkInterface};
+
+const COMMAND_COUNT: u32 = 10;
+const COMMAND_INTERVAL_MS: u64 = 300;
+const KEY: [u8; 32] = [0x3c; 32];
+
+/// Sends commands from a gateway to a UAV over a simulated LoRa channel with the encryption layer
+/// or, with `plaintext`, without it. An eavesdropper records the payloads, then replays them and
+/// sends tampered copies. Prints what the eavesdropper could read and what the UAV accepted.
+/// Usage: encryption [plaintext]
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    let plaintext = std::env::args().nth(1).is_some_and(|mode| mode == "plaintext");
+    std::env::set_var("NODE_TYPE", "Uav");
+
+    let air = SimulatedAir::new();
+    let driver = |node_type: NodeType| {
+        let driver = SimulatedLoRaDriver::new(air.clone(), None);
+        if plaintext {
+            driver
+        } else {
+            driver.with_link_layer(EncryptionLayer::new(
+                node_type,
+                Some(EncryptionOptionalConfig {
+                    key: Some(KEY),
+                    ..Default::default()
+                }),
+            ))
+        }
+    };
+    let gateway_driver = Arc::new(driver(NodeType::Gateway));
+    let modulation = gateway_driver.modulation().unwrap();
+    let (gateway, gateway_tx, _gateway_rx) = HalfDuplexNetwork::new(gateway_driver, 100);
+    let (uav, _uav_tx, mut uav_rx) = HalfDuplexNetwork::new(Arc::new(driver(NodeType::Uav)), 100);
+    gateway.run().await;
+    uav.run().await;
+
+    // Commands accepted by the UAV, by number
+    let received = Arc::new(Mutex::new(HashMap::<u32, u32>::new()));
+    {
+        let received = received.clone();
+        tokio::spawn(async move {
+            while let Some(packet) = uav_rx.recv().await {
+                if let MavMessage::COMMAND_LONG(command) = packet.msg {
+                    *received.lock().unwrap().entry(command.param1 as u32).or_default() += 1;
+                }
+            }
+        });
+    }
+
+    let (eavesdropper, _) = air.register(modulation, 0.0);
+    air.start_listening(eavesdropper);
+    for number in 0..COMMAND_COUNT {
+        gateway_tx.send(command_frame(number)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(COMMAND_INTERVAL_MS)).await;
+    }
+    let captured: Vec<Vec<u8>> = std::iter::from_fn(|| air.take_pending(eavesdropper)).collect();
+    let readable = captured
+        .iter()
+        .filter(|payload| deserialize_frame(payload).is_some())
+        .count();
+
+    for payload in &captured {
+        air.transmit(eavesdropper, payload.clone()).await;
+        tokio::time::sleep(Duration::from_millis(COMMAND_INTERVAL_MS)).await;
+    }
+    let replayed: u32 = received.lock().unwrap().values().map(|count| count - 1).sum();
+
+    let tampered_base = 100;
+    for payload in &captured {
+        let tampered = match deserialize_frame(payload) {
+            // A readable command is renumbered, its checksum recomputed
+            Some(mut packet) => {
+                if let MavMessage::COMMAND_LONG(command) = &mut packet.msg {
+                    command.param1 += tampered_base as f32;
+                }
+                serialize_frame(packet)
+            }
+            // Otherwise a bit of the ciphertext is flipped
+            None => {
+                let mut tampered = payload.clone();
+                let middle = tampered.len() / 2;
+                tampered[middle] ^= 0x40;
+                tampered
+            }
+        };
+        air.transmit(eavesdropper, tampered).await;
+        tokio::time::sleep(Duration::from_millis(COMMAND_INTERVAL_MS)).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let received = received.lock().unwrap();
+    let accepted = |base: u32| {
+        (base..base + COMMAND_COUNT)
+            .filter(|number| received.contains_key(number))
+            .count()
+    };
+    println!(
+        "{}: {} of {} commands received, eavesdropper read {} of {} payloads, {} replays and {} tampered payloads accepted",
+        if plaintext { "Plaintext" } else { "Encrypted" },
+        accepted(0),
+        COMMAND_COUNT,
+        readable,
+        captured.len(),
+        replayed,
+        accepted(tampered_base)
+    );
+    // Counted by the gateway as well, which hears the eavesdropper too
+    let rejected = |reason| metrics().counter(ENCRYPTED_PAYLOADS_REJECTED_METRIC, &[("reason", reason)]);
+    println!(
+        "Rejected payloads: {} failing authentication, {} replayed, {} reflected",
+        rejected("authentication"),
+        rejected("replayed"),
+        rejected("reflected")
+    );
+}
+
+/// COMMAND_LONG from the gateway to the UAV with its number in the first parameter
+fn command_frame(number: u32) -> MavFramePacket {
+    MavFramePacket {
+        header: MavHeader {
+            system_id: 101,
+            component_id: 1,
+            sequence: number as u8,
+        },
+        msg: MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+            param1: number as f32,
+            target_system: 201,
+            target_component: 1,
+            ..Default::default()
+        }),
+        protocol_version: MavlinkVersion::V2,
+    }
+}
@@ -32,7 +32,9 @@ async fn uav() {
     let mavlink_generator = MavlinkHeaderGenerator::new();
     driver.prepare_to_send().await.unwrap();
     loop {
-        driver.send(&mavlink_generator.create_mavlink_heartbeat_frame()).await;
+        if let Err(err) = driver.send(&mavlink_generator.create_mavlink_heartbeat_frame()).await {
+            println!("Send failed: {}", err);
+        }
         sleep(Duration::from_millis(1000)).await;
     }
 }
@@ -42,6 +44,8 @@ async fn gateway() {
     driver.prepare_to_receive().await.unwrap();
     loop {
         driver.ready_to_receive().await.unwrap();
-        driver.receive().await;
+        if let Err(err) = driver.receive().await {
+            println!("Receive failed: {}", err);
+        }
     }
 }
@@ -1,6 +1,7 @@
 use std::env;
 use std::time::Duration;
 
+use mavlink_network_node::config::E22PinMap;
 use mavlink_network_node::discover::DiscoveryService;
 use mavlink_network_node::logging_utils::init_logging;
 use mavlink_network_node::lora_serial::Sx1262UartE22;
@@ -17,7 +18,7 @@ async fn main() {
 
     match node_type {
         NodeType::Uav => {
-            let mut sx126x = Sx1262UartE22::new("/dev/ttyS0").unwrap();
+            let mut sx126x = Sx1262UartE22::new("/dev/ttyS0", &E22PinMap::default()).unwrap();
             loop {
                 println!("Sending message");
                 sx126x.send(0, 868, &"Hello World".as_bytes().to_vec()).unwrap();
@@ -25,9 +26,9 @@ async fn main() {
             }
         }
         NodeType::Gateway => {
-            let mut sx126x = Sx1262UartE22::new("/dev/ttyS0").unwrap();
+            let mut sx126x = Sx1262UartE22::new("/dev/ttyS0", &E22PinMap::default()).unwrap();
             loop {
-                if let Some(message) = sx126x.receive() {
+                if let Ok(Some(message)) = sx126x.receive() {
                     println!("Received message {:?}", message.data);
                 }
             }
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use mavlink_network_node::half_duplex_network::HalfDuplexNetwork;
+use mavlink_network_node::mavlink_utils::MavlinkHeaderGenerator;
+use mavlink_network_node::metrics::{MetricsServer, MetricsServerOptionalConfig};
+use mavlink_network_node::simulated_air::SimulatedAir;
+use mavlink_network_node::simulated_lora_driver::SimulatedLoRaDriver;
+use mavlink_network_node::NetworkInterface;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Exchanges heartbeats between two simulated LoRa nodes for a few seconds, then scrapes the
+/// metrics endpoint the way Prometheus does and prints the response.
+/// Usage: metrics
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    std::env::set_var("NODE_TYPE", "Uav");
+
+    let metrics_server = MetricsServer::new(Some(MetricsServerOptionalConfig {
+        addr: Some("127.0.0.1:0".to_string()),
+    }))
+    .await;
+    let addr = metrics_server.local_addr();
+    metrics_server.run().await;
+
+    let air = SimulatedAir::new();
+    let (gateway, gateway_tx, _gateway_rx) =
+        HalfDuplexNetwork::new(Arc::new(SimulatedLoRaDriver::new(air.clone(), None)), 100);
+    let (uav, _uav_tx, mut uav_rx) = HalfDuplexNetwork::new(Arc::new(SimulatedLoRaDriver::new(air.clone(), None)), 100);
+    gateway.run().await;
+    uav.run().await;
+    tokio::spawn(async move { while uav_rx.recv().await.is_some() {} });
+
+    let generator = MavlinkHeaderGenerator::new();
+    for _ in 0..20 {
+        gateway_tx
+            .send(generator.create_mavlink_heartbeat_frame())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nAccept: text/plain\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    println!("{}", response);
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use mavlink::ardupilotmega::MavMessage;
+use mavlink_network_node::half_duplex_network::HalfDuplexNetwork;
+use mavlink_network_node::mavlink_utils::MavlinkHeaderGenerator;
+use mavlink_network_node::radio_status::{
+    RadioStatistics, RadioStatusDriver, RadioStatusOptionalConfig, RadioStatusReporter,
+};
+use mavlink_network_node::simulated_air::SimulatedAir;
+use mavlink_network_node::simulated_lora_driver::{SimulatedLoRaDriver, SimulatedLoRaOptionalInitConfig};
+use mavlink_network_node::NetworkInterface;
+use tokio::sync::mpsc;
+
+const RUN_S: u64 = 12;
+
+/// Runs a gateway and a UAV over a simulated LoRa channel, each hearing the other at its own signal
+/// quality, and prints the RADIO_STATUS the flight controller of the UAV receives. The remote fields
+/// fill once the first report of the gateway arrives.
+/// Usage: radio_status
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    std::env::set_var("NODE_TYPE", "Uav");
+
+    let air = SimulatedAir::new();
+    let node = |rssi_dbm: i16, snr_db: i16, statistics: &Arc<RadioStatistics>| {
+        let driver = SimulatedLoRaDriver::new(
+            air.clone(),
+            Some(SimulatedLoRaOptionalInitConfig {
+                rssi_dbm: Some(rssi_dbm),
+                snr_db: Some(snr_db),
+                ..Default::default()
+            }),
+        );
+        Arc::new(RadioStatusDriver::new(Arc::new(driver), statistics.clone()))
+    };
+    let config = || {
+        Some(RadioStatusOptionalConfig {
+            peer_interval_ms: Some(2000),
+            ..Default::default()
+        })
+    };
+
+    let gateway_statistics = RadioStatistics::new();
+    let gateway_driver = node(-95, 3, &gateway_statistics);
+    let gateway_link_name = gateway_driver.to_string();
+    let (gateway, gateway_tx, mut gateway_rx) = HalfDuplexNetwork::new(gateway_driver, 100);
+    let gateway_queue_length = gateway.queue_length();
+    let uav_statistics = RadioStatistics::new();
+    let uav_driver = node(-110, -6, &uav_statistics);
+    let uav_link_name = uav_driver.to_string();
+    let (uav, uav_tx, mut uav_rx) = HalfDuplexNetwork::new(uav_driver, 100);
+    let uav_queue_length = uav.queue_length();
+    gateway.run().await;
+    uav.run().await;
+    tokio::spawn(async move { while gateway_rx.recv().await.is_some() {} });
+    tokio::spawn(async move { while uav_rx.recv().await.is_some() {} });
+
+    let heartbeat_tx = gateway_tx.clone();
+    let generator = MavlinkHeaderGenerator::new();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(500));
+        loop {
+            interval.tick().await;
+            heartbeat_tx
+                .send(generator.create_mavlink_heartbeat_frame())
+                .await
+                .unwrap();
+        }
+    });
+
+    // Without outputs, the gateway only reports to the UAV
+    RadioStatusReporter::new(
+        gateway_statistics,
+        &gateway_link_name,
+        gateway_tx,
+        gateway_queue_length,
+        config(),
+    )
+    .run()
+    .await;
+    // Offset from the transmissions of the gateway, which its reports would collide with
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let (autopilot_tx, mut autopilot_rx) = mpsc::channel(100);
+    let mut uav_reporter = RadioStatusReporter::new(uav_statistics, &uav_link_name, uav_tx, uav_queue_length, config());
+    uav_reporter.add_output("autopilot", autopilot_tx);
+    uav_reporter.run().await;
+
+    let dbm = |rssi: u8| {
+        if rssi == u8::MAX {
+            "unknown".to_string()
+        } else {
+            format!("{:.0} dBm", rssi as f64 / 1.9 - 127.0)
+        }
+    };
+    let _ = tokio::time::timeout(Duration::from_secs(RUN_S), async {
+        while let Some(packet) = autopilot_rx.recv().await {
+            if let MavMessage::RADIO_STATUS(status) = packet.msg {
+                println!(
+                    "RADIO_STATUS rssi {}, noise {}, remrssi {}, remnoise {}, txbuf {}%, rxerrors {}",
+                    dbm(status.rssi),
+                    dbm(status.noise),
+                    dbm(status.remrssi),
+                    dbm(status.remnoise),
+                    status.txbuf,
+                    status.rxerrors
+                );
+            }
+        }
+    })
+    .await;
+}
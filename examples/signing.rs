@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mavlink::ardupilotmega::{MavMessage, COMMAND_LONG_DATA};
+use mavlink::{MavHeader, MavlinkVersion};
+use mavlink_network_node::half_duplex_network::HalfDuplexNetwork;
+use mavlink_network_node::mavlink_utils::{MavlinkSigning, SigningOptionalConfig, SIGNATURE_REJECTED_METRIC};
+use mavlink_network_node::metrics::metrics;
+use mavlink_network_node::simulated_air::SimulatedAir;
+use mavlink_network_node::simulated_lora_driver::SimulatedLoRaDriver;
+use mavlink_network_node::types::MavFramePacket;
+use mavlink_network_node::{Driver, LoRaRadio, NetworkInterface};
+
+const COMMAND_COUNT: u32 = 10;
+const COMMAND_INTERVAL_MS: u64 = 300;
+const SECRET_KEY: [u8; 32] = [0x5a; 32];
+const ATTACKER_KEY: [u8; 32] = [0xa5; 32];
+// Commands are numbered through their first parameter, each sender in its own range
+const UNSIGNED_BASE: u32 = 100;
+const FORGED_BASE: u32 = 200;
+
+/// Sends signed commands from a gateway to a UAV over a simulated LoRa channel, while an attacker
+/// injects unsigned commands, commands signed with another key and replays of the captured commands.
+/// Prints how many of each the UAV accepted. With `unsigned`, neither node signs.
+/// Usage: signing [unsigned]
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    let unsigned = std::env::args().nth(1).is_some_and(|mode| mode == "unsigned");
+    std::env::set_var("NODE_TYPE", "Uav");
+
+    let air = SimulatedAir::new();
+    let signed_driver = |secret_key: [u8; 32]| {
+        let driver = SimulatedLoRaDriver::new(air.clone(), None);
+        if unsigned {
+            driver
+        } else {
+            driver.with_signing(MavlinkSigning::new(Some(SigningOptionalConfig {
+                secret_key: Some(secret_key),
+                ..Default::default()
+            })))
+        }
+    };
+    let gateway_driver = Arc::new(signed_driver(SECRET_KEY));
+    let modulation = gateway_driver.modulation().unwrap();
+    let (gateway, gateway_tx, _gateway_rx) = HalfDuplexNetwork::new(gateway_driver, 100);
+    let (uav, _uav_tx, mut uav_rx) = HalfDuplexNetwork::new(Arc::new(signed_driver(SECRET_KEY)), 100);
+    gateway.run().await;
+    uav.run().await;
+
+    // Commands accepted by the UAV, by number
+    let received = Arc::new(Mutex::new(HashMap::<u32, u32>::new()));
+    {
+        let received = received.clone();
+        tokio::spawn(async move {
+            while let Some(packet) = uav_rx.recv().await {
+                if let MavMessage::COMMAND_LONG(command) = packet.msg {
+                    *received.lock().unwrap().entry(command.param1 as u32).or_default() += 1;
+                }
+            }
+        });
+    }
+
+    // The attacker records everything on the channel
+    let (eavesdropper, _) = air.register(modulation, 0.0);
+    air.start_listening(eavesdropper);
+
+    for sequence in 0..COMMAND_COUNT {
+        gateway_tx.send(command_frame(sequence)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(COMMAND_INTERVAL_MS)).await;
+    }
+    let captured: Vec<Vec<u8>> = std::iter::from_fn(|| air.take_pending(eavesdropper)).collect();
+
+    let unsigned_attacker = SimulatedLoRaDriver::new(air.clone(), None);
+    for sequence in 0..COMMAND_COUNT {
+        unsigned_attacker
+            .send(&command_frame(UNSIGNED_BASE + sequence))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(COMMAND_INTERVAL_MS)).await;
+    }
+    let forging_attacker =
+        SimulatedLoRaDriver::new(air.clone(), None).with_signing(MavlinkSigning::new(Some(SigningOptionalConfig {
+            secret_key: Some(ATTACKER_KEY),
+            ..Default::default()
+        })));
+    for sequence in 0..COMMAND_COUNT {
+        forging_attacker
+            .send(&command_frame(FORGED_BASE + sequence))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(COMMAND_INTERVAL_MS)).await;
+    }
+    for payload in &captured {
+        air.transmit(eavesdropper, payload.clone()).await;
+        tokio::time::sleep(Duration::from_millis(COMMAND_INTERVAL_MS)).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let received = received.lock().unwrap();
+    let accepted = |base: u32| {
+        (base..base + COMMAND_COUNT)
+            .filter(|number| received.contains_key(number))
+            .count()
+    };
+    let replayed: u32 = (0..COMMAND_COUNT)
+        .filter_map(|number| received.get(&number))
+        .map(|count| count - 1)
+        .sum();
+    println!(
+        "{}: {} of {} commands received, accepted {} unsigned, {} signed with another key, {} of {} replayed",
+        if unsigned { "Unsigned" } else { "Signed" },
+        accepted(0),
+        COMMAND_COUNT,
+        accepted(UNSIGNED_BASE),
+        accepted(FORGED_BASE),
+        replayed,
+        captured.len()
+    );
+    // Counted by the gateway as well, which hears the attacker too
+    let rejected = |reason| metrics().counter(SIGNATURE_REJECTED_METRIC, &[("reason", reason)]);
+    println!(
+        "Rejected frames: {} unsigned, {} invalid signature, {} replayed",
+        rejected("unsigned"),
+        rejected("invalid_signature"),
+        rejected("replayed")
+    );
+}
+
+/// COMMAND_LONG from the gateway to the UAV with its number in the first parameter
+fn command_frame(number: u32) -> MavFramePacket {
+    MavFramePacket {
+        header: MavHeader {
+            system_id: 101,
+            component_id: 1,
+            sequence: number as u8,
+        },
+        msg: MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+            param1: number as f32,
+            target_system: 201,
+            target_component: 1,
+            ..Default::default()
+        }),
+        protocol_version: MavlinkVersion::V2,
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use futures::future::join_all;
+use mavlink_network_node::arq::ArqLayer;
+use mavlink_network_node::channel_access::ChannelAccessDriver;
+use mavlink_network_node::discover::DiscoveryService;
+use mavlink_network_node::duty_cycle::{DutyCycle, DutyCycleDriver};
+use mavlink_network_node::filter::FilterChain;
+use mavlink_network_node::fragmentation::{Fragmenter, FragmenterOptionalConfig};
+use mavlink_network_node::full_duplex_network::FullDuplexNetwork;
+use mavlink_network_node::half_duplex_network::HalfDuplexNetwork;
+use mavlink_network_node::logging_utils::{init_logging, log_debug_send_to_network};
+use mavlink_network_node::mavlink_utils::MavlinkHeaderGenerator;
+use mavlink_network_node::router::Router;
+use mavlink_network_node::scheduler::{MavlinkScheduler, SchedulerConfig};
+use mavlink_network_node::simulated_air::SimulatedAir;
+use mavlink_network_node::simulated_lora_driver::{
+    SimulatedLoRaDriver, SimulatedLoRaOptionalInitConfig, SIMULATED_LORA_DRIVER,
+};
+use mavlink_network_node::types::{MavFramePacket, NodeType, UdpMode};
+use mavlink_network_node::udp_driver::{
+    default_udp_filter, UDPConfig, UDPDriver, DEFAULT_UDP_PEER_TIMEOUT_MS, UDP_DRIVER,
+};
+use mavlink_network_node::{LoRaRadio, NetworkInterface};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Runs the UAV and gateway topology of `full.rs` in a single process, with the LoRa link simulated.
+/// Usage: simulated <packet loss between 0 and 1>
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let packet_loss = args.get(1).map_or(0.0, |loss| loss.parse().unwrap());
+    std::env::set_var("NODE_TYPE", "Gateway");
+    let (_discovery_service, discovery_notifier) = DiscoveryService::new();
+    let _guard = init_logging(discovery_notifier);
+
+    let air = SimulatedAir::new();
+    let uav_handles = node(air.clone(), NodeType::Uav, "127.0.0.1:14540", packet_loss).await;
+    let gateway_handles = node(air, NodeType::Gateway, "127.0.0.1:14550", packet_loss).await;
+
+    join_all(uav_handles.into_iter().chain(gateway_handles)).await;
+}
+
+async fn node(air: Arc<SimulatedAir>, node_type: NodeType, dest_addr: &str, packet_loss: f64) -> Vec<JoinHandle<()>> {
+    let config = UDPConfig {
+        addr: "127.0.0.1:0".to_string(),
+        dest_addr: Some(dest_addr.to_string()),
+        broadcast: false,
+        mode: UdpMode::Client,
+        unicast_addrs: Vec::new(),
+        peer_timeout_ms: DEFAULT_UDP_PEER_TIMEOUT_MS,
+    };
+
+    let udp_driver = Arc::new(UDPDriver::new(config).await);
+    let channel_size = 100;
+    let (udp_network, udp_tx, udp_rx) = FullDuplexNetwork::new(udp_driver, channel_size);
+    let udp_run_handle = udp_network.run().await;
+
+    // Payloads as small as the smallest E22 package size, so larger frames cross the link in fragments
+    // and small frames sent back to back share a transmission
+    let duty_cycle = DutyCycle::new(None);
+    let lora_driver = SimulatedLoRaDriver::new(
+        air,
+        Some(SimulatedLoRaOptionalInitConfig {
+            packet_loss: Some(packet_loss),
+            max_payload_length: Some(32),
+            pack_frames: Some(true),
+            ..Default::default()
+        }),
+    )
+    // Commands cross the lossy link with acknowledgements, closest to the frames to see their message IDs
+    .with_link_layer(ArqLayer::new(node_type, None))
+    .with_link_layer(Fragmenter::new(
+        node_type,
+        Some(FragmenterOptionalConfig {
+            max_fragment_length: Some(32),
+            ..Default::default()
+        }),
+    ))
+    // Books every fragment and acknowledgement against the duty cycle
+    .with_transmit_guard(duty_cycle.transmit_guard());
+    // Both nodes listen before talking, as their heartbeats are due at the same time
+    let lora_driver = Arc::new(ChannelAccessDriver::new(Arc::new(lora_driver), None));
+    let lora_driver = Arc::new(DutyCycleDriver::new(lora_driver, duty_cycle));
+    let (lora_network, lora_tx, lora_rx) = HalfDuplexNetwork::new(lora_driver, channel_size);
+    let lora_network = lora_network.with_scheduler(MavlinkScheduler::new(
+        SIMULATED_LORA_DRIVER,
+        &SchedulerConfig::default(),
+    ));
+    let lora_run_handle = lora_network.run().await;
+
+    let mut router = Router::new();
+    let udp_filter = FilterChain::new(UDP_DRIVER).with_filter(default_udp_filter());
+    router.add_link_with_filter(UDP_DRIVER, udp_tx.clone(), udp_rx, udp_filter);
+    router.add_link(SIMULATED_LORA_DRIVER, lora_tx, lora_rx);
+    let router_run_handle = router.run().await;
+
+    let udp_heartbeat = tokio::spawn(send_heartbeat_to_network(udp_tx, UDP_DRIVER, 1000));
+
+    udp_run_handle
+        .into_iter()
+        .chain(lora_run_handle)
+        .chain(router_run_handle)
+        .chain(std::iter::once(udp_heartbeat))
+        .collect()
+}
+
+async fn send_heartbeat_to_network(transmit_tx: mpsc::Sender<MavFramePacket>, driver: &str, interval_ms: u64) {
+    let generator = MavlinkHeaderGenerator::new();
+
+    loop {
+        log_debug_send_to_network(driver);
+        transmit_tx
+            .send(generator.create_mavlink_heartbeat_frame())
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(interval_ms)).await;
+    }
+}
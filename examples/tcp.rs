@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use mavlink_network_node::mavlink_utils::MavlinkHeaderGenerator;
+use mavlink_network_node::tcp_driver::{
+    TcpClientDriver, TcpClientOptionalInitConfig, TcpServerDriver, TcpServerOptionalInitConfig,
+};
+use mavlink_network_node::Driver;
+
+/// Connects two TCP client drivers to a TCP server driver on the loopback interface, as two
+/// ground stations would, and exchanges heartbeats between them
+#[tokio::main]
+async fn main() {
+    std::env::set_var("NODE_TYPE", "Gateway");
+    let server = TcpServerDriver::new(Some(TcpServerOptionalInitConfig {
+        addr: Some("127.0.0.1:0".to_string()),
+        ..Default::default()
+    }))
+    .await;
+    let clients: Vec<TcpClientDriver> = (0..2)
+        .map(|_| {
+            TcpClientDriver::new(Some(TcpClientOptionalInitConfig {
+                addr: Some(server.local_addr.to_string()),
+                ..Default::default()
+            }))
+        })
+        .collect();
+
+    // Connecting is done by the receive side of the client, as in a running network
+    for client in &clients {
+        let _ = tokio::time::timeout(Duration::from_millis(100), client.receive()).await;
+    }
+
+    let generator = MavlinkHeaderGenerator::new();
+    server
+        .send(&generator.create_mavlink_heartbeat_frame())
+        .await
+        .expect("Failed to send heartbeat");
+    for (index, client) in clients.iter().enumerate() {
+        let frame = client.receive().await.ok().flatten().expect("Heartbeat lost");
+        println!("Client {} received sequence {}", index, frame.header.sequence);
+    }
+
+    clients[1]
+        .send(&generator.create_mavlink_heartbeat_frame())
+        .await
+        .expect("Failed to send heartbeat");
+    let frame = server.receive().await.ok().flatten().expect("Heartbeat lost");
+    println!("Server received sequence {}", frame.header.sequence);
+}
@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use mavlink::ardupilotmega::MavMessage;
+use mavlink_network_node::half_duplex_network::HalfDuplexNetwork;
+use mavlink_network_node::mavlink_utils::MavlinkHeaderGenerator;
+use mavlink_network_node::scheduler::FifoScheduler;
+use mavlink_network_node::simulated_air::SimulatedAir;
+use mavlink_network_node::simulated_lora_driver::{SimulatedLoRaDriver, SIMULATED_LORA_DRIVER};
+use mavlink_network_node::tdma::{TdmaOptionalConfig, TdmaScheduler};
+use mavlink_network_node::types::NodeType;
+use mavlink_network_node::{LoRaRadio, NetworkInterface};
+
+const SEND_INTERVAL_MS: u64 = 100;
+const WARM_UP_S: u64 = 2;
+const RUN_S: u64 = 10;
+const DRAIN_S: u64 = 2;
+
+/// Runs a gateway and two UAVs sending heartbeats at the same rate over one simulated LoRa channel,
+/// in TDMA slots or, with `aloha`, as soon as they are queued, and prints the share that arrived.
+/// Usage: tdma [aloha]
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    let aloha = std::env::args().nth(1).is_some_and(|mode| mode == "aloha");
+    std::env::set_var("NODE_TYPE", "Gateway");
+
+    let air = SimulatedAir::new();
+    let sent = Arc::new(AtomicU64::new(0));
+    let received = Arc::new(AtomicU64::new(0));
+    let nodes = [(NodeType::Gateway, 101), (NodeType::Uav, 201), (NodeType::Uav, 202)];
+
+    let mut senders = Vec::new();
+    for (node_type, system_id) in nodes {
+        let tdma = (!aloha).then(|| {
+            TdmaScheduler::new(
+                SIMULATED_LORA_DRIVER,
+                FifoScheduler::default(),
+                node_type,
+                Some(TdmaOptionalConfig {
+                    slot_table: Some(vec![101, 201, 202]),
+                    system_id: Some(Some(system_id)),
+                    beacon_interval_ms: Some(1000),
+                    ..Default::default()
+                }),
+            )
+        });
+        let mut driver = SimulatedLoRaDriver::new(air.clone(), None);
+        if let Some(tdma) = &tdma {
+            driver = driver.with_transmit_guard(tdma.transmit_guard());
+        }
+        let (network, lora_tx, mut lora_rx) = HalfDuplexNetwork::new(Arc::new(driver), 100);
+        let network = match tdma {
+            Some(tdma) => network.with_scheduler(tdma),
+            None => network,
+        };
+        network.run().await;
+
+        let received = received.clone();
+        tokio::spawn(async move {
+            while let Some(packet) = lora_rx.recv().await {
+                // Beacons of the gateway are not part of the load
+                if matches!(packet.msg, MavMessage::HEARTBEAT(_)) {
+                    received.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+
+        let sent = sent.clone();
+        senders.push(async move {
+            let generator = MavlinkHeaderGenerator::new();
+            let mut interval = tokio::time::interval(Duration::from_millis(SEND_INTERVAL_MS));
+            loop {
+                interval.tick().await;
+                lora_tx.send(generator.create_mavlink_heartbeat_frame()).await.unwrap();
+                sent.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+    }
+
+    // UAVs transmit without slots until the first beacon arrives
+    tokio::time::sleep(Duration::from_secs(WARM_UP_S)).await;
+    let senders: Vec<_> = senders.into_iter().map(tokio::spawn).collect();
+    tokio::time::sleep(Duration::from_secs(RUN_S)).await;
+    senders.iter().for_each(|sender| sender.abort());
+    // Lets the packets still queued for a slot go out
+    tokio::time::sleep(Duration::from_secs(DRAIN_S)).await;
+
+    // Every heartbeat is heard by the two other nodes
+    let sent = sent.load(Ordering::Relaxed);
+    let received = received.load(Ordering::Relaxed);
+    println!(
+        "{}: {} heartbeats sent, {:.1}% received",
+        if aloha { "ALOHA" } else { "TDMA" },
+        sent,
+        100.0 * received as f64 / (2 * sent) as f64
+    );
+}
@@ -2,10 +2,13 @@ use std::sync::Arc;
 
 use futures::future::join_all;
 use mavlink_network_node::discover::DiscoveryService;
+use mavlink_network_node::filter::{FilterChain, FilteredDriver};
 use mavlink_network_node::full_duplex_network::FullDuplexNetwork;
 use mavlink_network_node::logging_utils::init_logging;
-use mavlink_network_node::types::NodeType;
-use mavlink_network_node::udp_driver::{UDPConfig, UDPDriver};
+use mavlink_network_node::types::{NodeType, UdpMode};
+use mavlink_network_node::udp_driver::{
+    default_udp_filter, UDPConfig, UDPDriver, DEFAULT_UDP_PEER_TIMEOUT_MS, UDP_DRIVER,
+};
 use mavlink_network_node::NetworkInterface;
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
@@ -30,12 +33,19 @@ async fn uav() {}
 
 async fn gateway() {
     let config = UDPConfig {
-        addr: "0.0.0.0:14550".to_string(),            // Listening address
-        dest_addr: "192.168.1.255:14550".to_string(), // Destination address for sending
+        addr: "0.0.0.0:14550".to_string(),                  // Listening address
+        dest_addr: Some("192.168.1.255:14550".to_string()), // Destination address for sending
         broadcast: true,
+        mode: UdpMode::Client,
+        unicast_addrs: Vec::new(),
+        peer_timeout_ms: DEFAULT_UDP_PEER_TIMEOUT_MS,
     };
 
     let driver = Arc::new(UDPDriver::new(config).await);
+    let driver = Arc::new(FilteredDriver::new(
+        driver,
+        FilterChain::new(UDP_DRIVER).with_filter(default_udp_filter()),
+    ));
     let channel_size = 100;
     let (udp_network, _tx, _rx) = FullDuplexNetwork::new(driver, channel_size);
 
@@ -0,0 +1,41 @@
+use mavlink_network_node::mavlink_utils::MavlinkHeaderGenerator;
+use mavlink_network_node::types::{WebSocketEncoding, WebSocketRole};
+use mavlink_network_node::websocket_driver::{WebSocketDriver, WebSocketOptionalInitConfig};
+use mavlink_network_node::Driver;
+
+/// Connects a JSON WebSocket client driver, as a browser dashboard would, to a binary WebSocket
+/// server driver on the loopback interface and exchanges heartbeats between them
+#[tokio::main]
+async fn main() {
+    std::env::set_var("NODE_TYPE", "Gateway");
+    let server = WebSocketDriver::new(Some(WebSocketOptionalInitConfig {
+        role: Some(WebSocketRole::Server),
+        addr: Some("127.0.0.1:0".to_string()),
+        ..Default::default()
+    }))
+    .await;
+    let client = WebSocketDriver::new(Some(WebSocketOptionalInitConfig {
+        role: Some(WebSocketRole::Client),
+        url: Some(format!("ws://{}", server.local_addr.unwrap())),
+        encoding: Some(WebSocketEncoding::Json),
+        ..Default::default()
+    }))
+    .await;
+
+    let generator = MavlinkHeaderGenerator::new();
+    // Frames sent before the client connected are not queued for it
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    client
+        .send(&generator.create_mavlink_heartbeat_frame())
+        .await
+        .expect("Failed to send heartbeat");
+    let frame = server.receive().await.ok().flatten().expect("Heartbeat lost");
+    println!("Server received sequence {} from JSON", frame.header.sequence);
+
+    server
+        .send(&generator.create_mavlink_heartbeat_frame())
+        .await
+        .expect("Failed to send heartbeat");
+    let frame = client.receive().await.ok().flatten().expect("Heartbeat lost");
+    println!("Client received sequence {} from binary", frame.header.sequence);
+}
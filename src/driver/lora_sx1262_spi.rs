@@ -1,17 +1,25 @@
 use std::fmt::Display;
 use std::sync::Arc;
+use std::time::Duration;
 
-use lora_phy::mod_params::{Bandwidth, CodingRate, ModulationParams, PacketParams, SpreadingFactor};
+use lora_phy::mod_params::{Bandwidth, CodingRate, SpreadingFactor};
 use lora_phy::mod_traits::{IrqState, TargetIrqState};
 use tokio::sync::Mutex;
+use tokio::time::Instant;
 
-use super::Driver;
-use crate::define_struct_with_defaults;
+use super::{Driver, DriverError, LoRaRadio, SignalQuality};
+use crate::config::{LoRaSpiSection, Sx1262PinMap};
 use crate::lora_types::LoRaDeviceSx126x;
-use crate::mavlink_utils::{deserialize_frame, serialize_frame};
+use crate::mavlink_utils::{deserialize_frame, serialize_frame, MavlinkSigning};
+use crate::utils::link_layer::{LinkLayer, LinkLayerStack};
 use crate::utils::logging_utils::{log_debug_receive_packet, log_debug_send_packet, log_driver_creation};
-use crate::utils::lora_utils::{create_lora_sx1262_spi, create_spi_sx1262, LORA_FREQUENCY_IN_HZ};
+use crate::utils::lora_airtime::LoRaModulation;
+use crate::utils::lora_utils::{
+    create_lora_sx1262_spi, create_spi_sx1262, lora_modulation, IrqEvents, LoRaSpiConfig, LORA_FREQUENCY_IN_HZ,
+};
+use crate::utils::transmit_guard::{TransmitGuard, TransmitGuards};
 use crate::utils::types::MavFramePacket;
+use crate::{define_struct_with_defaults, impl_from_lora_spi_section};
 
 pub const LORA_SX1262_SPI_DRIVER: &str = "lora_sx1262_spi_driver";
 
@@ -24,21 +32,27 @@ define_struct_with_defaults! {
         preamble_length: u16 = 4,
         implicit_header: bool = false,
         max_payload_length: u8 = 255,
+        // Packs frames sent back to back into shared transmissions up to the max payload length
+        pack_frames: bool = false,
         crc_enabled: bool = true,
         iq_inverted: bool = false,
+        tx_power: i32 = 22,
+        tx_boost: bool = true,
+        pins: Sx1262PinMap = Sx1262PinMap::default(),
     }
 }
 
-#[allow(dead_code)]
-pub struct LoRaSx1262SpiConfig {
-    modulation_params: ModulationParams,
-    rx_pkt_params: PacketParams,
-    tx_pkt_params: PacketParams,
-}
+impl_from_lora_spi_section!(LoRaSpiSection<Sx1262PinMap>, LoRaSx1262SpiOptionalInitConfig);
 
 pub struct LoRaSx1262SpiDriver {
     pub device: Arc<Mutex<LoRaDeviceSx126x>>,
-    config: LoRaSx1262SpiConfig,
+    // Waited on without the device, so a transmission can take the device while waiting for a reception
+    irq_events: Mutex<IrqEvents>,
+    // Replaced when the modulation changes, copied out before each operation on the radio
+    config: std::sync::Mutex<LoRaSpiConfig>,
+    link_layers: LinkLayerStack,
+    guards: TransmitGuards,
+    last_signal: std::sync::Mutex<Option<SignalQuality>>,
 }
 
 impl Display for LoRaSx1262SpiDriver {
@@ -52,81 +66,130 @@ impl LoRaSx1262SpiDriver {
     pub async fn new(init_config: Option<LoRaSx1262SpiOptionalInitConfig>) -> Self {
         let init_config = init_config.unwrap_or_default().build();
 
-        let spi = create_spi_sx1262().expect("Failed to create SPI");
-        let mut lora = create_lora_sx1262_spi(spi)
+        let spi = create_spi_sx1262(init_config.pins.cs).expect("Failed to create SPI");
+        let (mut lora, irq_events) = create_lora_sx1262_spi(spi, &init_config.pins)
             .await
             .expect("Failed to create LoRa instance");
 
-        let modulation_params = lora
-            .create_modulation_params(
-                init_config.spreading_factor,
-                init_config.bandwidth,
-                init_config.coding_rate,
-                init_config.frequency,
-            )
-            .expect("Failed to create modulation params");
-
-        let rx_pkt_params = lora
-            .create_rx_packet_params(
-                init_config.preamble_length,
-                init_config.implicit_header,
-                init_config.max_payload_length,
-                init_config.crc_enabled,
-                init_config.iq_inverted,
-                &modulation_params,
-            )
-            .expect("Failed to create RX packet params");
-
-        let tx_pkt_params = lora
-            .create_tx_packet_params(
-                init_config.preamble_length,
-                init_config.implicit_header,
-                init_config.crc_enabled,
-                init_config.iq_inverted,
-                &modulation_params,
-            )
-            .expect("Failed to create TX packet params");
+        let modulation = lora_modulation(
+            init_config.spreading_factor,
+            init_config.bandwidth,
+            init_config.coding_rate,
+            init_config.frequency,
+            init_config.preamble_length,
+            init_config.implicit_header,
+            init_config.crc_enabled,
+        );
+        let config = LoRaSpiConfig::new(
+            &mut lora,
+            modulation,
+            init_config.max_payload_length,
+            init_config.iq_inverted,
+            init_config.tx_power,
+            init_config.tx_boost,
+        )
+        .expect("Failed to create modulation and packet params");
 
         log_driver_creation(LORA_SX1262_SPI_DRIVER);
 
+        let mut link_layers = LinkLayerStack::default();
+        if init_config.pack_frames {
+            link_layers.enable_packing(init_config.max_payload_length as usize);
+        }
+
         Self {
             device: Arc::new(Mutex::new(lora)),
-            config: LoRaSx1262SpiConfig {
-                modulation_params,
-                rx_pkt_params,
-                tx_pkt_params,
-            },
+            irq_events: Mutex::new(irq_events),
+            config: std::sync::Mutex::new(config),
+            link_layers,
+            guards: TransmitGuards::default(),
+            last_signal: std::sync::Mutex::new(None),
         }
     }
+
+    fn config(&self) -> LoRaSpiConfig {
+        self.config.lock().unwrap().clone()
+    }
+}
+
+impl LoRaRadio for LoRaSx1262SpiDriver {
+    fn with_link_layer(mut self, layer: impl LinkLayer + 'static) -> Self {
+        self.link_layers.push(layer);
+        self
+    }
+
+    fn with_signing(mut self, signing: MavlinkSigning) -> Self {
+        self.link_layers.set_signing(Arc::new(signing));
+        self
+    }
+
+    fn with_transmit_guard(mut self, guard: impl TransmitGuard + 'static) -> Self {
+        self.guards.push(guard);
+        self
+    }
 }
 
 #[async_trait::async_trait]
 impl Driver<MavFramePacket> for LoRaSx1262SpiDriver {
-    async fn send(&self, packet: &MavFramePacket) {
+    async fn send(&self, packet: &MavFramePacket) -> Result<(), DriverError> {
+        self.send_batch(std::slice::from_ref(packet)).await
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        target = "network",
+        name = "Transmitting",
+        fields(packet, driver = LORA_SX1262_SPI_DRIVER)
+    )]
+    async fn send_batch(&self, packets: &[MavFramePacket]) -> Result<(), DriverError> {
+        let config = self.config();
+        let driver = self.to_string();
+        let plan = self
+            .guards
+            .encode_batch(&driver, &config.modulation, &self.link_layers, packets);
+
         let mut lora = self.device.lock().await;
-        let serialised_packet = serialize_frame(packet.clone());
-
-        match lora
-            .tx(
-                &self.config.modulation_params,
-                &mut self.config.tx_pkt_params.clone(),
-                &serialised_packet,
-                0xffffff,
-            )
-            .await
-        {
-            Ok(()) => {
-                log_debug_send_packet(&self.to_string(), &packet);
-            }
-            Err(err) => {
-                println!("Radio error = {:?}", err);
-                return;
+        for payload in &plan.payloads {
+            let started = Instant::now();
+            if let Err(err) = lora
+                .tx(
+                    &config.modulation_params,
+                    &mut config.tx_pkt_params.clone(),
+                    payload,
+                    0xffffff,
+                )
+                .await
+            {
+                return Err(DriverError::radio(err));
             }
-        };
+            self.guards.book(&driver, &config.modulation, started, payload);
+        }
+        if plan.dropped {
+            return Err(DriverError::Busy("payloads not allowed on air were dropped".to_string()));
+        }
+        for packet in packets {
+            log_debug_send_packet(&self.to_string(), packet);
+        }
+        Ok(())
     }
 
-    async fn receive(&self) -> Option<MavFramePacket> {
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        target = "network",
+        name = "Receiving",
+        fields(driver = LORA_SX1262_SPI_DRIVER)
+    )]
+    async fn receive(&self) -> Result<Option<MavFramePacket>, DriverError> {
+        if let Some(frame) = self.link_layers.pop_decoded() {
+            let mavlink_frame = deserialize_frame(&frame[..]).ok_or_else(DriverError::invalid_frame)?;
+            log_debug_receive_packet(&self.to_string(), &mavlink_frame, None, None);
+            return Ok(Some(mavlink_frame));
+        }
+
         let mut lora = self.device.lock().await;
+        let config = self.config();
         // let mut receiving_buffer = [00u8; 255];
 
         // match lora.rx(&self.config.rx_pkt_params, &mut receiving_buffer).await {
@@ -140,74 +203,126 @@ impl Driver<MavFramePacket> for LoRaSx1262SpiDriver {
         //     }
         //     _ => return None,
         // }
-        let target_irq_state = lora.process_irq_event().await.unwrap();
+        let target_irq_state = lora.process_irq_event().await.map_err(DriverError::radio)?;
         if let Some(TargetIrqState::Done) = target_irq_state {
             let mut receiving_buffer = [00u8; 255];
-            match lora
-                .process_rx_irq(&self.config.rx_pkt_params, &mut receiving_buffer)
-                .await
-            {
+            match lora.process_rx_irq(&config.rx_pkt_params, &mut receiving_buffer).await {
                 Ok(IrqState::RxDone(received_len, rx_pkt_status)) => {
+                    *self.last_signal.lock().unwrap() = Some(SignalQuality {
+                        rssi_dbm: rx_pkt_status.rssi,
+                        snr_db: rx_pkt_status.snr,
+                    });
                     let received_data = Vec::from(&receiving_buffer[..received_len as usize]);
-                    if let Some(mavlink_frame) = deserialize_frame(&received_data[..]) {
-                        // log_packet_received(received_len as usize, None, &mavlink_frame, LORA_DRIVER);
-                        log_debug_receive_packet(
-                            &self.to_string(),
-                            &mavlink_frame,
-                            Some(rx_pkt_status.rssi),
-                            Some(rx_pkt_status.snr),
-                        );
-                        return Some(mavlink_frame);
-                    }
+                    // No frame yet while the fragments of one are still arriving
+                    let Some(frame) = self.link_layers.decode(received_data) else {
+                        return Ok(None);
+                    };
+                    let mavlink_frame = deserialize_frame(&frame[..]).ok_or_else(DriverError::invalid_frame)?;
+                    // log_packet_received(received_len as usize, None, &mavlink_frame, LORA_DRIVER);
+                    log_debug_receive_packet(
+                        &self.to_string(),
+                        &mavlink_frame,
+                        Some(rx_pkt_status.rssi),
+                        Some(rx_pkt_status.snr),
+                    );
+                    return Ok(Some(mavlink_frame));
                 }
                 // PreambleReceived is not expected here as we passed target_rx_state = TargetIrqState::Done
                 Ok(IrqState::PreambleReceived) => unreachable!(),
-                _ => return None,
+                Err(err) => return Err(DriverError::radio(err)),
             }
         }
-        None
+        Ok(None)
     }
 
-    async fn ready_to_receive(&self) -> Result<(), &str> {
-        // DANGER not cancellation safe
-        let mut lora = self.device.lock().await;
-        match lora.wait_for_irq().await {
-            Ok(_) => Ok(()),
-            Err(_err) => {
-                return Err("Failed to wait for IRQ");
-            }
+    async fn ready_to_receive(&self) -> Result<(), DriverError> {
+        if self.link_layers.has_decoded() {
+            return Ok(());
         }
+        // Cancellation safe, an IRQ raised while nobody waits is seen by the next wait
+        let mut irq_events = self.irq_events.lock().await;
+        irq_events.changed().await.map_err(DriverError::radio)
     }
 
-    async fn prepare_to_receive(&self) -> Result<(), &str> {
-        // DANGER not cancellation safe
+    async fn prepare_to_receive(&self) -> Result<(), DriverError> {
+        // Not cancellation safe, the half-duplex network runs it to completion
         let mut lora = self.device.lock().await;
-        match lora
-            .prepare_for_rx(
-                lora_phy::RxMode::Continuous,
-                &self.config.modulation_params,
-                &self.config.rx_pkt_params,
-                false,
-            )
+        let config = self.config();
+        // The IRQs raised before, such as TX done, are not receptions
+        self.irq_events.lock().await.borrow_and_update();
+        lora.prepare_for_rx(
+            lora_phy::RxMode::Continuous,
+            &config.modulation_params,
+            &config.rx_pkt_params,
+            false,
+        )
+        .await
+        .map_err(DriverError::radio)
+    }
+
+    async fn prepare_to_send(&self) -> Result<(), DriverError> {
+        // Not cancellation safe, the half-duplex network runs it to completion
+        let mut lora = self.device.lock().await;
+        let config = self.config();
+        lora.prepare_for_tx(&config.modulation_params, config.tx_power, config.tx_boost)
+            .await
+            .map_err(DriverError::radio)
+    }
+
+    async fn channel_busy(&self) -> Result<bool, DriverError> {
+        // Channel activity detection, which leaves the radio in standby
+        let mut lora = self.device.lock().await;
+        let config = self.config();
+        lora.prepare_for_cad(&config.modulation_params, false)
             .await
+            .map_err(DriverError::radio)?;
+        lora.cad(&config.modulation_params).await.map_err(DriverError::radio)
+    }
+
+    async fn reinitialize(&self) -> Result<(), DriverError> {
         {
-            Ok(()) => Ok(()),
-            Err(err) => {
-                println!("Radio error = {:?}", err);
-                Err("Failed to prepare for RX")
-            }
+            let mut lora = self.device.lock().await;
+            // Runs the initialization of LoRa::new again, which resets the radio through the reset pin of
+            // the interface variant
+            lora.init().await.map_err(DriverError::radio)?;
         }
+        // Modulation and packet params are applied again with the receive mode
+        self.prepare_to_receive().await
+    }
+
+    fn modulation(&self) -> Option<LoRaModulation> {
+        Some(self.config.lock().unwrap().modulation)
+    }
+
+    fn time_on_air(&self, packet: &MavFramePacket) -> Option<Duration> {
+        let modulation = self.config.lock().unwrap().modulation;
+        Some(
+            self.link_layers
+                .time_on_air(&modulation, &serialize_frame(packet.clone())),
+        )
     }
 
-    async fn prepare_to_send(&self) -> Result<(), &str> {
-        // DANGER not cancellation safe
+    async fn set_modulation(&self, modulation: LoRaModulation) -> Result<(), DriverError> {
+        // Only the params change here, the radio takes them with the next prepare call
         let mut lora = self.device.lock().await;
-        match lora.prepare_for_tx(&self.config.modulation_params, 22, true).await {
-            Ok(()) => Ok(()),
-            Err(err) => {
-                println!("Radio error = {:?}", err);
-                Err("Failed to prepare for RX")
-            }
-        }
+        let config = self.config();
+        let config = LoRaSpiConfig::new(
+            &mut lora,
+            modulation,
+            config.max_payload_length,
+            config.iq_inverted,
+            config.tx_power,
+            config.tx_boost,
+        )?;
+        *self.config.lock().unwrap() = config;
+        Ok(())
+    }
+
+    fn signal_quality(&self) -> Option<SignalQuality> {
+        *self.last_signal.lock().unwrap()
+    }
+
+    fn next_link_transmit(&self) -> Option<Instant> {
+        self.link_layers.next_transmit()
     }
 }
@@ -3,22 +3,73 @@ use std::sync::Arc;
 
 use tokio::sync::Mutex;
 
-use super::Driver;
+use super::{Driver, DriverError};
+use crate::config::{E22PinMap, LoRaSx1262UartSection};
+use crate::define_struct_with_defaults;
 use crate::lora_serial::{AirSpeed, PackageSize, PowerLevel};
-use crate::mavlink_utils::{deserialize_frame, serialize_frame};
+use crate::mavlink_utils::{serialize_frame, MavlinkSigning, MavlinkStreamParser};
+use crate::utils::link_layer::{LinkLayer, LinkLayerStack};
 use crate::utils::logging_utils::{log_debug_receive_packet, log_debug_send_packet, log_driver_creation};
 use crate::utils::lora_serial::Sx1262UartE22;
 use crate::utils::types::MavFramePacket;
 
 pub const LORA_SX1262_UART_DRIVER: &str = "lora_sx1262_uart_driver";
 
+define_struct_with_defaults! {
+    LoRaSx1262UartOptionalInitConfig, LoRaSx1262UartInitConfig {
+        serial_port: String = "/dev/ttyS0".to_string(),
+        frequency: u32 = 868,
+        address: u16 = 0,
+        target_address: u16 = 0,
+        net_id: u16 = 0xFFFF,
+        power: PowerLevel = PowerLevel::Power22dBm,
+        air_speed: AirSpeed = AirSpeed::Speed2400,
+        package_size: PackageSize = PackageSize::Size240Byte,
+        crypt: u16 = 0,
+        // Channel noise above which the channel counts as busy for listen before talk
+        lbt_rssi_threshold_dbm: i16 = -90,
+        pins: E22PinMap = E22PinMap::default(),
+    }
+}
+
+impl From<&LoRaSx1262UartSection> for LoRaSx1262UartOptionalInitConfig {
+    fn from(section: &LoRaSx1262UartSection) -> Self {
+        Self {
+            serial_port: section.serial_port.clone(),
+            frequency: section.frequency_mhz,
+            address: section.address,
+            target_address: section.target_address,
+            net_id: section.net_id,
+            power: section
+                .power_dbm
+                .map(|dbm| PowerLevel::from_dbm(dbm).expect("Power validated at load time")),
+            air_speed: section
+                .air_speed
+                .map(|bps| AirSpeed::from_bps(bps).expect("Air speed validated at load time")),
+            package_size: section
+                .package_size
+                .map(|bytes| PackageSize::from_bytes(bytes).expect("Package size validated at load time")),
+            crypt: section.crypt,
+            lbt_rssi_threshold_dbm: section.lbt_rssi_threshold_dbm,
+            pins: Some(section.pins),
+        }
+    }
+}
+
 #[allow(dead_code)]
-pub struct LoRaSx1262UartConfig {}
+pub struct LoRaSx1262UartConfig {
+    frequency: u32,
+    target_address: u16,
+    lbt_rssi_threshold_dbm: i16,
+}
 
 #[allow(dead_code)]
 pub struct LoRaSx1262UartDriver {
     pub device: Arc<Mutex<Sx1262UartE22>>,
     config: LoRaSx1262UartConfig,
+    link_layers: LinkLayerStack,
+    // Reads from the serial port do not align with frame boundaries
+    parser: std::sync::Mutex<MavlinkStreamParser>,
 }
 
 impl Display for LoRaSx1262UartDriver {
@@ -29,50 +80,97 @@ impl Display for LoRaSx1262UartDriver {
 
 #[allow(dead_code)]
 impl LoRaSx1262UartDriver {
-    pub async fn new(_config: Option<LoRaSx1262UartConfig>) -> Self {
-        let mut lora = Sx1262UartE22::new("/dev/ttyS0").unwrap();
+    pub async fn new(init_config: Option<LoRaSx1262UartOptionalInitConfig>) -> Self {
+        let init_config = init_config.unwrap_or_default().build();
+
+        let mut lora = Sx1262UartE22::new(&init_config.serial_port, &init_config.pins).unwrap();
         lora.set(
-            868,
-            0,
-            0xFFFF,
-            PowerLevel::Power22dBm,
+            init_config.frequency,
+            init_config.address,
+            init_config.net_id,
+            init_config.power,
             false,
-            AirSpeed::Speed2400,
-            PackageSize::Size240Byte,
-            0,
+            init_config.air_speed,
+            init_config.package_size,
+            init_config.crypt,
         )
         .unwrap();
         log_driver_creation(LORA_SX1262_UART_DRIVER);
 
         Self {
             device: Arc::new(Mutex::new(lora)),
-            config: LoRaSx1262UartConfig {},
+            config: LoRaSx1262UartConfig {
+                frequency: init_config.frequency,
+                target_address: init_config.target_address,
+                lbt_rssi_threshold_dbm: init_config.lbt_rssi_threshold_dbm,
+            },
+            link_layers: LinkLayerStack::default(),
+            parser: std::sync::Mutex::new(MavlinkStreamParser::new()),
         }
     }
+
+    /// Adds a link layer between the serialized frames and the radio, on top of the ones added before
+    pub fn with_link_layer(mut self, layer: impl LinkLayer + 'static) -> Self {
+        self.link_layers.push(layer);
+        self
+    }
+
+    /// Signs the frames sent over the radio and drops the received ones failing verification
+    pub fn with_signing(mut self, signing: MavlinkSigning) -> Self {
+        let signing = Arc::new(signing);
+        self.link_layers.set_signing(signing.clone());
+        // The stream is verified while it is parsed
+        self.parser = std::sync::Mutex::new(MavlinkStreamParser::with_signing(signing));
+        self
+    }
 }
 
 #[async_trait::async_trait]
 impl Driver<MavFramePacket> for LoRaSx1262UartDriver {
-    async fn send(&self, packet: &MavFramePacket) {
+    async fn send(&self, packet: &MavFramePacket) -> Result<(), DriverError> {
         let mut lora = self.device.lock().await;
         let serialised_frame = serialize_frame(packet.clone());
-        lora.send(0, 868, &serialised_frame).unwrap();
+        for payload in self.link_layers.encode(serialised_frame) {
+            lora.send(self.config.target_address, self.config.frequency, &payload)
+                .map_err(|err| DriverError::Radio(err.to_string()))?;
+        }
         log_debug_send_packet(&self.to_string(), packet);
+        Ok(())
     }
 
-    async fn receive(&self) -> Option<MavFramePacket> {
+    async fn receive(&self) -> Result<Option<MavFramePacket>, DriverError> {
+        let buffered_frame = self.parser.lock().unwrap().next_frame();
+        if let Some(mavlink_frame) = buffered_frame {
+            log_debug_receive_packet(&self.to_string(), &mavlink_frame, None, None);
+            return Ok(Some(mavlink_frame));
+        }
+
         let mut lora = self.device.lock().await;
-        if let Some(receive_result) = lora.receive() {
-            if let Some(mavlink_frame) = deserialize_frame(&receive_result.data[..]) {
-                log_debug_receive_packet(
-                    &self.to_string(),
-                    &mavlink_frame,
-                    receive_result.rssi,
-                    receive_result.snr.map(|snr| snr as i16), // Convert u8 to i16
-                );
-                return Some(mavlink_frame);
-            }
+        let receive_result = lora.receive().map_err(|err| DriverError::Radio(err.to_string()))?;
+        let Some(receive_result) = receive_result else {
+            return Ok(None);
+        };
+        let mut parser = self.parser.lock().unwrap();
+        for payload in self.link_layers.decode_payloads(receive_result.data) {
+            parser.push(&payload);
         }
-        None
+        let Some(mavlink_frame) = parser.next_frame() else {
+            return Ok(None);
+        };
+        log_debug_receive_packet(
+            &self.to_string(),
+            &mavlink_frame,
+            receive_result.rssi,
+            receive_result.snr.map(|snr| snr as i16), // Convert u8 to i16
+        );
+        Ok(Some(mavlink_frame))
+    }
+    async fn channel_busy(&self) -> Result<bool, DriverError> {
+        let mut lora = self.device.lock().await;
+        // The module reports the magnitude of the noise floor in dBm
+        let noise = lora
+            .get_channel_rssi()
+            .map_err(|err| DriverError::Radio(err.to_string()))?;
+        Ok(-noise > self.config.lbt_rssi_threshold_dbm)
     }
 }
@@ -4,23 +4,249 @@ pub mod lora_sx1262_spi;
 pub mod lora_sx1262_uart;
 #[cfg(feature = "embedded")]
 pub mod lora_sx1276_spi;
+pub mod serial_driver;
+pub mod simulated_lora_driver;
+pub mod tcp_driver;
 pub mod udp_driver;
 pub mod websocket_driver;
 
-use std::fmt::Display;
+use std::fmt::{Debug, Display};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::utils::link_layer::LinkLayer;
+use crate::utils::lora_airtime::LoRaModulation;
+use crate::utils::mavlink_utils::MavlinkSigning;
+use crate::utils::transmit_guard::TransmitGuard;
+use crate::utils::types::MavFramePacket;
+
+/// Failure of a driver operation, its kind tells the network interface how to recover
+#[derive(Debug)]
+pub enum DriverError {
+    /// The transport failed, such as a closed socket or an unplugged serial device
+    Io(std::io::Error),
+    /// The radio rejected a command or did not respond
+    Radio(String),
+    /// Data was received but did not hold a valid frame
+    Decode(String),
+    /// The operation did not complete in the time allowed
+    Timeout,
+    /// The link cannot take packets right now, such as a busy channel
+    Busy(String),
+}
+
+impl DriverError {
+    /// Error of a radio library, which only exposes its errors through `Debug`
+    pub fn radio(err: impl Debug) -> Self {
+        DriverError::Radio(format!("{:?}", err))
+    }
+
+    /// Bytes that do not deserialize into a MAVLink frame
+    pub fn invalid_frame() -> Self {
+        DriverError::Decode("invalid MAVLink frame".to_string())
+    }
+
+    pub fn not_connected(reason: &str) -> Self {
+        DriverError::Io(std::io::Error::new(std::io::ErrorKind::NotConnected, reason))
+    }
+
+    /// Whether the same operation can succeed right away, as after a radio glitch
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, DriverError::Radio(_) | DriverError::Timeout)
+    }
+
+    /// Whether the link should pause before the next operation, so a failing device is not hammered
+    pub fn needs_backoff(&self) -> bool {
+        matches!(self, DriverError::Io(_) | DriverError::Radio(_) | DriverError::Busy(_))
+    }
+}
+
+impl Display for DriverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DriverError::Io(err) => write!(f, "I/O error: {}", err),
+            DriverError::Radio(err) => write!(f, "radio error: {}", err),
+            DriverError::Decode(err) => write!(f, "decode error: {}", err),
+            DriverError::Timeout => write!(f, "timed out"),
+            DriverError::Busy(reason) => write!(f, "busy: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DriverError {}
+
+/// Signal quality a radio reported for a received packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalQuality {
+    pub rssi_dbm: i16,
+    pub snr_db: i16,
+}
+
+impl From<std::io::Error> for DriverError {
+    fn from(err: std::io::Error) -> Self {
+        DriverError::Io(err)
+    }
+}
 
 #[async_trait::async_trait]
 pub trait Driver<P>: Display + Send + Sync {
-    async fn send(&self, packet_to_send: &P);
-    async fn receive(&self) -> Option<P>;
+    async fn send(&self, packet_to_send: &P) -> Result<(), DriverError>;
+    // Packets queued back to back, drivers able to pack them into fewer transmissions override it
+    async fn send_batch(&self, packets_to_send: &[P]) -> Result<(), DriverError>
+    where
+        P: Sync,
+    {
+        for packet in packets_to_send {
+            self.send(packet).await?;
+        }
+        Ok(())
+    }
+    // None when something was received but there is no packet to hand out yet, such as a fragment
+    async fn receive(&self) -> Result<Option<P>, DriverError>;
     // Only relevant for drivers that work in half-duplex mode
-    async fn prepare_to_receive(&self) -> Result<(), &str> {
+    async fn prepare_to_receive(&self) -> Result<(), DriverError> {
         Ok(())
     }
-    async fn prepare_to_send(&self) -> Result<(), &str> {
+    async fn prepare_to_send(&self) -> Result<(), DriverError> {
         Ok(())
     }
-    async fn ready_to_receive(&self) -> Result<(), &str> {
+    async fn ready_to_receive(&self) -> Result<(), DriverError> {
         Ok(())
     }
+    // Only relevant for radio drivers, whether another node is transmitting on the channel
+    async fn channel_busy(&self) -> Result<bool, DriverError> {
+        Ok(false)
+    }
+    // Only relevant for radio drivers, resets the radio and configures it again after it stopped responding
+    async fn reinitialize(&self) -> Result<(), DriverError> {
+        Ok(())
+    }
+    // Only relevant for LoRa drivers, used to compute the time on air of packets
+    fn modulation(&self) -> Option<LoRaModulation> {
+        None
+    }
+    // Only relevant for LoRa drivers, the time on air of a packet once the link layers encoded it, with the
+    // preamble of every payload it is split into
+    fn time_on_air(&self, _packet: &P) -> Option<Duration> {
+        None
+    }
+    // Only relevant for LoRa radio drivers, switches spreading factor, bandwidth and coding rate. The radio
+    // uses the new modulation from the next prepare call on.
+    async fn set_modulation(&self, _modulation: LoRaModulation) -> Result<(), DriverError> {
+        Err(DriverError::Radio(format!("{} cannot change its modulation", self)))
+    }
+    // Only relevant for radio drivers, the signal quality of the last packet received
+    fn signal_quality(&self) -> Option<SignalQuality> {
+        None
+    }
+    // Only relevant for drivers whose link layers transmit on their own, such as acknowledgements, when
+    // they next need to. A `send_batch` without packets transmits what is due.
+    fn next_link_transmit(&self) -> Option<Instant> {
+        None
+    }
+}
+
+/// LoRa radio driver whose payloads pass through link layers and transmit guards, set up before it is shared
+pub trait LoRaRadio: Driver<MavFramePacket> + Sized {
+    /// Adds a link layer between the serialized frames and the radio, on top of the ones added before
+    fn with_link_layer(self, layer: impl LinkLayer + 'static) -> Self;
+    /// Signs the frames sent over the radio and drops the received ones failing verification
+    fn with_signing(self, signing: MavlinkSigning) -> Self;
+    /// Applies a guard to every payload transmitted, such as a duty cycle or a transmit slot
+    fn with_transmit_guard(self, guard: impl TransmitGuard + 'static) -> Self;
+}
+
+/// Driver adding behaviour on top of another one, every operation it does not override is passed
+/// through to the wrapped driver
+#[async_trait::async_trait]
+pub trait DriverWrapper<P: Send + Sync>: Display + Send + Sync {
+    /// The wrapped driver
+    fn inner(&self) -> &Arc<dyn Driver<P> + Send + Sync>;
+    async fn send(&self, packet_to_send: &P) -> Result<(), DriverError> {
+        self.inner().send(packet_to_send).await
+    }
+    async fn send_batch(&self, packets_to_send: &[P]) -> Result<(), DriverError> {
+        self.inner().send_batch(packets_to_send).await
+    }
+    async fn receive(&self) -> Result<Option<P>, DriverError> {
+        self.inner().receive().await
+    }
+    async fn prepare_to_receive(&self) -> Result<(), DriverError> {
+        self.inner().prepare_to_receive().await
+    }
+    async fn prepare_to_send(&self) -> Result<(), DriverError> {
+        self.inner().prepare_to_send().await
+    }
+    async fn ready_to_receive(&self) -> Result<(), DriverError> {
+        self.inner().ready_to_receive().await
+    }
+    async fn channel_busy(&self) -> Result<bool, DriverError> {
+        self.inner().channel_busy().await
+    }
+    async fn reinitialize(&self) -> Result<(), DriverError> {
+        self.inner().reinitialize().await
+    }
+    fn modulation(&self) -> Option<LoRaModulation> {
+        self.inner().modulation()
+    }
+    fn time_on_air(&self, packet: &P) -> Option<Duration> {
+        self.inner().time_on_air(packet)
+    }
+    async fn set_modulation(&self, modulation: LoRaModulation) -> Result<(), DriverError> {
+        self.inner().set_modulation(modulation).await
+    }
+    fn signal_quality(&self) -> Option<SignalQuality> {
+        self.inner().signal_quality()
+    }
+    fn next_link_transmit(&self) -> Option<Instant> {
+        self.inner().next_link_transmit()
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: Send + Sync, W: DriverWrapper<P>> Driver<P> for W {
+    async fn send(&self, packet_to_send: &P) -> Result<(), DriverError> {
+        DriverWrapper::send(self, packet_to_send).await
+    }
+    async fn send_batch(&self, packets_to_send: &[P]) -> Result<(), DriverError>
+    where
+        P: Sync,
+    {
+        DriverWrapper::send_batch(self, packets_to_send).await
+    }
+    async fn receive(&self) -> Result<Option<P>, DriverError> {
+        DriverWrapper::receive(self).await
+    }
+    async fn prepare_to_receive(&self) -> Result<(), DriverError> {
+        DriverWrapper::prepare_to_receive(self).await
+    }
+    async fn prepare_to_send(&self) -> Result<(), DriverError> {
+        DriverWrapper::prepare_to_send(self).await
+    }
+    async fn ready_to_receive(&self) -> Result<(), DriverError> {
+        DriverWrapper::ready_to_receive(self).await
+    }
+    async fn channel_busy(&self) -> Result<bool, DriverError> {
+        DriverWrapper::channel_busy(self).await
+    }
+    async fn reinitialize(&self) -> Result<(), DriverError> {
+        DriverWrapper::reinitialize(self).await
+    }
+    fn modulation(&self) -> Option<LoRaModulation> {
+        DriverWrapper::modulation(self)
+    }
+    fn time_on_air(&self, packet: &P) -> Option<Duration> {
+        DriverWrapper::time_on_air(self, packet)
+    }
+    async fn set_modulation(&self, modulation: LoRaModulation) -> Result<(), DriverError> {
+        DriverWrapper::set_modulation(self, modulation).await
+    }
+    fn signal_quality(&self) -> Option<SignalQuality> {
+        DriverWrapper::signal_quality(self)
+    }
+    fn next_link_transmit(&self) -> Option<Instant> {
+        DriverWrapper::next_link_transmit(self)
+    }
 }
@@ -0,0 +1,178 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{split, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::Mutex;
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+use super::{Driver, DriverError};
+use crate::config::SerialSection;
+use crate::define_struct_with_defaults;
+use crate::mavlink_utils::{serialize_frame, MavlinkStreamParser};
+use crate::utils::logging_utils::{
+    log_debug_receive_packet, log_debug_send_packet, log_driver_creation, log_serial_connected, log_serial_disconnected,
+};
+use crate::utils::types::{MavFramePacket, SerialFlowControl, SerialParity};
+
+pub const SERIAL_DRIVER: &str = "serial_driver";
+
+const READ_BUFFER_SIZE: usize = 1024;
+
+impl From<SerialFlowControl> for tokio_serial::FlowControl {
+    fn from(flow_control: SerialFlowControl) -> Self {
+        match flow_control {
+            SerialFlowControl::None => tokio_serial::FlowControl::None,
+            SerialFlowControl::Software => tokio_serial::FlowControl::Software,
+            SerialFlowControl::Hardware => tokio_serial::FlowControl::Hardware,
+        }
+    }
+}
+
+impl From<SerialParity> for tokio_serial::Parity {
+    fn from(parity: SerialParity) -> Self {
+        match parity {
+            SerialParity::None => tokio_serial::Parity::None,
+            SerialParity::Odd => tokio_serial::Parity::Odd,
+            SerialParity::Even => tokio_serial::Parity::Even,
+        }
+    }
+}
+
+define_struct_with_defaults! {
+    SerialOptionalInitConfig, SerialInitConfig {
+        path: String = "/dev/ttyACM0".to_string(),
+        baud_rate: u32 = 57_600,
+        flow_control: SerialFlowControl = SerialFlowControl::None,
+        parity: SerialParity = SerialParity::None,
+        // Delay between attempts to open the device while it is missing
+        reconnect_interval_ms: u64 = 1000,
+    }
+}
+
+impl From<&SerialSection> for SerialOptionalInitConfig {
+    fn from(section: &SerialSection) -> Self {
+        Self {
+            path: section.path.clone(),
+            baud_rate: section.baud_rate,
+            flow_control: section.flow_control,
+            parity: section.parity,
+            reconnect_interval_ms: section.reconnect_interval_ms,
+        }
+    }
+}
+
+struct SerialConnection {
+    reader: Mutex<ReadHalf<SerialStream>>,
+    writer: Mutex<WriteHalf<SerialStream>>,
+}
+
+/// Driver for a flight controller on a serial port, such as a Pixhawk TELEM port.
+///
+/// The device is opened lazily and reopened after it disappears, so the node keeps running while
+/// the autopilot reboots or the USB cable is replugged. Frames are reassembled from the byte stream
+/// whatever the size of the reads.
+pub struct SerialDriver {
+    config: SerialInitConfig,
+    connection: std::sync::Mutex<Option<Arc<SerialConnection>>>,
+    parser: std::sync::Mutex<MavlinkStreamParser>,
+}
+
+impl Display for SerialDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", SERIAL_DRIVER)
+    }
+}
+
+impl SerialDriver {
+    pub fn new(init_config: Option<SerialOptionalInitConfig>) -> Self {
+        let init_config = init_config.unwrap_or_default().build();
+
+        log_driver_creation(SERIAL_DRIVER);
+
+        Self {
+            config: init_config,
+            connection: std::sync::Mutex::new(None),
+            parser: std::sync::Mutex::new(MavlinkStreamParser::new()),
+        }
+    }
+
+    /// Current connection to the device, opening it if needed
+    fn connection(&self) -> Option<Arc<SerialConnection>> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            let stream = tokio_serial::new(&self.config.path, self.config.baud_rate)
+                .flow_control(self.config.flow_control.into())
+                .parity(self.config.parity.into())
+                .open_native_async();
+            match stream {
+                Ok(stream) => {
+                    let (reader, writer) = split(stream);
+                    *connection = Some(Arc::new(SerialConnection {
+                        reader: Mutex::new(reader),
+                        writer: Mutex::new(writer),
+                    }));
+                    // Bytes left from the previous connection cannot complete a frame of this one
+                    *self.parser.lock().unwrap() = MavlinkStreamParser::new();
+                    log_serial_connected(SERIAL_DRIVER, &self.config.path);
+                }
+                Err(err) => log_serial_disconnected(SERIAL_DRIVER, &self.config.path, &err.to_string()),
+            }
+        }
+        connection.clone()
+    }
+
+    /// Drops a failed connection, unless the other direction already replaced it
+    fn disconnect(&self, failed: &Arc<SerialConnection>, error: &str) {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.as_ref().is_some_and(|current| Arc::ptr_eq(current, failed)) {
+            *connection = None;
+            log_serial_disconnected(SERIAL_DRIVER, &self.config.path, error);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Driver<MavFramePacket> for SerialDriver {
+    async fn send(&self, packet: &MavFramePacket) -> Result<(), DriverError> {
+        let connection = self
+            .connection()
+            .ok_or_else(|| DriverError::not_connected("Serial device not connected"))?;
+        let serialised_frame = serialize_frame(packet.clone());
+        let result = connection.writer.lock().await.write_all(&serialised_frame).await;
+        if let Err(err) = &result {
+            self.disconnect(&connection, &err.to_string());
+        }
+        result?;
+        log_debug_send_packet(SERIAL_DRIVER, packet);
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Option<MavFramePacket>, DriverError> {
+        let mut buffer = [0u8; READ_BUFFER_SIZE];
+        loop {
+            let buffered_frame = self.parser.lock().unwrap().next_frame();
+            if let Some(mavlink_frame) = buffered_frame {
+                log_debug_receive_packet(SERIAL_DRIVER, &mavlink_frame, None, None);
+                return Ok(Some(mavlink_frame));
+            }
+
+            let Some(connection) = self.connection() else {
+                tokio::time::sleep(Duration::from_millis(self.config.reconnect_interval_ms)).await;
+                return Err(DriverError::not_connected("Serial device not connected"));
+            };
+            let result = connection.reader.lock().await.read(&mut buffer).await;
+            match result {
+                Ok(0) => {
+                    self.disconnect(&connection, "end of stream");
+                    return Err(DriverError::not_connected("end of stream"));
+                }
+                Ok(length) => self.parser.lock().unwrap().push(&buffer[..length]),
+                Err(err) => {
+                    self.disconnect(&connection, &err.to_string());
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+}
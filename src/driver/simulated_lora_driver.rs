@@ -0,0 +1,239 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use super::{Driver, DriverError, LoRaRadio, SignalQuality};
+use crate::define_struct_with_defaults;
+use crate::mavlink_utils::{deserialize_frame, serialize_frame, MavlinkSigning};
+use crate::utils::link_layer::{LinkLayer, LinkLayerStack};
+use crate::utils::logging_utils::{log_debug_receive_packet, log_debug_send_packet, log_driver_creation};
+use crate::utils::lora_airtime::LoRaModulation;
+use crate::utils::simulated_air::SimulatedAir;
+use crate::utils::transmit_guard::{TransmitGuard, TransmitGuards};
+use crate::utils::types::MavFramePacket;
+
+pub const SIMULATED_LORA_DRIVER: &str = "simulated_lora_driver";
+
+define_struct_with_defaults! {
+    SimulatedLoRaOptionalInitConfig, SimulatedLoRaInitConfig {
+        spreading_factor: u8 = 7,
+        bandwidth_hz: u32 = 250_000,
+        coding_rate: u8 = 5,
+        frequency_hz: u32 = 868_100_000,
+        preamble_length: u16 = 4,
+        implicit_header: bool = false,
+        crc_enabled: bool = true,
+        max_payload_length: u8 = 255,
+        // Packs frames sent back to back into shared transmissions up to the max payload length
+        pack_frames: bool = false,
+        packet_loss: f64 = 0.0,
+        // Signal quality reported for every packet received, the SNR is the one at the configured bandwidth.
+        // Packets below the demodulation floor of the spreading factor in use are lost.
+        rssi_dbm: i16 = -80,
+        snr_db: i16 = 8,
+    }
+}
+
+/// LoRa driver transmitting over a [`SimulatedAir`] medium instead of a radio, for hardware-free testing
+pub struct SimulatedLoRaDriver {
+    pub air: Arc<SimulatedAir>,
+    node_id: usize,
+    notify: Arc<Notify>,
+    modulation: std::sync::Mutex<LoRaModulation>,
+    max_payload_length: u8,
+    link_layers: LinkLayerStack,
+    guards: TransmitGuards,
+    signal: std::sync::Mutex<SignalQuality>,
+    // Bandwidth the configured SNR applies to, a wider one lets in more noise
+    signal_bandwidth_hz: u32,
+    last_signal: std::sync::Mutex<Option<SignalQuality>>,
+}
+
+impl Display for SimulatedLoRaDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", SIMULATED_LORA_DRIVER)
+    }
+}
+
+impl SimulatedLoRaDriver {
+    pub fn new(air: Arc<SimulatedAir>, init_config: Option<SimulatedLoRaOptionalInitConfig>) -> Self {
+        let init_config = init_config.unwrap_or_default().build();
+
+        let modulation = LoRaModulation {
+            spreading_factor: init_config.spreading_factor,
+            bandwidth_hz: init_config.bandwidth_hz,
+            coding_rate: init_config.coding_rate,
+            frequency_hz: init_config.frequency_hz,
+            preamble_length: init_config.preamble_length,
+            implicit_header: init_config.implicit_header,
+            crc_enabled: init_config.crc_enabled,
+        };
+        let (node_id, notify) = air.register(modulation, init_config.packet_loss);
+
+        log_driver_creation(SIMULATED_LORA_DRIVER);
+
+        let mut link_layers = LinkLayerStack::default();
+        if init_config.pack_frames {
+            link_layers.enable_packing(init_config.max_payload_length as usize);
+        }
+
+        Self {
+            air,
+            node_id,
+            notify,
+            modulation: std::sync::Mutex::new(modulation),
+            max_payload_length: init_config.max_payload_length,
+            link_layers,
+            guards: TransmitGuards::default(),
+            signal: std::sync::Mutex::new(SignalQuality {
+                rssi_dbm: init_config.rssi_dbm,
+                snr_db: init_config.snr_db,
+            }),
+            signal_bandwidth_hz: init_config.bandwidth_hz,
+            last_signal: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// Signal quality of a packet received with the modulation in use, None when it is too weak to be demodulated
+    fn current_signal(&self) -> Option<SignalQuality> {
+        let modulation = *self.modulation.lock().unwrap();
+        let signal = *self.signal.lock().unwrap();
+        let snr_db =
+            signal.snr_db as f64 - 10.0 * (modulation.bandwidth_hz as f64 / self.signal_bandwidth_hz as f64).log10();
+        (snr_db >= modulation.demodulation_floor_db()).then_some(SignalQuality {
+            rssi_dbm: signal.rssi_dbm,
+            snr_db: snr_db.round() as i16,
+        })
+    }
+
+    /// Changes the signal quality the packets of the peer arrive with, as when the nodes move apart
+    pub fn set_signal(&self, rssi_dbm: i16, snr_db: i16) {
+        *self.signal.lock().unwrap() = SignalQuality { rssi_dbm, snr_db };
+    }
+}
+
+impl LoRaRadio for SimulatedLoRaDriver {
+    fn with_link_layer(mut self, layer: impl LinkLayer + 'static) -> Self {
+        self.link_layers.push(layer);
+        self
+    }
+
+    fn with_signing(mut self, signing: MavlinkSigning) -> Self {
+        self.link_layers.set_signing(Arc::new(signing));
+        self
+    }
+
+    fn with_transmit_guard(mut self, guard: impl TransmitGuard + 'static) -> Self {
+        self.guards.push(guard);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Driver<MavFramePacket> for SimulatedLoRaDriver {
+    async fn send(&self, packet: &MavFramePacket) -> Result<(), DriverError> {
+        self.send_batch(std::slice::from_ref(packet)).await
+    }
+
+    async fn send_batch(&self, packets: &[MavFramePacket]) -> Result<(), DriverError> {
+        let modulation = *self.modulation.lock().unwrap();
+        let driver = self.to_string();
+        let plan = self
+            .guards
+            .encode_batch(&driver, &modulation, &self.link_layers, packets);
+        if plan
+            .payloads
+            .iter()
+            .any(|payload| payload.len() > self.max_payload_length as usize)
+        {
+            return Err(DriverError::Radio("Packet exceeds max payload length".to_string()));
+        }
+
+        for payload in &plan.payloads {
+            let started = Instant::now();
+            self.air.transmit(self.node_id, payload.clone()).await;
+            self.guards.book(&driver, &modulation, started, payload);
+        }
+        if plan.dropped {
+            return Err(DriverError::Busy("payloads not allowed on air were dropped".to_string()));
+        }
+        for packet in packets {
+            log_debug_send_packet(&self.to_string(), packet);
+        }
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Option<MavFramePacket>, DriverError> {
+        let frame = match self.link_layers.pop_decoded() {
+            Some(frame) => Some(frame),
+            None => self.air.take_pending(self.node_id).and_then(|payload| {
+                let signal = self.current_signal()?;
+                *self.last_signal.lock().unwrap() = Some(signal);
+                self.link_layers.decode(payload)
+            }),
+        };
+        let Some(frame) = frame else {
+            return Ok(None);
+        };
+        let mavlink_frame = deserialize_frame(&frame[..]).ok_or_else(DriverError::invalid_frame)?;
+        let signal = self.last_signal.lock().unwrap().unwrap_or(*self.signal.lock().unwrap());
+        log_debug_receive_packet(
+            &self.to_string(),
+            &mavlink_frame,
+            Some(signal.rssi_dbm),
+            Some(signal.snr_db),
+        );
+        Ok(Some(mavlink_frame))
+    }
+
+    async fn ready_to_receive(&self) -> Result<(), DriverError> {
+        // A notification arriving between the check and the wait is kept as a permit, so none is missed
+        while !self.link_layers.has_decoded() && !self.air.has_pending(self.node_id) {
+            self.notify.notified().await;
+        }
+        Ok(())
+    }
+
+    async fn prepare_to_receive(&self) -> Result<(), DriverError> {
+        self.air.start_listening(self.node_id);
+        Ok(())
+    }
+
+    async fn prepare_to_send(&self) -> Result<(), DriverError> {
+        self.air.stop_listening(self.node_id);
+        Ok(())
+    }
+
+    async fn channel_busy(&self) -> Result<bool, DriverError> {
+        Ok(self.air.channel_active(self.node_id))
+    }
+
+    fn modulation(&self) -> Option<LoRaModulation> {
+        Some(*self.modulation.lock().unwrap())
+    }
+
+    fn time_on_air(&self, packet: &MavFramePacket) -> Option<Duration> {
+        let modulation = *self.modulation.lock().unwrap();
+        Some(
+            self.link_layers
+                .time_on_air(&modulation, &serialize_frame(packet.clone())),
+        )
+    }
+
+    async fn set_modulation(&self, modulation: LoRaModulation) -> Result<(), DriverError> {
+        *self.modulation.lock().unwrap() = modulation;
+        self.air.set_modulation(self.node_id, modulation);
+        Ok(())
+    }
+
+    fn signal_quality(&self) -> Option<SignalQuality> {
+        *self.last_signal.lock().unwrap()
+    }
+
+    fn next_link_transmit(&self) -> Option<Instant> {
+        self.link_layers.next_transmit()
+    }
+}
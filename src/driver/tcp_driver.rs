@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{sleep, timeout};
+
+use super::{Driver, DriverError};
+use crate::config::{TcpClientSection, TcpServerSection};
+use crate::define_struct_with_defaults;
+use crate::mavlink_utils::{serialize_frame, MavlinkStreamParser};
+use crate::utils::logging_utils::{
+    log_debug_receive_packet, log_debug_send_packet, log_driver_creation, log_tcp_connected, log_tcp_disconnected,
+};
+use crate::utils::types::MavFramePacket;
+
+pub const TCP_SERVER_DRIVER: &str = "tcp_server_driver";
+pub const TCP_CLIENT_DRIVER: &str = "tcp_client_driver";
+
+const READ_BUFFER_SIZE: usize = 1024;
+// Frames received from all clients of a server, waiting for `receive`
+const SERVER_INCOMING_CHANNEL_SIZE: usize = 100;
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+define_struct_with_defaults! {
+    TcpServerOptionalInitConfig, TcpServerInitConfig {
+        addr: String = "0.0.0.0:5760".to_string(),
+        // A client not accepting a frame within this time is disconnected, so it cannot stall the others
+        write_timeout_ms: u64 = 1000,
+    }
+}
+
+impl From<&TcpServerSection> for TcpServerOptionalInitConfig {
+    fn from(section: &TcpServerSection) -> Self {
+        Self {
+            addr: section.addr.clone(),
+            write_timeout_ms: section.write_timeout_ms,
+        }
+    }
+}
+
+/// Driver accepting any number of ground station clients, QGroundControl and mavproxy use port 5760.
+///
+/// Every frame sent goes to all connected clients, and frames from all clients come out of
+/// `receive` in arrival order. Each client has its own parser, so the streams never mix.
+pub struct TcpServerDriver {
+    pub local_addr: SocketAddr,
+    write_timeout: Duration,
+    clients: Arc<Mutex<HashMap<SocketAddr, OwnedWriteHalf>>>,
+    incoming: Mutex<mpsc::Receiver<MavFramePacket>>,
+}
+
+impl Display for TcpServerDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", TCP_SERVER_DRIVER)
+    }
+}
+
+impl TcpServerDriver {
+    pub async fn new(init_config: Option<TcpServerOptionalInitConfig>) -> Self {
+        let init_config = init_config.unwrap_or_default().build();
+
+        let listener = TcpListener::bind(&init_config.addr)
+            .await
+            .expect("Failed to bind TCP listener");
+        let local_addr = listener.local_addr().expect("TCP listener without local address");
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (incoming_tx, incoming_rx) = mpsc::channel(SERVER_INCOMING_CHANNEL_SIZE);
+        tokio::spawn(accept_clients(listener, clients.clone(), incoming_tx));
+
+        log_driver_creation(TCP_SERVER_DRIVER);
+
+        Self {
+            local_addr,
+            write_timeout: Duration::from_millis(init_config.write_timeout_ms),
+            clients,
+            incoming: Mutex::new(incoming_rx),
+        }
+    }
+}
+
+async fn accept_clients(
+    listener: TcpListener,
+    clients: Arc<Mutex<HashMap<SocketAddr, OwnedWriteHalf>>>,
+    incoming_tx: mpsc::Sender<MavFramePacket>,
+) {
+    loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                log_tcp_disconnected(TCP_SERVER_DRIVER, "accept", &err.to_string());
+                // Accepting fails while the process is out of file descriptors, give clients time to leave
+                sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        let (reader, writer) = stream.into_split();
+        clients.lock().await.insert(peer_addr, writer);
+        log_tcp_connected(TCP_SERVER_DRIVER, &peer_addr.to_string());
+        tokio::spawn(read_client(reader, peer_addr, clients.clone(), incoming_tx.clone()));
+    }
+}
+
+async fn read_client(
+    mut reader: OwnedReadHalf,
+    peer_addr: SocketAddr,
+    clients: Arc<Mutex<HashMap<SocketAddr, OwnedWriteHalf>>>,
+    incoming_tx: mpsc::Sender<MavFramePacket>,
+) {
+    let mut parser = MavlinkStreamParser::new();
+    let mut buffer = [0u8; READ_BUFFER_SIZE];
+    let reason = loop {
+        match reader.read(&mut buffer).await {
+            Ok(0) => break "closed by peer".to_string(),
+            Ok(length) => parser.push(&buffer[..length]),
+            Err(err) => break err.to_string(),
+        }
+        while let Some(mavlink_frame) = parser.next_frame() {
+            log_debug_receive_packet(TCP_SERVER_DRIVER, &mavlink_frame, None, None);
+            if incoming_tx.send(mavlink_frame).await.is_err() {
+                // The driver was dropped
+                return;
+            }
+        }
+    };
+    // The send side may have dropped the client already
+    if clients.lock().await.remove(&peer_addr).is_some() {
+        log_tcp_disconnected(TCP_SERVER_DRIVER, &peer_addr.to_string(), &reason);
+    }
+}
+
+#[async_trait::async_trait]
+impl Driver<MavFramePacket> for TcpServerDriver {
+    async fn send(&self, packet: &MavFramePacket) -> Result<(), DriverError> {
+        let serialised_frame = serialize_frame(packet.clone());
+        let mut clients = self.clients.lock().await;
+        let mut failed_clients = Vec::new();
+        for (peer_addr, writer) in clients.iter_mut() {
+            match timeout(self.write_timeout, writer.write_all(&serialised_frame)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => failed_clients.push((*peer_addr, err.to_string())),
+                Err(_) => failed_clients.push((*peer_addr, "write timed out".to_string())),
+            }
+        }
+        for (peer_addr, reason) in failed_clients {
+            clients.remove(&peer_addr);
+            log_tcp_disconnected(TCP_SERVER_DRIVER, &peer_addr.to_string(), &reason);
+        }
+        // Clients that failed are dropped above, the others still got the frame
+        if !clients.is_empty() {
+            log_debug_send_packet(TCP_SERVER_DRIVER, packet);
+        }
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Option<MavFramePacket>, DriverError> {
+        match self.incoming.lock().await.recv().await {
+            Some(mavlink_frame) => Ok(Some(mavlink_frame)),
+            None => Err(DriverError::not_connected("TCP listener stopped")),
+        }
+    }
+}
+
+define_struct_with_defaults! {
+    TcpClientOptionalInitConfig, TcpClientInitConfig {
+        addr: String = "127.0.0.1:5760".to_string(),
+        // Delay before the first reconnection attempt, doubled after each failure up to the maximum
+        initial_backoff_ms: u64 = 500,
+        max_backoff_ms: u64 = 10_000,
+    }
+}
+
+impl From<&TcpClientSection> for TcpClientOptionalInitConfig {
+    fn from(section: &TcpClientSection) -> Self {
+        Self {
+            addr: section.addr.clone(),
+            initial_backoff_ms: section.initial_backoff_ms,
+            max_backoff_ms: section.max_backoff_ms,
+        }
+    }
+}
+
+struct TcpConnection {
+    reader: Mutex<OwnedReadHalf>,
+    writer: Mutex<OwnedWriteHalf>,
+}
+
+/// Driver connecting to a TCP MAVLink endpoint, such as SITL or mavproxy on port 5760.
+///
+/// The connection is made and remade by `receive`, waiting longer after each failed attempt.
+/// Frames sent while disconnected are dropped.
+pub struct TcpClientDriver {
+    config: TcpClientInitConfig,
+    connection: std::sync::Mutex<Option<Arc<TcpConnection>>>,
+    backoff: std::sync::Mutex<Duration>,
+    parser: std::sync::Mutex<MavlinkStreamParser>,
+}
+
+impl Display for TcpClientDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", TCP_CLIENT_DRIVER)
+    }
+}
+
+impl TcpClientDriver {
+    pub fn new(init_config: Option<TcpClientOptionalInitConfig>) -> Self {
+        let init_config = init_config.unwrap_or_default().build();
+
+        log_driver_creation(TCP_CLIENT_DRIVER);
+
+        Self {
+            backoff: std::sync::Mutex::new(Duration::from_millis(init_config.initial_backoff_ms)),
+            config: init_config,
+            connection: std::sync::Mutex::new(None),
+            parser: std::sync::Mutex::new(MavlinkStreamParser::new()),
+        }
+    }
+
+    async fn connect(&self) -> Option<Arc<TcpConnection>> {
+        let existing = self.connection.lock().unwrap().clone();
+        if existing.is_some() {
+            return existing;
+        }
+
+        match TcpStream::connect(&self.config.addr).await {
+            Ok(stream) => {
+                let _ = stream.set_nodelay(true);
+                let (reader, writer) = stream.into_split();
+                let connection = Arc::new(TcpConnection {
+                    reader: Mutex::new(reader),
+                    writer: Mutex::new(writer),
+                });
+                *self.connection.lock().unwrap() = Some(connection.clone());
+                *self.backoff.lock().unwrap() = Duration::from_millis(self.config.initial_backoff_ms);
+                // Bytes left from the previous connection cannot complete a frame of this one
+                *self.parser.lock().unwrap() = MavlinkStreamParser::new();
+                log_tcp_connected(TCP_CLIENT_DRIVER, &self.config.addr);
+                Some(connection)
+            }
+            Err(err) => {
+                let backoff = {
+                    let mut backoff = self.backoff.lock().unwrap();
+                    let current = *backoff;
+                    *backoff = (current * 2).min(Duration::from_millis(self.config.max_backoff_ms));
+                    current
+                };
+                log_tcp_disconnected(TCP_CLIENT_DRIVER, &self.config.addr, &err.to_string());
+                sleep(backoff).await;
+                None
+            }
+        }
+    }
+
+    /// Drops a failed connection, unless it was already replaced
+    fn disconnect(&self, failed: &Arc<TcpConnection>, reason: &str) {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.as_ref().is_some_and(|current| Arc::ptr_eq(current, failed)) {
+            *connection = None;
+            log_tcp_disconnected(TCP_CLIENT_DRIVER, &self.config.addr, reason);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Driver<MavFramePacket> for TcpClientDriver {
+    async fn send(&self, packet: &MavFramePacket) -> Result<(), DriverError> {
+        let connection = self.connection.lock().unwrap().clone();
+        let connection = connection.ok_or_else(|| DriverError::not_connected("Not connected"))?;
+        let serialised_frame = serialize_frame(packet.clone());
+        let result = connection.writer.lock().await.write_all(&serialised_frame).await;
+        if let Err(err) = &result {
+            self.disconnect(&connection, &err.to_string());
+        }
+        result?;
+        log_debug_send_packet(TCP_CLIENT_DRIVER, packet);
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Option<MavFramePacket>, DriverError> {
+        let mut buffer = [0u8; READ_BUFFER_SIZE];
+        loop {
+            let buffered_frame = self.parser.lock().unwrap().next_frame();
+            if let Some(mavlink_frame) = buffered_frame {
+                log_debug_receive_packet(TCP_CLIENT_DRIVER, &mavlink_frame, None, None);
+                return Ok(Some(mavlink_frame));
+            }
+
+            let connection = self
+                .connect()
+                .await
+                .ok_or_else(|| DriverError::not_connected("Not connected"))?;
+            let result = connection.reader.lock().await.read(&mut buffer).await;
+            match result {
+                Ok(0) => {
+                    self.disconnect(&connection, "closed by peer");
+                    return Err(DriverError::not_connected("closed by peer"));
+                }
+                Ok(length) => self.parser.lock().unwrap().push(&buffer[..length]),
+                Err(err) => {
+                    self.disconnect(&connection, &err.to_string());
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+}
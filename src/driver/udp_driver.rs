@@ -1,26 +1,60 @@
+use std::collections::HashMap;
 use std::fmt::Display;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use mavlink::Message as MavMessage;
 use tokio::net::UdpSocket;
 
-use super::Driver;
+use super::{Driver, DriverError};
+use crate::config::UdpSection;
 use crate::mavlink_utils::{deserialize_frame, serialize_frame};
-use crate::utils::logging_utils::{log_debug_receive_packet, log_debug_send_packet, log_driver_creation};
-use crate::utils::types::MavFramePacket;
+use crate::utils::filter::MessageFilter;
+use crate::utils::logging_utils::{
+    log_debug_receive_packet, log_debug_send_packet, log_driver_creation, log_udp_peer_expired, log_udp_peer_learned,
+};
+use crate::utils::types::{Direction, MavFramePacket, UdpMode};
 
 pub const UDP_DRIVER: &str = "udp_driver";
+pub const DEFAULT_UDP_PEER_TIMEOUT_MS: u64 = 10_000;
+/// Messages dropped when received over UDP unless a filter is configured for the link: ATTITUDE,
+/// MISSION_CURRENT, VFR_HUD, RADIO_STATUS, ALTITUDE and EVENT
+pub const UDP_DENIED_MESSAGE_IDS: [u32; 6] = [30, 42, 74, 109, 141, 410];
+// Largest UDP payload over IPv4, a datagram is truncated to the buffer it is read into
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// Filter of a UDP link without a configured one, dropping the [`UDP_DENIED_MESSAGE_IDS`] it receives
+pub fn default_udp_filter() -> MessageFilter {
+    MessageFilter::deny_message_ids(Direction::Incoming, UDP_DENIED_MESSAGE_IDS.to_vec())
+}
 
-#[allow(dead_code)]
 pub struct UDPConfig {
     pub addr: String,
-    pub dest_addr: String,
+    pub dest_addr: Option<String>,
     pub broadcast: bool,
+    pub mode: UdpMode,
+    pub unicast_addrs: Vec<String>,
+    pub peer_timeout_ms: u64,
+}
+
+impl From<&UdpSection> for UDPConfig {
+    fn from(section: &UdpSection) -> Self {
+        Self {
+            addr: section.addr.clone(),
+            dest_addr: section.dest_addr.clone(),
+            broadcast: section.broadcast,
+            mode: section.mode,
+            unicast_addrs: section.unicast_addrs.clone(),
+            peer_timeout_ms: section.peer_timeout_ms.unwrap_or(DEFAULT_UDP_PEER_TIMEOUT_MS),
+        }
+    }
 }
 
 pub struct UDPDriver {
     pub device: Arc<UdpSocket>,
     config: UDPConfig,
+    // Peers of a server with the time they were last heard from
+    peers: Mutex<HashMap<SocketAddr, Instant>>,
 }
 
 impl Display for UDPDriver {
@@ -38,49 +72,73 @@ impl UDPDriver {
 
         log_driver_creation(UDP_DRIVER);
 
-        Self { device: socket, config }
+        Self {
+            device: socket,
+            config,
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Addresses a frame is sent to, forgetting the server peers that went quiet
+    fn destinations(&self) -> Vec<String> {
+        match self.config.mode {
+            UdpMode::Client => self
+                .config
+                .dest_addr
+                .iter()
+                .chain(self.config.unicast_addrs.iter())
+                .cloned()
+                .collect(),
+            UdpMode::Server => {
+                let peer_timeout = Duration::from_millis(self.config.peer_timeout_ms);
+                let mut peers = self.peers.lock().unwrap();
+                peers.retain(|peer, last_heard| {
+                    let active = last_heard.elapsed() < peer_timeout;
+                    if !active {
+                        log_udp_peer_expired(UDP_DRIVER, &peer.to_string());
+                    }
+                    active
+                });
+                peers.keys().map(|peer| peer.to_string()).collect()
+            }
+        }
+    }
+
+    fn learn_peer(&self, peer: SocketAddr) {
+        if self.config.mode == UdpMode::Server && self.peers.lock().unwrap().insert(peer, Instant::now()).is_none() {
+            log_udp_peer_learned(UDP_DRIVER, &peer.to_string());
+        }
     }
 }
 
 #[async_trait::async_trait]
 impl Driver<MavFramePacket> for UDPDriver {
-    async fn send(&self, packet: &MavFramePacket) {
+    async fn send(&self, packet: &MavFramePacket) -> Result<(), DriverError> {
         let socket_send = Arc::clone(&self.device);
         let serialised_frame = serialize_frame(packet.clone());
         // log_packet_sent(raw_frame.len(), Some(&dest_addr), &packet, UDP_DRIVER);
         log_debug_send_packet(&self.to_string(), packet);
-        let _ = socket_send.send_to(&serialised_frame, &self.config.dest_addr).await;
+        // A destination failing does not keep the frame from the others
+        let mut result = Ok(());
+        for destination in self.destinations() {
+            if let Err(err) = socket_send.send_to(&serialised_frame, &destination).await {
+                result = Err(err.into());
+            }
+        }
+        result
     }
 
-    async fn receive(&self) -> Option<MavFramePacket> {
-        let mut buf = [0; 256];
+    async fn receive(&self) -> Result<Option<MavFramePacket>, DriverError> {
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
         let socket_recv = Arc::clone(&self.device);
 
-        match socket_recv.recv_from(&mut buf).await {
-            Ok((size, _src_addr)) => {
-                let received_data = Vec::from(&buf[..size]);
-                if let Some(mavlink_frame) = deserialize_frame(&received_data[..]) {
-                    // log_packet_received(size, Some(src_addr), &mavlink_frame, UDP_DRIVER);
-                    log_debug_receive_packet(UDP_DRIVER, &mavlink_frame, None, None);
-                    if mavlink_frame.msg.message_id() == 30
-                        || mavlink_frame.msg.message_id() == 141
-                        || mavlink_frame.msg.message_id() == 74
-                        || mavlink_frame.msg.message_id() == 410
-                        || mavlink_frame.msg.message_id() == 109 // RADIO_STATUS
-                        || mavlink_frame.msg.message_name() == "RADIO_STATUS"
-                        || mavlink_frame.msg.message_name() == "MISSION_CURRENT"
-                    {
-                        // info!("Message ignored");
-                        None
-                    } else {
-                        Some(mavlink_frame)
-                    }
-                } else {
-                    // info!("Message corrupted");
-                    None
-                }
-            }
-            Err(_) => None, // Ignoring errors for proof of concept
-        }
+        let (size, src_addr) = socket_recv.recv_from(&mut buf).await?;
+        let received_data = Vec::from(&buf[..size]);
+        let mavlink_frame = deserialize_frame(&received_data[..]).ok_or_else(DriverError::invalid_frame)?;
+        // Only peers sending MAVLink are replied to
+        self.learn_peer(src_addr);
+        // log_packet_received(size, Some(src_addr), &mavlink_frame, UDP_DRIVER);
+        log_debug_receive_packet(UDP_DRIVER, &mavlink_frame, None, None);
+        Ok(Some(mavlink_frame))
     }
 }
@@ -1,71 +1,283 @@
-// use std::fmt::Display;
-// use std::sync::Arc;
-
-// use futures::stream::{SplitSink, SplitStream};
-// use futures::SinkExt;
-// use futures_util::stream::StreamExt;
-// use tokio::net::TcpStream;
-// use tokio_tungstenite::tungstenite::protocol::Message;
-// use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
-
-// use crate::driver::Driver; // Make sure this path matches your project structure
-// use crate::utils::types::MavFramePacket; // Assuming MavFramePacket is the data structure you want to send/receive
-
-// pub const WEBSOCKET_DRIVER: &str = "websocket_driver";
-
-// impl Display for WebSocketDriver {
-//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//         write!(f, "{}", WEBSOCKET_DRIVER)
-//     }
-// }
-
-// pub struct WebSocketDriver {
-//     write_half: Arc<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>,
-//     read_half: Arc<SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>>,
-// }
-
-// impl WebSocketDriver {
-//     async fn new(url: &str) -> Self {
-//         let (ws_stream, _) = connect_async(url).await.expect("Failed to connect to WebSocket");
-
-//         let (write_half, read_half) = ws_stream.split();
-
-//         WebSocketDriver {
-//             write_half: Arc::new(write_half),
-//             read_half: Arc::new(read_half),
-//         }
-//     }
-// }
-
-// #[async_trait::async_trait]
-// impl Driver<String> for WebSocketDriver {
-//     async fn send(&self, packet: &String) {
-//         // Assuming packet can be converted to a suitable format for WebSocket,
-//         // such as JSON, binary, etc. This part depends on your implementation.
-//         let message = Message::text(packet.to_string());
-//         self.write_half
-//             .clone()
-//             .send(message)
-//             .await
-//             .map_err(|e| eprintln!("WebSocket send error: {}", e));
-//     }
-
-//     async fn receive(&self) -> Option<String> {
-//         let mut lock = self.read_half.clone();
-//         if let Some(message_result) = lock.next().await {
-//             match message_result {
-//                 Ok(message) => match message {
-//                     Message::Text(text) => Some(text),
-//                     // Handle other message types (binary, ping, close, etc.) as needed
-//                     _ => None,
-//                 },
-//                 Err(e) => {
-//                     eprintln!("WebSocket receive error: {}", e);
-//                     None
-//                 }
-//             }
-//         } else {
-//             None
-//         }
-//     }
-// }
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use mavlink::ardupilotmega::MavMessage;
+use mavlink::{MavHeader, MavlinkVersion};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{accept_async, connect_async, WebSocketStream};
+
+use super::{Driver, DriverError};
+use crate::config::WebSocketSection;
+use crate::define_struct_with_defaults;
+use crate::mavlink_utils::{serialize_frame, MavlinkStreamParser};
+use crate::utils::logging_utils::{
+    log_debug_receive_packet, log_debug_send_packet, log_driver_creation, log_packet_receive_error,
+    log_websocket_connected, log_websocket_disconnected,
+};
+use crate::utils::types::{MavFramePacket, WebSocketEncoding, WebSocketRole};
+
+pub const WEBSOCKET_DRIVER: &str = "websocket_driver";
+
+// Frames received from all peers, waiting for `receive`
+const INCOMING_CHANNEL_SIZE: usize = 100;
+// Messages waiting for a slow peer, newer ones are dropped for that peer once it is full
+const PEER_CHANNEL_SIZE: usize = 100;
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Frame in the JSON form `MavFrame` serializes to, mavlink derives no `Deserialize` for it
+#[derive(Deserialize)]
+struct JsonFrame {
+    header: MavHeader,
+    msg: MavMessage,
+    protocol_version: JsonVersion,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum JsonVersion {
+    V1,
+    V2,
+}
+
+impl From<JsonFrame> for MavFramePacket {
+    fn from(frame: JsonFrame) -> Self {
+        MavFramePacket {
+            header: frame.header,
+            msg: frame.msg,
+            protocol_version: match frame.protocol_version {
+                JsonVersion::V1 => MavlinkVersion::V1,
+                JsonVersion::V2 => MavlinkVersion::V2,
+            },
+        }
+    }
+}
+
+define_struct_with_defaults! {
+    WebSocketOptionalInitConfig, WebSocketInitConfig {
+        role: WebSocketRole = WebSocketRole::Server,
+        url: String = "ws://127.0.0.1:8765".to_string(),
+        addr: String = "0.0.0.0:8765".to_string(),
+        encoding: WebSocketEncoding = WebSocketEncoding::Binary,
+        // Delay before the first reconnection attempt of a client, doubled after each failure up to the maximum
+        initial_backoff_ms: u64 = 500,
+        max_backoff_ms: u64 = 10_000,
+    }
+}
+
+impl From<&WebSocketSection> for WebSocketOptionalInitConfig {
+    fn from(section: &WebSocketSection) -> Self {
+        Self {
+            role: section.role,
+            url: section.url.clone(),
+            addr: section.addr.clone(),
+            encoding: section.encoding,
+            initial_backoff_ms: section.initial_backoff_ms,
+            max_backoff_ms: section.max_backoff_ms,
+        }
+    }
+}
+
+type Peers = Arc<Mutex<HashMap<u64, mpsc::Sender<Message>>>>;
+
+/// Driver exchanging MAVLink over WebSocket, so browser dashboards can reach the network without a bridge.
+///
+/// As a server every frame sent goes to all connected peers, as a client to the one server.
+/// Frames from all peers come out of `receive` in arrival order.
+pub struct WebSocketDriver {
+    pub local_addr: Option<std::net::SocketAddr>,
+    encoding: WebSocketEncoding,
+    peers: Peers,
+    incoming: tokio::sync::Mutex<mpsc::Receiver<MavFramePacket>>,
+}
+
+impl Display for WebSocketDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", WEBSOCKET_DRIVER)
+    }
+}
+
+impl WebSocketDriver {
+    pub async fn new(init_config: Option<WebSocketOptionalInitConfig>) -> Self {
+        let init_config = init_config.unwrap_or_default().build();
+
+        let peers: Peers = Arc::new(Mutex::new(HashMap::new()));
+        let (incoming_tx, incoming_rx) = mpsc::channel(INCOMING_CHANNEL_SIZE);
+        let local_addr = match init_config.role {
+            WebSocketRole::Server => {
+                let listener = TcpListener::bind(&init_config.addr)
+                    .await
+                    .expect("Failed to bind WebSocket listener");
+                let local_addr = listener.local_addr().ok();
+                tokio::spawn(accept_peers(listener, peers.clone(), incoming_tx));
+                local_addr
+            }
+            WebSocketRole::Client => {
+                tokio::spawn(connect_to_server(
+                    init_config.url.clone(),
+                    Duration::from_millis(init_config.initial_backoff_ms),
+                    Duration::from_millis(init_config.max_backoff_ms),
+                    peers.clone(),
+                    incoming_tx,
+                ));
+                None
+            }
+        };
+
+        log_driver_creation(WEBSOCKET_DRIVER);
+
+        Self {
+            local_addr,
+            encoding: init_config.encoding,
+            peers,
+            incoming: tokio::sync::Mutex::new(incoming_rx),
+        }
+    }
+}
+
+async fn accept_peers(listener: TcpListener, peers: Peers, incoming_tx: mpsc::Sender<MavFramePacket>) {
+    let mut next_peer_id: u64 = 0;
+    loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                log_websocket_disconnected(WEBSOCKET_DRIVER, "accept", &err.to_string());
+                sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let peers = peers.clone();
+        let incoming_tx = incoming_tx.clone();
+        let peer_id = next_peer_id;
+        next_peer_id += 1;
+        // The handshake runs in its own task so a stalled peer does not hold up the others
+        tokio::spawn(async move {
+            let peer = peer_addr.to_string();
+            match accept_async(stream).await {
+                Ok(websocket) => {
+                    let reason = run_peer(websocket, peer_id, &peer, &peers, &incoming_tx).await;
+                    log_websocket_disconnected(WEBSOCKET_DRIVER, &peer, &reason);
+                }
+                Err(err) => log_websocket_disconnected(WEBSOCKET_DRIVER, &peer, &err.to_string()),
+            }
+        });
+    }
+}
+
+async fn connect_to_server(
+    url: String,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    peers: Peers,
+    incoming_tx: mpsc::Sender<MavFramePacket>,
+) {
+    let mut backoff = initial_backoff;
+    loop {
+        match connect_async(url.as_str()).await {
+            Ok((websocket, _)) => {
+                backoff = initial_backoff;
+                let reason = run_peer(websocket, 0, &url, &peers, &incoming_tx).await;
+                log_websocket_disconnected(WEBSOCKET_DRIVER, &url, &reason);
+            }
+            Err(err) => {
+                log_websocket_disconnected(WEBSOCKET_DRIVER, &url, &err.to_string());
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
+            }
+        }
+        if incoming_tx.is_closed() {
+            // The driver was dropped
+            return;
+        }
+    }
+}
+
+/// Exchanges messages with one connected peer until the connection ends, returns why it ended
+async fn run_peer<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+    websocket: WebSocketStream<S>,
+    peer_id: u64,
+    peer: &str,
+    peers: &Peers,
+    incoming_tx: &mpsc::Sender<MavFramePacket>,
+) -> String {
+    let (mut sink, mut stream) = websocket.split();
+    let (outgoing_tx, mut outgoing_rx) = mpsc::channel::<Message>(PEER_CHANNEL_SIZE);
+    peers.lock().unwrap().insert(peer_id, outgoing_tx);
+    log_websocket_connected(WEBSOCKET_DRIVER, peer);
+
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outgoing_rx.recv().await {
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut parser = MavlinkStreamParser::new();
+    let reason = 'read: loop {
+        let message = match stream.next().await {
+            Some(Ok(message)) => message,
+            Some(Err(err)) => break err.to_string(),
+            None => break "closed by peer".to_string(),
+        };
+        let frames = match message {
+            Message::Binary(data) => {
+                parser.push(&data);
+                std::iter::from_fn(|| parser.next_frame()).collect()
+            }
+            Message::Text(text) => match serde_json::from_str::<JsonFrame>(&text) {
+                Ok(frame) => vec![frame.into()],
+                Err(err) => {
+                    log_packet_receive_error(WEBSOCKET_DRIVER, &err.to_string());
+                    Vec::new()
+                }
+            },
+            Message::Close(_) => break "closed by peer".to_string(),
+            // Pings are answered by tungstenite itself
+            _ => Vec::new(),
+        };
+        for frame in frames {
+            log_debug_receive_packet(WEBSOCKET_DRIVER, &frame, None, None);
+            if incoming_tx.send(frame).await.is_err() {
+                break 'read "driver dropped".to_string();
+            }
+        }
+    };
+
+    peers.lock().unwrap().remove(&peer_id);
+    writer.abort();
+    reason
+}
+
+#[async_trait::async_trait]
+impl Driver<MavFramePacket> for WebSocketDriver {
+    async fn send(&self, packet: &MavFramePacket) -> Result<(), DriverError> {
+        let message = match self.encoding {
+            WebSocketEncoding::Binary => Message::Binary(serialize_frame(packet.clone())),
+            WebSocketEncoding::Json => Message::Text(serde_json::to_string(packet).map_err(std::io::Error::from)?),
+        };
+        let peers = self.peers.lock().unwrap();
+        for outgoing_tx in peers.values() {
+            // A peer too slow to keep up misses frames instead of holding back the others
+            let _ = outgoing_tx.try_send(message.clone());
+        }
+        if !peers.is_empty() {
+            log_debug_send_packet(WEBSOCKET_DRIVER, packet);
+        }
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Option<MavFramePacket>, DriverError> {
+        match self.incoming.lock().await.recv().await {
+            Some(mavlink_frame) => Ok(Some(mavlink_frame)),
+            None => Err(DriverError::not_connected("WebSocket tasks stopped")),
+        }
+    }
+}
@@ -1,5 +1,7 @@
 pub mod driver;
 pub mod network;
+#[cfg(feature = "embedded")]
+pub mod node;
 pub mod utils;
 
 pub use driver::*;
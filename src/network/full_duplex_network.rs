@@ -5,9 +5,11 @@ use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::task::JoinHandle;
 use tracing::error;
 
-use super::NetworkInterface;
+use super::recovery::{recover_from_receive_error, recover_from_transmit_error, ErrorBackoff};
+use super::{NetworkInterface, CHANNEL_FULL_DROPPED_METRIC};
 use crate::driver::Driver;
 use crate::utils::logging_utils::log_debug_send_to_main;
+use crate::utils::metrics::metrics;
 
 pub struct FullDuplexNetwork<P> {
     driver: Arc<dyn Driver<P>>,
@@ -45,17 +47,32 @@ impl<P: Send + 'static> NetworkInterface<P> for FullDuplexNetwork<P> {
         let receive_driver = self.driver.clone();
 
         let recv_task = spawn(async move {
+            let mut backoff = ErrorBackoff::default();
             loop {
-                if let Some(mavlink_frame) = receive_driver.receive().await {
-                    match send_channel.try_send(mavlink_frame) {
-                        Err(mpsc::error::TrySendError::Full(_)) => {
-                            error!("Send channel is full, dropping packet.");
-                        }
-                        Ok(_) => {
-                            log_debug_send_to_main(&receive_driver.to_string());
-                        }
-                        _ => {}
+                let mavlink_frame = match receive_driver.receive().await {
+                    Ok(mavlink_frame) => {
+                        backoff.reset();
+                        mavlink_frame
+                    }
+                    Err(err) => {
+                        recover_from_receive_error(&receive_driver.to_string(), &err, &mut backoff).await;
+                        continue;
+                    }
+                };
+                // Nothing to hand out yet, such as part of a fragmented frame
+                let Some(mavlink_frame) = mavlink_frame else {
+                    continue;
+                };
+                match send_channel.try_send(mavlink_frame) {
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        metrics()
+                            .increment_counter(CHANNEL_FULL_DROPPED_METRIC, &[("driver", &receive_driver.to_string())]);
+                        error!("Send channel is full, dropping packet.");
+                    }
+                    Ok(_) => {
+                        log_debug_send_to_main(&receive_driver.to_string());
                     }
+                    _ => {}
                 }
             }
         });
@@ -64,8 +81,24 @@ impl<P: Send + 'static> NetworkInterface<P> for FullDuplexNetwork<P> {
         let sending_driver = self.driver.clone();
         let mut recv_channel = self.recv_channel;
         let send_task = spawn(async move {
+            let mut backoff = ErrorBackoff::default();
             while let Some(packet) = recv_channel.recv().await {
-                sending_driver.send(&packet).await;
+                let mut attempt = 1;
+                loop {
+                    match sending_driver.send(&packet).await {
+                        Ok(()) => {
+                            backoff.reset();
+                            break;
+                        }
+                        Err(err) => {
+                            let driver = sending_driver.to_string();
+                            if !recover_from_transmit_error(&driver, &err, attempt, &mut backoff).await {
+                                break;
+                            }
+                            attempt += 1;
+                        }
+                    }
+                }
             }
         });
 
@@ -1,26 +1,250 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use futures::FutureExt;
 use tokio::spawn;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::task::JoinHandle;
-use tokio::time::timeout;
+use tokio::time::{sleep_until, timeout, Instant};
 use tracing::error;
 
-use super::NetworkInterface;
-use crate::driver::Driver;
-use crate::utils::logging_utils::log_debug_send_to_main;
+use super::recovery::{recover_from_receive_error, recover_from_transmit_error, ErrorBackoff};
+use super::scheduler::{FifoScheduler, PacketScheduler, SCHEDULER_QUEUE_LENGTH_METRIC};
+use super::{NetworkInterface, QueueLength, CHANNEL_FULL_DROPPED_METRIC};
+use crate::driver::{Driver, DriverError};
+use crate::utils::logging_utils::{log_debug_radio_state, log_debug_send_to_main};
+use crate::utils::metrics::metrics;
 
 const CONTINOUS_TRANSMISSION_PACKET_LIMIT: u8 = 5;
 const CONTINOUS_TRANSMISSION_WAIT_MS: u64 = 2;
 
+/// States of the radio driven by a half-duplex network interface.
+///
+/// Waits are only raced against each other in `Rx`, and the driver guarantees they are
+/// cancellation safe. Every operation reconfiguring the radio or reading from it runs to
+/// completion, so a transmission never interrupts one halfway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RadioState {
+    /// Not set up for either direction, at start, after a transmission or after a failure
+    Idle,
+    /// Listening for packets from the air while waiting for packets to transmit
+    Rx,
+    /// Collecting the packets the scheduler allows into a burst, a reception the radio completed
+    /// meanwhile is handed out before transmitting. The burst may stay empty when only the link
+    /// layers of the driver have payloads to transmit.
+    TxPending,
+    /// Transmitting the collected burst
+    Tx,
+    /// The transmission ended, successfully or not
+    TxDone,
+}
+
 pub struct HalfDuplexNetwork<P> {
     driver: Arc<dyn Driver<P> + Send + Sync>,
     send_channel: Sender<P>,
     recv_channel: Receiver<P>,
+    scheduler: Box<dyn PacketScheduler<P>>,
+    queue_length: QueueLength,
+    backoff: ErrorBackoff,
+    // Kept across states so no packet is lost between collecting and transmitting it
+    burst: Vec<P>,
 }
 
-impl<P: Send + 'static> NetworkInterface<P> for HalfDuplexNetwork<P> {
+impl<P: Send + Sync + 'static> HalfDuplexNetwork<P> {
+    /// Replaces the default FIFO ordering of packets waiting for the link
+    pub fn with_scheduler(mut self, scheduler: impl PacketScheduler<P> + 'static) -> Self {
+        self.scheduler = Box::new(scheduler);
+        let driver = self.driver.clone();
+        self.scheduler
+            .set_time_on_air(Arc::new(move |packet: &P| driver.time_on_air(packet)));
+        self
+    }
+
+    /// Runs the work of a state and returns the state to move to
+    async fn step(&mut self, state: RadioState) -> RadioState {
+        match state {
+            RadioState::Idle => match self.driver.prepare_to_receive().await {
+                Ok(()) => RadioState::Rx,
+                Err(err) => {
+                    recover_from_receive_error(&self.driver.to_string(), &err, &mut self.backoff).await;
+                    RadioState::Idle
+                }
+            },
+            RadioState::Rx => {
+                if let Some(modulation) = self.driver.modulation() {
+                    self.scheduler.update_modulation(modulation);
+                }
+                let next_ready = self.scheduler.next_ready();
+                let link_due = self
+                    .driver
+                    .next_link_transmit()
+                    .map(|due| self.scheduler.next_transmit_opportunity(due));
+                tokio::select! {
+                    // Transmit packets received through channel
+                    Some(packet) = self.recv_channel.recv() => {
+                        self.scheduler.push(packet);
+                        RadioState::TxPending
+                    }
+                    // Transmit packets the scheduler held back
+                    _ = sleep_until(next_ready.unwrap_or_else(Instant::now)), if next_ready.is_some() => {
+                        RadioState::TxPending
+                    }
+                    // Transmit what the link layers of the driver send on their own, such as acknowledgements
+                    _ = sleep_until(link_due.unwrap_or_else(Instant::now)), if link_due.is_some() => {
+                        RadioState::TxPending
+                    }
+                    // Receive packets from LoRa
+                    ready = self.driver.ready_to_receive() => self.handle_reception(ready).await,
+                }
+            }
+            RadioState::TxPending => {
+                self.collect_burst().await;
+                if self.burst.is_empty() && !self.link_transmit_due() {
+                    // The scheduler held every packet back, the radio is still listening
+                    return RadioState::Rx;
+                }
+                // The radio drops a received packet it did not hand out yet when it switches to TX,
+                // so it is checked right before
+                if let Some(ready) = self.driver.ready_to_receive().now_or_never() {
+                    self.handle_reception(ready).await;
+                }
+                RadioState::Tx
+            }
+            RadioState::Tx => {
+                self.transmit_burst().await;
+                RadioState::TxDone
+            }
+            RadioState::TxDone => RadioState::Idle,
+        }
+    }
+
+    /// Receives the packet the driver signaled and returns the state to move to
+    async fn handle_reception(&mut self, ready: Result<(), DriverError>) -> RadioState {
+        let received = match ready {
+            Ok(()) => self.driver.receive().await,
+            Err(err) => Err(err),
+        };
+        match received {
+            Ok(mavlink_frame) => {
+                self.backoff.reset();
+                // None while the packet is incomplete, such as part of a fragmented frame
+                if let Some(mavlink_frame) = mavlink_frame {
+                    if !self.scheduler.observe_received(&mavlink_frame) {
+                        self.forward(mavlink_frame);
+                    }
+                }
+                RadioState::Rx
+            }
+            Err(err) => {
+                recover_from_receive_error(&self.driver.to_string(), &err, &mut self.backoff).await;
+                // Receive mode is set up again, which also lets the driver recover the radio
+                RadioState::Idle
+            }
+        }
+    }
+
+    /// Collects the next packets the scheduler allows into the burst, together with the packets
+    /// arriving shortly after each other
+    async fn collect_burst(&mut self) {
+        while let Ok(packet) = self.recv_channel.try_recv() {
+            self.scheduler.push(packet);
+        }
+        let Some(packet) = self.scheduler.pop() else {
+            self.update_queue_length();
+            return;
+        };
+        self.burst.push(packet);
+
+        // Collects the packets following closely so the driver can pack them into fewer transmissions
+        let mut continous_transmission_packet_count: u8 = 0;
+        while continous_transmission_packet_count < CONTINOUS_TRANSMISSION_PACKET_LIMIT {
+            while let Ok(packet) = self.recv_channel.try_recv() {
+                self.scheduler.push(packet);
+            }
+            if self
+                .scheduler
+                .next_ready()
+                .map_or(true, |ready_at| ready_at > Instant::now())
+            {
+                match timeout(
+                    Duration::from_millis(CONTINOUS_TRANSMISSION_WAIT_MS),
+                    self.recv_channel.recv(),
+                )
+                .await
+                {
+                    Ok(Some(packet)) => self.scheduler.push(packet),
+                    _ => break,
+                }
+            }
+            let Some(packet) = self.scheduler.pop() else {
+                break;
+            };
+            self.burst.push(packet);
+            continous_transmission_packet_count += 1;
+        }
+        self.update_queue_length();
+    }
+
+    /// Published here for every scheduler, RADIO_STATUS reports the backlog of the link from it
+    fn update_queue_length(&self) {
+        let queue_length = self.scheduler.queue_length();
+        self.queue_length.set(queue_length);
+        metrics().set_gauge(
+            SCHEDULER_QUEUE_LENGTH_METRIC,
+            &[("link", &self.driver.to_string())],
+            queue_length as f64,
+        );
+    }
+
+    /// Whether the link layers of the driver have payloads of their own to transmit now
+    fn link_transmit_due(&self) -> bool {
+        self.driver
+            .next_link_transmit()
+            .is_some_and(|due| self.scheduler.next_transmit_opportunity(due) <= Instant::now())
+    }
+
+    /// Sends the collected burst as one batch, together with the payloads the link layers transmit on their own
+    async fn transmit_burst(&mut self) {
+        let burst = std::mem::take(&mut self.burst);
+        let mut attempt = 1;
+        loop {
+            // The radio is prepared again before each attempt, in case the failure left it in another mode
+            let result = match self.driver.prepare_to_send().await {
+                Ok(()) => self.driver.send_batch(&burst).await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) => {
+                    self.backoff.reset();
+                    return;
+                }
+                Err(err) => {
+                    let driver = self.driver.to_string();
+                    if !recover_from_transmit_error(&driver, &err, attempt, &mut self.backoff).await {
+                        return;
+                    }
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Hands a received packet to the main task
+    fn forward(&self, mavlink_frame: P) {
+        match self.send_channel.try_send(mavlink_frame) {
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                metrics().increment_counter(CHANNEL_FULL_DROPPED_METRIC, &[("driver", &self.driver.to_string())]);
+                error!("Send channel is full, dropping packet.");
+            }
+            Ok(_) => {
+                log_debug_send_to_main(&self.driver.to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<P: Send + Sync + 'static> NetworkInterface<P> for HalfDuplexNetwork<P> {
     fn new(driver: Arc<dyn Driver<P> + Send + Sync>, buffer_size: usize) -> (Self, Sender<P>, Receiver<P>) {
         let (tx_send, rx_send) = mpsc::channel(buffer_size);
         let (tx_recv, rx_recv) = mpsc::channel(buffer_size);
@@ -30,6 +254,10 @@ impl<P: Send + 'static> NetworkInterface<P> for HalfDuplexNetwork<P> {
                 driver,
                 send_channel: tx_send,
                 recv_channel: rx_recv,
+                scheduler: Box::<FifoScheduler<P>>::default(),
+                queue_length: QueueLength::default(),
+                backoff: ErrorBackoff::default(),
+                burst: Vec::new(),
             },
             tx_recv,
             rx_send,
@@ -41,43 +269,27 @@ impl<P: Send + 'static> NetworkInterface<P> for HalfDuplexNetwork<P> {
             driver,
             send_channel: tx_send,
             recv_channel: rx_recv,
+            scheduler: Box::<FifoScheduler<P>>::default(),
+            queue_length: QueueLength::default(),
+            backoff: ErrorBackoff::default(),
+            burst: Vec::new(),
         }
     }
 
+    fn queue_length(&self) -> QueueLength {
+        self.queue_length.clone()
+    }
+
     async fn run(mut self) -> Vec<JoinHandle<()>> {
         let task = spawn(async move {
+            let driver = self.driver.to_string();
+            let mut state = RadioState::Idle;
             loop {
-                self.driver.prepare_to_receive().await.unwrap();
-                tokio::select! {
-                    // Transmit packets received through channel
-                    Some(packet) = self.recv_channel.recv() => {
-                        self.driver.prepare_to_send().await.unwrap();
-                        self.driver.send(&packet).await;
-                        let mut continous_transmission_packet_count: u8 = 0;
-                        while let Ok(Some(packet)) = timeout(Duration::from_millis(CONTINOUS_TRANSMISSION_WAIT_MS), self.recv_channel.recv()).await
-                        {
-                            if continous_transmission_packet_count >= CONTINOUS_TRANSMISSION_PACKET_LIMIT {
-                                break;
-                            }
-                            self.driver.send(&packet).await;
-                            continous_transmission_packet_count += 1;
-                        }
-                    }
-                    // Receive packets from LoRa
-                    Ok(_) = self.driver.ready_to_receive() => {
-                        if let Some(mavlink_frame) = self.driver.receive().await {
-                            match self.send_channel.try_send(mavlink_frame) {
-                                Err(mpsc::error::TrySendError::Full(_)) => {
-                                    error!("Send channel is full, dropping packet.");
-                                }
-                                Ok(_) => {
-                                    log_debug_send_to_main(&self.driver.to_string());
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
+                let next_state = self.step(state).await;
+                if next_state != state {
+                    log_debug_radio_state(&driver, state, next_state);
                 }
+                state = next_state;
             }
         });
 
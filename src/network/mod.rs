@@ -1,12 +1,37 @@
 pub mod full_duplex_network;
 pub mod half_duplex_network;
+pub mod recovery;
+pub mod router;
+pub mod scheduler;
+pub mod tdma;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::task::JoinHandle;
 
 use crate::driver::Driver;
+
+/// Received packets dropped because the channel to the main task was full
+pub const CHANNEL_FULL_DROPPED_METRIC: &str = "channel_full_dropped_total";
+
+/// Number of packets a network interface holds back for transmission on top of its send channel,
+/// such as the ones waiting in its scheduler. Clones share the count, which stays readable while
+/// the interface runs.
+#[derive(Debug, Clone, Default)]
+pub struct QueueLength(Arc<AtomicUsize>);
+
+impl QueueLength {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, length: usize) {
+        self.0.store(length, Ordering::Relaxed);
+    }
+}
+
 #[allow(async_fn_in_trait)]
 pub trait NetworkInterface<P> {
     /// Creates a new instance of the network interface along with channels for sending and receiving packets of type `P`.
@@ -21,4 +46,9 @@ pub trait NetworkInterface<P> {
 
     /// Starts the network interface's asynchronous operation, returning a handle on 1 or 2 tasks depending on whether it is half or full duplex, for sending and receiving packets of type `P`.
     async fn run(self) -> Vec<JoinHandle<()>>;
+
+    /// Packets held back for transmission, always none for interfaces sending straight from the channel
+    fn queue_length(&self) -> QueueLength {
+        QueueLength::default()
+    }
 }
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use tokio::time::sleep;
+
+use crate::driver::DriverError;
+use crate::utils::logging_utils::{
+    log_debug_received_data_dropped, log_driver_backoff, log_packet_receive_error, log_transmit_error,
+    log_transmit_retry,
+};
+
+const INITIAL_ERROR_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ERROR_BACKOFF: Duration = Duration::from_secs(2);
+// Attempts at transmitting a batch when the error may clear right away, such as a radio glitch
+pub const TRANSMIT_ATTEMPTS: u32 = 2;
+
+/// Pause of a network interface after driver errors, doubled after each error until one operation succeeds
+pub struct ErrorBackoff {
+    delay: Duration,
+}
+
+impl Default for ErrorBackoff {
+    fn default() -> Self {
+        Self {
+            delay: INITIAL_ERROR_BACKOFF,
+        }
+    }
+}
+
+impl ErrorBackoff {
+    pub async fn wait(&mut self, driver: &str) {
+        log_driver_backoff(driver, self.delay);
+        sleep(self.delay).await;
+        self.delay = (self.delay * 2).min(MAX_ERROR_BACKOFF);
+    }
+
+    pub fn reset(&mut self) {
+        self.delay = INITIAL_ERROR_BACKOFF;
+    }
+}
+
+/// Logs a failed receive and pauses when the driver needs time to recover
+pub async fn recover_from_receive_error(driver: &str, err: &DriverError, backoff: &mut ErrorBackoff) {
+    match err {
+        DriverError::Timeout => return,
+        DriverError::Decode(_) => log_debug_received_data_dropped(driver, &err.to_string()),
+        _ => log_packet_receive_error(driver, &err.to_string()),
+    }
+    if err.needs_backoff() {
+        backoff.wait(driver).await;
+    }
+}
+
+/// Logs a failed transmission and tells whether to attempt it again, pausing first when the
+/// driver needs time to recover
+pub async fn recover_from_transmit_error(
+    driver: &str,
+    err: &DriverError,
+    attempt: u32,
+    backoff: &mut ErrorBackoff,
+) -> bool {
+    let retry = err.is_retryable() && attempt < TRANSMIT_ATTEMPTS;
+    if retry {
+        log_transmit_retry(driver, &err.to_string(), attempt);
+    } else {
+        log_transmit_error(driver, &err.to_string());
+    }
+    if err.needs_backoff() {
+        backoff.wait(driver).await;
+    }
+    retry
+}
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mavlink::MavHeader;
+use tokio::spawn;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+use crate::utils::filter::{FilterChain, PacketFilter};
+use crate::utils::logging_utils::log_debug_send_to_network;
+use crate::utils::mavlink_utils::message_target;
+use crate::utils::types::{Direction, MavFramePacket};
+
+/// Learned routes are forgotten when their system stays silent for this long
+const ROUTE_EXPIRY: Duration = Duration::from_secs(30);
+
+/// Learns on which link each `(system_id, component_id)` was last seen, like mavlink-router and ArduPilot do
+#[derive(Default)]
+pub struct RoutingTable {
+    routes: HashMap<(u8, u8), (usize, Instant)>,
+}
+
+impl RoutingTable {
+    pub fn learn(&mut self, header: &MavHeader, link: usize) {
+        let key = (header.system_id, header.component_id);
+        let previous = self.routes.insert(key, (link, Instant::now()));
+        if previous.map_or(true, |(previous_link, _)| previous_link != link) {
+            debug!(
+                target: "network",
+                system_id = header.system_id,
+                component_id = header.component_id,
+                link,
+                "Learned route"
+            );
+        }
+    }
+
+    /// Links on which the target was seen, `None` when the target is unknown.
+    /// A target component of 0 addresses every component of the target system.
+    pub fn links_for(&self, target_system: u8, target_component: u8) -> Option<HashSet<usize>> {
+        let now = Instant::now();
+        let links: HashSet<usize> = self
+            .routes
+            .iter()
+            .filter(|((system_id, component_id), (_, last_seen))| {
+                *system_id == target_system
+                    && (target_component == 0 || *component_id == target_component)
+                    && now.duration_since(*last_seen) < ROUTE_EXPIRY
+            })
+            .map(|(_, (link, _))| *link)
+            .collect();
+
+        if links.is_empty() {
+            None
+        } else {
+            Some(links)
+        }
+    }
+}
+
+struct RouterLink {
+    name: String,
+    to_network: Sender<MavFramePacket>,
+    from_network: Receiver<MavFramePacket>,
+    filter: Arc<FilterChain<MavFramePacket>>,
+}
+
+struct RouterOutput {
+    name: String,
+    to_network: Sender<MavFramePacket>,
+    filter: Arc<FilterChain<MavFramePacket>>,
+}
+
+/// Connects any number of network interfaces.
+///
+/// Broadcasts and messages for unknown targets go out every link except the one they came from,
+/// targeted messages only go out the links where their target was seen.
+#[derive(Default)]
+pub struct Router {
+    links: Vec<RouterLink>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a link from the channels returned by `NetworkInterface::new`
+    pub fn add_link(&mut self, name: &str, to_network: Sender<MavFramePacket>, from_network: Receiver<MavFramePacket>) {
+        self.add_link_with_filter(name, to_network, from_network, FilterChain::new(name));
+    }
+
+    /// Adds a link whose filter sees packets coming from its network as incoming and packets routed to it as outgoing
+    pub fn add_link_with_filter(
+        &mut self,
+        name: &str,
+        to_network: Sender<MavFramePacket>,
+        from_network: Receiver<MavFramePacket>,
+        filter: FilterChain<MavFramePacket>,
+    ) {
+        self.links.push(RouterLink {
+            name: name.to_string(),
+            to_network,
+            from_network,
+            filter: Arc::new(filter),
+        });
+    }
+
+    /// Starts one forwarding task per link
+    pub async fn run(self) -> Vec<JoinHandle<()>> {
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        for link in self.links {
+            inputs.push((link.from_network, link.filter.clone()));
+            outputs.push(RouterOutput {
+                name: link.name,
+                to_network: link.to_network,
+                filter: link.filter,
+            });
+        }
+        let outputs = Arc::new(outputs);
+        let routing_table = Arc::new(Mutex::new(RoutingTable::default()));
+
+        inputs
+            .into_iter()
+            .enumerate()
+            .map(|(source_index, (mut from_network, filter))| {
+                let outputs = outputs.clone();
+                let routing_table = routing_table.clone();
+                spawn(async move {
+                    while let Some(packet) = from_network.recv().await {
+                        if !filter.accept(&packet, Direction::Incoming) {
+                            continue;
+                        }
+                        routing_table.lock().unwrap().learn(&packet.header, source_index);
+                        forward(&outputs, &routing_table, source_index, &packet);
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+fn forward(
+    outputs: &[RouterOutput],
+    routing_table: &Mutex<RoutingTable>,
+    source_index: usize,
+    packet: &MavFramePacket,
+) {
+    let destinations = match message_target(&packet.msg) {
+        Some((target_system, target_component)) if target_system != 0 => {
+            routing_table.lock().unwrap().links_for(target_system, target_component)
+        }
+        _ => None,
+    };
+
+    for (index, output) in outputs.iter().enumerate() {
+        if index == source_index
+            || destinations
+                .as_ref()
+                .is_some_and(|destinations| !destinations.contains(&index))
+            || !output.filter.accept(packet, Direction::Outgoing)
+        {
+            continue;
+        }
+        match output.to_network.try_send(packet.clone()) {
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                error!("Channel to {} is full, dropping packet.", output.name);
+            }
+            Ok(_) => {
+                log_debug_send_to_network(&output.name);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use mavlink::ardupilotmega::MavMessage;
+use mavlink::Message;
+use tokio::time::Instant;
+
+use crate::utils::config::SchedulerSection;
+use crate::utils::logging_utils::log_debug_scheduler_drop;
+use crate::utils::lora_airtime::LoRaModulation;
+use crate::utils::metrics::metrics;
+use crate::utils::types::{MavFramePacket, Priority};
+
+pub const SCHEDULER_DROPPED_METRIC: &str = "scheduler_dropped_total";
+pub const SCHEDULER_QUEUE_LENGTH_METRIC: &str = "scheduler_queue_length";
+
+const DEFAULT_MAX_QUEUE_LENGTH: usize = 64;
+
+/// Time on air of a packet on a link once its link layers encoded it, `None` when the link does not know it
+pub type TimeOnAir<P> = Arc<dyn Fn(&P) -> Option<Duration> + Send + Sync>;
+
+/// Orders the packets waiting for a half duplex link
+pub trait PacketScheduler<P>: Send {
+    fn push(&mut self, packet: P);
+
+    /// Next packet allowed on the link now, if any
+    fn pop(&mut self) -> Option<P>;
+
+    /// When the next queued packet becomes sendable, `None` when the queue is empty
+    fn next_ready(&self) -> Option<Instant>;
+
+    /// Number of packets waiting in the queue
+    fn queue_length(&self) -> usize;
+
+    /// Sees each packet received on the link, for schedulers following the state of the link. Returns
+    /// whether the packet was meant for the scheduler alone, such as a time beacon, and is not forwarded.
+    fn observe_received(&mut self, _packet: &P) -> bool {
+        false
+    }
+
+    /// Sees the modulation the link currently transmits with, for schedulers timing packets by their airtime
+    fn update_modulation(&mut self, _modulation: LoRaModulation) {}
+
+    /// Sees how long packets take on air on the link, for schedulers timing packets by their airtime
+    fn set_time_on_air(&mut self, _time_on_air: TimeOnAir<P>) {}
+
+    /// Earliest time from `due` on at which the link may transmit payloads that bypass the queue, such as
+    /// acknowledgements
+    fn next_transmit_opportunity(&self, due: Instant) -> Instant {
+        due
+    }
+}
+
+/// Sends packets in arrival order, the behaviour of a link without a scheduler
+pub struct FifoScheduler<P> {
+    queue: VecDeque<P>,
+}
+
+impl<P> Default for FifoScheduler<P> {
+    fn default() -> Self {
+        Self { queue: VecDeque::new() }
+    }
+}
+
+impl<P: Send> PacketScheduler<P> for FifoScheduler<P> {
+    fn push(&mut self, packet: P) {
+        self.queue.push_back(packet);
+    }
+
+    fn pop(&mut self) -> Option<P> {
+        self.queue.pop_front()
+    }
+
+    fn next_ready(&self) -> Option<Instant> {
+        (!self.queue.is_empty()).then(Instant::now)
+    }
+
+    fn queue_length(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+const PRIORITY_CLASSES: usize = 3;
+
+/// Scheduling policy of a link, message types are referred to by their MAVLink name
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    pub max_queue_length: usize,
+    /// Highest rate at which each source may send a message type over the link
+    pub rate_limits_hz: HashMap<String, f64>,
+    /// Priority class of each message type, unlisted types are `Normal`
+    pub priorities: HashMap<String, Priority>,
+    /// Telemetry streams where a queued packet is replaced by a newer one of the same source
+    pub coalesce: HashSet<String>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self::from(&SchedulerSection::default())
+    }
+}
+
+impl From<&SchedulerSection> for SchedulerConfig {
+    fn from(section: &SchedulerSection) -> Self {
+        Self {
+            max_queue_length: section.max_queue_length.unwrap_or(DEFAULT_MAX_QUEUE_LENGTH),
+            rate_limits_hz: section.rate_limits_hz.clone(),
+            priorities: section.priorities.clone().unwrap_or_else(default_priorities),
+            coalesce: section.coalesce.clone(),
+        }
+    }
+}
+
+fn default_priorities() -> HashMap<String, Priority> {
+    [
+        "HEARTBEAT",
+        "COMMAND_LONG",
+        "COMMAND_INT",
+        "COMMAND_ACK",
+        "MISSION_COUNT",
+        "MISSION_ITEM_INT",
+        "MISSION_REQUEST_INT",
+        "MISSION_ACK",
+    ]
+    .into_iter()
+    .map(|name| (name.to_string(), Priority::High))
+    .collect()
+}
+
+/// `(system_id, component_id, message_id)` of a packet stream
+type StreamKey = (u8, u8, u32);
+
+struct QueuedPacket {
+    key: StreamKey,
+    packet: MavFramePacket,
+}
+
+/// Priority scheduler for MAVLink links with per message type rate caps and latest value coalescing.
+///
+/// Queued packets are sent highest priority class first and in arrival order within a class.
+/// A packet over its rate cap is dropped, unless its type is coalesced, in which case it waits in
+/// the queue and is replaced by newer packets of its stream until the cap allows it out.
+/// When the queue is full the oldest packet of the lowest priority class is dropped.
+pub struct MavlinkScheduler {
+    link: String,
+    max_queue_length: usize,
+    min_intervals: HashMap<u32, Duration>,
+    priorities: HashMap<u32, Priority>,
+    coalesce: HashSet<u32>,
+    queues: [VecDeque<QueuedPacket>; PRIORITY_CLASSES],
+    /// When each rate limited stream was last let onto the link, at queueing time for dropped
+    /// streams and at sending time for coalesced ones
+    last_admitted: HashMap<StreamKey, Instant>,
+}
+
+impl MavlinkScheduler {
+    pub fn new(link: &str, config: &SchedulerConfig) -> Self {
+        let message_id = |name: &String| MavMessage::message_id_from_name(name).ok();
+        Self {
+            link: link.to_string(),
+            max_queue_length: config.max_queue_length.max(1),
+            min_intervals: config
+                .rate_limits_hz
+                .iter()
+                .filter(|(_, rate)| **rate > 0.0)
+                .filter_map(|(name, rate)| Some((message_id(name)?, Duration::from_secs_f64(1.0 / rate))))
+                .collect(),
+            priorities: config
+                .priorities
+                .iter()
+                .filter_map(|(name, priority)| Some((message_id(name)?, *priority)))
+                .collect(),
+            coalesce: config.coalesce.iter().filter_map(message_id).collect(),
+            queues: Default::default(),
+            last_admitted: HashMap::new(),
+        }
+    }
+
+    fn priority(&self, message_id: u32) -> Priority {
+        self.priorities.get(&message_id).copied().unwrap_or_default()
+    }
+
+    fn is_rate_limited_coalesced(&self, message_id: u32) -> bool {
+        self.coalesce.contains(&message_id) && self.min_intervals.contains_key(&message_id)
+    }
+
+    /// Earliest time a coalesced stream may send again under its rate cap, other streams are
+    /// rate limited when they are queued and are always ready
+    fn ready_at(&self, key: &StreamKey) -> Option<Instant> {
+        if !self.coalesce.contains(&key.2) {
+            return None;
+        }
+        let interval = self.min_intervals.get(&key.2)?;
+        self.last_admitted
+            .get(key)
+            .map(|last_admitted| *last_admitted + *interval)
+    }
+
+    fn record_drop(&self, packet: &MavFramePacket, reason: &str) {
+        metrics().increment_counter(SCHEDULER_DROPPED_METRIC, &[("link", &self.link), ("reason", reason)]);
+        log_debug_scheduler_drop(&self.link, packet.msg.message_name(), reason);
+    }
+}
+
+impl PacketScheduler<MavFramePacket> for MavlinkScheduler {
+    fn push(&mut self, packet: MavFramePacket) {
+        let message_id = packet.msg.message_id();
+        let key = (packet.header.system_id, packet.header.component_id, message_id);
+        let class = self.priority(message_id) as usize;
+
+        if self.coalesce.contains(&message_id) {
+            if let Some(queued) = self.queues[class].iter_mut().find(|queued| queued.key == key) {
+                let replaced = std::mem::replace(&mut queued.packet, packet);
+                self.record_drop(&replaced, "coalesced");
+                return;
+            }
+        } else if let Some(interval) = self.min_intervals.get(&message_id) {
+            let now = Instant::now();
+            if self
+                .last_admitted
+                .get(&key)
+                .is_some_and(|last_admitted| now < *last_admitted + *interval)
+            {
+                self.record_drop(&packet, "rate_limited");
+                return;
+            }
+            self.last_admitted.insert(key, now);
+        }
+
+        self.queues[class].push_back(QueuedPacket { key, packet });
+
+        if self.queue_length() > self.max_queue_length {
+            if let Some(dropped) = self.queues.iter_mut().rev().find_map(VecDeque::pop_front) {
+                self.record_drop(&dropped.packet, "queue_full");
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<MavFramePacket> {
+        let now = Instant::now();
+        for class in 0..PRIORITY_CLASSES {
+            let position = self.queues[class]
+                .iter()
+                .position(|queued| self.ready_at(&queued.key).map_or(true, |ready_at| ready_at <= now));
+            if let Some(queued) = position.and_then(|position| self.queues[class].remove(position)) {
+                if self.is_rate_limited_coalesced(queued.key.2) {
+                    self.last_admitted.insert(queued.key, now);
+                }
+                return Some(queued.packet);
+            }
+        }
+        None
+    }
+
+    fn next_ready(&self) -> Option<Instant> {
+        let now = Instant::now();
+        self.queues
+            .iter()
+            .flatten()
+            .map(|queued| self.ready_at(&queued.key).map_or(now, |ready_at| ready_at.max(now)))
+            .min()
+    }
+
+    fn queue_length(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+}
@@ -0,0 +1,454 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use mavlink::ardupilotmega::{MavMessage, SYSTEM_TIME_DATA};
+use mavlink::{MavHeader, MavlinkVersion, Message};
+use tokio::time::Instant;
+
+use super::scheduler::{PacketScheduler, TimeOnAir, SCHEDULER_DROPPED_METRIC};
+use crate::define_struct_with_defaults;
+use crate::utils::config::TdmaSection;
+use crate::utils::logging_utils::{log_debug_scheduler_drop, log_debug_tdma_sync};
+use crate::utils::lora_airtime::{LoRaModulation, MAX_LORA_PAYLOAD_LENGTH};
+use crate::utils::mavlink_utils::{node_system_id, serialize_frame};
+use crate::utils::metrics::metrics;
+use crate::utils::transmit_guard::TransmitGuard;
+use crate::utils::types::{MavFramePacket, NodeType, TdmaTimeSource};
+
+pub const TDMA_DEFERRED_METRIC: &str = "tdma_deferred_total";
+pub const TDMA_BEACONS_SENT_METRIC: &str = "tdma_beacons_sent_total";
+pub const TDMA_CLOCK_CORRECTION_METRIC: &str = "tdma_clock_correction_seconds";
+pub const TDMA_OVERRUN_DROPPED_METRIC: &str = "tdma_overrun_dropped_total";
+
+/// Slot length when neither the config nor the modulation gives one
+const DEFAULT_SLOT_DURATION: Duration = Duration::from_secs(1);
+/// Component of the flight controller, whose SYSTEM_TIME carries GPS time once it has a fix
+const AUTOPILOT_COMPONENT_ID: u8 = 1;
+
+define_struct_with_defaults! {
+    TdmaOptionalConfig, TdmaConfig {
+        slot_count: u32 = 2,
+        // System IDs in slot order, the one of the node included, overrides the slot count when not empty
+        slot_table: Vec<u8> = Vec::new(),
+        // Defaults to the system ID of the node type
+        system_id: Option<u8> = None,
+        // Defaults to the time on air of a full payload plus the guard intervals
+        slot_duration_ms: Option<u64> = None,
+        // Added to the guard interval derived from the modulation, for clock jitter and radio setup
+        guard_margin_ms: u64 = 5,
+        // Same on every node, the gateway only defines the network time with beacons
+        time_source: TdmaTimeSource = TdmaTimeSource::Beacon,
+        // Beacons sent by the gateway, 0 disables them
+        beacon_interval_ms: u64 = 5000,
+        // Node whose SYSTEM_TIME messages are taken as beacons
+        beacon_system_id: u8 = node_system_id(NodeType::Gateway),
+    }
+}
+
+impl From<&TdmaSection> for TdmaOptionalConfig {
+    fn from(section: &TdmaSection) -> Self {
+        Self {
+            slot_count: section.slot_count,
+            slot_table: Some(section.slot_table.clone()),
+            system_id: Some(section.system_id),
+            slot_duration_ms: Some(section.slot_duration_ms),
+            guard_margin_ms: section.guard_margin_ms,
+            time_source: section.time_source,
+            beacon_interval_ms: section.beacon_interval_ms,
+            beacon_system_id: section.beacon_system_id,
+        }
+    }
+}
+
+/// Network time in microseconds since the Unix epoch, followed from the latest time sample
+#[derive(Default)]
+struct NetworkClock {
+    reference: Option<(Instant, u64)>,
+}
+
+impl NetworkClock {
+    fn now_us(&self, at: Instant) -> Option<u64> {
+        let (reference_at, reference_us) = self.reference?;
+        let elapsed_us = at.saturating_duration_since(reference_at).as_micros() as u64;
+        let before_us = reference_at.saturating_duration_since(at).as_micros() as u64;
+        Some((reference_us + elapsed_us).saturating_sub(before_us))
+    }
+
+    /// Adopts a time sample, returning the correction it made in microseconds
+    fn sync(&mut self, network_us: u64, at: Instant) -> i64 {
+        let correction = self.now_us(at).map_or(0, |now_us| network_us as i64 - now_us as i64);
+        self.reference = Some((at, network_us));
+        correction
+    }
+}
+
+/// Slots and network time of a node, shared by its [`TdmaScheduler`] and its [`TdmaGuard`]
+struct SlotTiming {
+    slot: u32,
+    slot_count: u32,
+    slot_duration: Option<Duration>,
+    guard_margin: Duration,
+    modulation: Option<LoRaModulation>,
+    clock: NetworkClock,
+}
+
+impl SlotTiming {
+    fn guard_interval(&self) -> Duration {
+        self.modulation
+            .map_or(Duration::ZERO, |modulation| modulation.time_on_air(0))
+            + self.guard_margin
+    }
+
+    fn slot_duration(&self) -> Duration {
+        self.slot_duration.unwrap_or_else(|| match self.modulation {
+            Some(modulation) => modulation.time_on_air(MAX_LORA_PAYLOAD_LENGTH) + self.guard_interval() * 2,
+            None => DEFAULT_SLOT_DURATION,
+        })
+    }
+
+    /// Transmit window of the node containing `at`, or the next one, `None` while the node has no time
+    fn own_window(&self, at: Instant) -> Option<(Instant, Instant)> {
+        let now_us = self.clock.now_us(at)?;
+        let slot_us = self.slot_duration().as_micros().max(1) as u64;
+        let frame_us = slot_us * self.slot_count as u64;
+        let guard_us = self.guard_interval().as_micros() as u64;
+
+        let mut slot_start_us = now_us - now_us % frame_us + self.slot as u64 * slot_us;
+        if now_us + guard_us >= slot_start_us + slot_us {
+            slot_start_us += frame_us;
+        }
+        let instant_of = |network_us: u64| match network_us.checked_sub(now_us) {
+            Some(ahead_us) => at + Duration::from_micros(ahead_us),
+            None => at - Duration::from_micros(now_us - network_us),
+        };
+        Some((
+            instant_of(slot_start_us + guard_us),
+            instant_of(slot_start_us + slot_us - guard_us),
+        ))
+    }
+}
+
+/// Time division scheduler giving each node sharing a LoRa channel its own transmit slot.
+///
+/// Packets of the inner scheduler only leave within the node's slot, and only if their time on
+/// air, with the overhead of the link layers of the driver, ends before the slot does. Each slot
+/// is shortened at both ends by a guard interval of the preamble and header airtime plus
+/// `guard_margin_ms`. Every node of the fleet uses the same time source. With beacons, the gateway
+/// defines the network time from its system clock and broadcasts it as SYSTEM_TIME beacons at the start
+/// of its slot, stamped by the radio when it encodes them so a wait for the channel or the duty cycle
+/// does not skew them, and the other nodes follow the beacons. With GPS, every node follows the GPS time
+/// in the SYSTEM_TIME of its flight controller once it has a fix. Until a node has a time it transmits
+/// without slots.
+///
+/// The [`TdmaGuard`] of the scheduler keeps the radio within the windows as well, for the payloads
+/// its link layers transmit on their own and for packets delayed on their way to the air.
+pub struct TdmaScheduler {
+    link: String,
+    inner: Box<dyn PacketScheduler<MavFramePacket>>,
+    system_id: u8,
+    timing: Arc<Mutex<SlotTiming>>,
+    time_on_air: Option<TimeOnAir<MavFramePacket>>,
+    time_source: TdmaTimeSource,
+    /// Whether the node defines the network time, only the gateway does with the beacon time source
+    time_master: bool,
+    beacon_interval: Option<Duration>,
+    beacon_system_id: u8,
+    started: Instant,
+    /// Packet that did not fit the rest of a slot, with the start of the slot it waits for
+    held: Option<(MavFramePacket, Instant)>,
+    /// End of the airtime of the packets let out in the current slot
+    booked_until: Option<Instant>,
+    next_beacon: Instant,
+    beacon_sequence: u8,
+}
+
+impl TdmaScheduler {
+    pub fn new(
+        link: &str,
+        inner: impl PacketScheduler<MavFramePacket> + 'static,
+        node_type: NodeType,
+        config: Option<TdmaOptionalConfig>,
+    ) -> Self {
+        let config = config.unwrap_or_default().build();
+        let system_id = config.system_id.unwrap_or_else(|| node_system_id(node_type));
+        let (slot, slot_count) = if config.slot_table.is_empty() {
+            (system_id as u32 % config.slot_count.max(1), config.slot_count.max(1))
+        } else {
+            let position = config
+                .slot_table
+                .iter()
+                .position(|listed| *listed == system_id)
+                .expect("Slot table validated at load time to list the system ID of the node");
+            (position as u32, config.slot_table.len() as u32)
+        };
+
+        let now = Instant::now();
+        let mut clock = NetworkClock::default();
+        let time_master = matches!(node_type, NodeType::Gateway) && config.time_source == TdmaTimeSource::Beacon;
+        let beacon_interval = if time_master {
+            let unix_us = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64;
+            clock.sync(unix_us, now);
+            (config.beacon_interval_ms > 0).then(|| Duration::from_millis(config.beacon_interval_ms))
+        } else {
+            None
+        };
+
+        Self {
+            link: link.to_string(),
+            inner: Box::new(inner),
+            system_id,
+            timing: Arc::new(Mutex::new(SlotTiming {
+                slot,
+                slot_count,
+                slot_duration: config.slot_duration_ms.map(Duration::from_millis),
+                guard_margin: Duration::from_millis(config.guard_margin_ms),
+                modulation: None,
+                clock,
+            })),
+            time_on_air: None,
+            time_source: config.time_source,
+            time_master,
+            beacon_interval,
+            beacon_system_id: config.beacon_system_id,
+            started: now,
+            held: None,
+            booked_until: None,
+            next_beacon: now,
+            beacon_sequence: 0,
+        }
+    }
+
+    /// Slot of the node within a frame of `slot_count` slots
+    pub fn slot(&self) -> u32 {
+        self.timing.lock().unwrap().slot
+    }
+
+    /// Preamble and header airtime plus the margin, so a late or early neighbour does not overlap
+    pub fn guard_interval(&self) -> Duration {
+        self.timing.lock().unwrap().guard_interval()
+    }
+
+    pub fn slot_duration(&self) -> Duration {
+        self.timing.lock().unwrap().slot_duration()
+    }
+
+    /// Guard keeping every payload of the radio within the windows of the node, which the radio driver needs
+    pub fn transmit_guard(&self) -> TdmaGuard {
+        TdmaGuard {
+            link: self.link.clone(),
+            timing: self.timing.clone(),
+            beacon_system_id: self.beacon_interval.map(|_| self.system_id),
+        }
+    }
+
+    fn own_window(&self, at: Instant) -> Option<(Instant, Instant)> {
+        self.timing.lock().unwrap().own_window(at)
+    }
+
+    fn beacon_due(&self) -> Option<Instant> {
+        self.beacon_interval.map(|_| self.next_beacon)
+    }
+
+    /// SYSTEM_TIME carrying the network time, which the guard stamps again when the radio encodes it
+    fn create_beacon(&mut self, now: Instant) -> Option<MavFramePacket> {
+        let time_unix_usec = self.timing.lock().unwrap().clock.now_us(now)?;
+        self.beacon_sequence = self.beacon_sequence.wrapping_add(1);
+        metrics().increment_counter(TDMA_BEACONS_SENT_METRIC, &[("link", &self.link)]);
+        Some(MavFramePacket {
+            header: MavHeader {
+                system_id: self.system_id,
+                component_id: AUTOPILOT_COMPONENT_ID,
+                sequence: self.beacon_sequence,
+            },
+            msg: MavMessage::SYSTEM_TIME(SYSTEM_TIME_DATA {
+                time_unix_usec,
+                time_boot_ms: now.duration_since(self.started).as_millis() as u32,
+            }),
+            protocol_version: MavlinkVersion::V2,
+        })
+    }
+
+    fn time_on_air(&self, packet: &MavFramePacket) -> Duration {
+        self.time_on_air
+            .as_ref()
+            .and_then(|time_on_air| time_on_air(packet))
+            .or_else(|| {
+                let modulation = self.timing.lock().unwrap().modulation?;
+                Some(modulation.time_on_air(serialize_frame(packet.clone()).len()))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Adopts a sample of the time source of the fleet
+    fn sync(&mut self, network_us: u64, at: Instant) {
+        let correction_us = self.timing.lock().unwrap().clock.sync(network_us, at);
+        let source = match self.time_source {
+            TdmaTimeSource::Beacon => "beacon",
+            TdmaTimeSource::Gps => "gps",
+        };
+        metrics().set_gauge(
+            TDMA_CLOCK_CORRECTION_METRIC,
+            &[("link", &self.link), ("source", source)],
+            correction_us as f64 / 1e6,
+        );
+        log_debug_tdma_sync(&self.link, source, correction_us);
+    }
+}
+
+impl PacketScheduler<MavFramePacket> for TdmaScheduler {
+    fn push(&mut self, packet: MavFramePacket) {
+        if let (TdmaTimeSource::Gps, MavMessage::SYSTEM_TIME(time)) = (self.time_source, &packet.msg) {
+            // Zero until the flight controller has a GPS fix
+            if packet.header.component_id == AUTOPILOT_COMPONENT_ID && time.time_unix_usec != 0 {
+                self.sync(time.time_unix_usec, Instant::now());
+            }
+        }
+        self.inner.push(packet);
+    }
+
+    fn pop(&mut self) -> Option<MavFramePacket> {
+        let now = Instant::now();
+        let window = self.own_window(now);
+        if let Some((start, _)) = window {
+            if now < start {
+                return None;
+            }
+            if self
+                .booked_until
+                .filter(|booked_until| *booked_until >= start)
+                .is_none()
+            {
+                self.booked_until = Some(now);
+            }
+        }
+
+        let packet = match self.held.take() {
+            Some((packet, _)) => packet,
+            None if self.beacon_due().is_some_and(|due| due <= now) => {
+                self.next_beacon = now + self.beacon_interval.unwrap_or_default();
+                self.create_beacon(now)?
+            }
+            None => self.inner.pop()?,
+        };
+
+        let Some((start, end)) = window else {
+            return Some(packet);
+        };
+        let airtime = self.time_on_air(&packet);
+        let transmitted_at = self.booked_until.unwrap_or(now).max(now) + airtime;
+        if transmitted_at > end {
+            if airtime > end - start {
+                metrics().increment_counter(
+                    SCHEDULER_DROPPED_METRIC,
+                    &[("link", &self.link), ("reason", "exceeds_slot")],
+                );
+                log_debug_scheduler_drop(&self.link, packet.msg.message_name(), "exceeds_slot");
+            } else {
+                metrics().increment_counter(TDMA_DEFERRED_METRIC, &[("link", &self.link)]);
+                let next_start = self.own_window(end).map_or(end, |(next_start, _)| next_start);
+                self.held = Some((packet, next_start));
+            }
+            return None;
+        }
+        self.booked_until = Some(transmitted_at);
+        Some(packet)
+    }
+
+    fn next_ready(&self) -> Option<Instant> {
+        let ready = [
+            self.held.as_ref().map(|(_, next_start)| *next_start),
+            self.inner.next_ready(),
+            self.beacon_due(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()?;
+        match self.own_window(ready) {
+            Some((start, _)) => Some(ready.max(start)),
+            None => Some(ready),
+        }
+    }
+
+    fn queue_length(&self) -> usize {
+        self.inner.queue_length() + usize::from(self.held.is_some())
+    }
+
+    fn observe_received(&mut self, packet: &MavFramePacket) -> bool {
+        if self.inner.observe_received(packet) {
+            return true;
+        }
+        if self.time_source != TdmaTimeSource::Beacon
+            || packet.header.system_id != self.beacon_system_id
+            || packet.header.component_id != AUTOPILOT_COMPONENT_ID
+        {
+            return false;
+        }
+        let MavMessage::SYSTEM_TIME(time) = &packet.msg else {
+            return false;
+        };
+        if !self.time_master {
+            // The beacon was stamped when the radio encoded it and arrives after its time on air
+            let airtime_us = self.time_on_air(packet).as_micros() as u64;
+            self.sync(time.time_unix_usec + airtime_us, Instant::now());
+        }
+        // Flight controllers set their clock from SYSTEM_TIME, the beacons stay on the LoRa link
+        true
+    }
+
+    fn update_modulation(&mut self, modulation: LoRaModulation) {
+        self.timing.lock().unwrap().modulation = Some(modulation);
+        self.inner.update_modulation(modulation);
+    }
+
+    fn set_time_on_air(&mut self, time_on_air: TimeOnAir<MavFramePacket>) {
+        self.inner.set_time_on_air(time_on_air.clone());
+        self.time_on_air = Some(time_on_air);
+    }
+
+    fn next_transmit_opportunity(&self, due: Instant) -> Instant {
+        let at = due.max(Instant::now());
+        match self.own_window(at) {
+            Some((start, _)) => at.max(start),
+            None => at,
+        }
+    }
+}
+
+/// Drops the payloads of a LoRa radio that would not end within the transmit window of its
+/// [`TdmaScheduler`], such as acknowledgements due late in the slot or a batch held up by a busy channel.
+/// On the gateway it also stamps the beacons with the network time right before they are encoded.
+pub struct TdmaGuard {
+    link: String,
+    timing: Arc<Mutex<SlotTiming>>,
+    /// System ID of the beacons the node sends, `None` when it sends none
+    beacon_system_id: Option<u8>,
+}
+
+impl TransmitGuard for TdmaGuard {
+    fn stamp(&self, packet: &mut MavFramePacket) {
+        if self.beacon_system_id != Some(packet.header.system_id)
+            || packet.header.component_id != AUTOPILOT_COMPONENT_ID
+        {
+            return;
+        }
+        if let MavMessage::SYSTEM_TIME(time) = &mut packet.msg {
+            if let Some(now_us) = self.timing.lock().unwrap().clock.now_us(Instant::now()) {
+                time.time_unix_usec = now_us;
+            }
+        }
+    }
+
+    fn allows(&self, _driver: &str, _modulation: &LoRaModulation, at: Instant, airtime: Duration) -> bool {
+        let Some((start, end)) = self.timing.lock().unwrap().own_window(at) else {
+            return true;
+        };
+        if start <= at && at + airtime <= end {
+            return true;
+        }
+        metrics().increment_counter(TDMA_OVERRUN_DROPPED_METRIC, &[("link", &self.link)]);
+        false
+    }
+}
@@ -0,0 +1,362 @@
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::adr::{AdaptiveDataRate, AdaptiveDataRateDriver, AdrOptionalConfig};
+use crate::arq::ArqLayer;
+use crate::channel_access::ChannelAccessDriver;
+use crate::config::NodeConfig;
+use crate::duty_cycle::{DutyCycle, DutyCycleDriver};
+use crate::encryption::EncryptionLayer;
+use crate::fragmentation::{Fragmenter, FragmenterOptionalConfig};
+use crate::full_duplex_network::FullDuplexNetwork;
+use crate::half_duplex_network::HalfDuplexNetwork;
+use crate::logging_utils::log_debug_send_to_network;
+use crate::lora_airtime::LoRaModulation;
+use crate::lora_sx1262_spi::LoRaSx1262SpiDriver;
+use crate::lora_sx1262_uart::LoRaSx1262UartDriver;
+use crate::lora_sx1276_spi::LoRaSx1276SpiDriver;
+use crate::mavlink_utils::{MavlinkHeaderGenerator, MavlinkSigning};
+use crate::metrics::MetricsServer;
+use crate::radio_status::{RadioStatistics, RadioStatusDriver, RadioStatusReporter};
+use crate::router::Router;
+use crate::scheduler::{FifoScheduler, MavlinkScheduler, SchedulerConfig};
+use crate::serial_driver::{SerialDriver, SERIAL_DRIVER};
+use crate::tcp_driver::{TcpClientDriver, TcpServerDriver, TCP_CLIENT_DRIVER, TCP_SERVER_DRIVER};
+use crate::tdma::TdmaScheduler;
+use crate::types::{MavFramePacket, NodeType};
+use crate::udp_driver::{default_udp_filter, UDPDriver, UDP_DRIVER};
+use crate::watchdog::WatchdogDriver;
+use crate::websocket_driver::{WebSocketDriver, WEBSOCKET_DRIVER};
+use crate::{Driver, LoRaRadio, NetworkInterface};
+
+/// Sets up the links, router and reports of a node from its validated config and starts them, returning
+/// the handles of the tasks they run on
+pub async fn build(config: &NodeConfig) -> Vec<JoinHandle<()>> {
+    let channel_size = config.node.channel_size;
+    let (autopilot_run_handle, autopilot_link_name, autopilot_tx, autopilot_rx) = if let Some(section) = &config.serial
+    {
+        let serial_driver = Arc::new(SerialDriver::new(Some(section.into())));
+        let (serial_network, serial_tx, serial_rx) = FullDuplexNetwork::new(serial_driver, channel_size);
+        (serial_network.run().await, SERIAL_DRIVER, serial_tx, serial_rx)
+    } else {
+        let udp_section = config.udp.as_ref().expect("Autopilot link validated at load time");
+        let udp_driver = Arc::new(UDPDriver::new(udp_section.into()).await);
+        let (udp_network, udp_tx, udp_rx) = FullDuplexNetwork::new(udp_driver, channel_size);
+        (udp_network.run().await, UDP_DRIVER, udp_tx, udp_rx)
+    };
+
+    let radio_statistics = RadioStatistics::new();
+    let spi_driver = if let Some(section) = &config.lora_sx1276_spi {
+        let lora_driver = LoRaSx1276SpiDriver::new(Some(section.into())).await;
+        Some(wrap_lora_driver(
+            lora_driver,
+            config,
+            section.max_payload_length,
+            &radio_statistics,
+        ))
+    } else if let Some(section) = &config.lora_sx1262_spi {
+        let lora_driver = LoRaSx1262SpiDriver::new(Some(section.into())).await;
+        Some(wrap_lora_driver(
+            lora_driver,
+            config,
+            section.max_payload_length,
+            &radio_statistics,
+        ))
+    } else {
+        None
+    };
+    let (lora_run_handle, lora_link_name, lora_tx, lora_rx, lora_queue_length) =
+        if let Some((lora_driver, tdma)) = spi_driver {
+            let lora_link_name = lora_driver.to_string();
+            let (lora_network, lora_tx, lora_rx) = HalfDuplexNetwork::new(lora_driver, channel_size);
+            let lora_network = with_configured_scheduler(lora_network, config, &lora_link_name, tdma);
+            let lora_queue_length = lora_network.queue_length();
+            (
+                lora_network.run().await,
+                lora_link_name,
+                lora_tx,
+                lora_rx,
+                lora_queue_length,
+            )
+        } else if let Some(section) = &config.lora_sx1262_uart {
+            let mut lora_driver = LoRaSx1262UartDriver::new(Some(section.into())).await;
+            if let Some(signing) = &config.signing {
+                lora_driver = lora_driver.with_signing(MavlinkSigning::new(Some(signing.into())));
+            }
+            if let Some(encryption) = configured_encryption(config) {
+                lora_driver = lora_driver.with_link_layer(encryption);
+            }
+            if let Some(fragmenter) = configured_fragmenter(config, section.package_size.map(usize::from)) {
+                lora_driver = lora_driver.with_link_layer(fragmenter);
+            }
+            let lora_driver = with_configured_channel_access(Arc::new(lora_driver), config);
+            let lora_driver = with_configured_radio_status(lora_driver, config, &radio_statistics);
+            let lora_link_name = lora_driver.to_string();
+            let (lora_network, lora_tx, lora_rx) = FullDuplexNetwork::new(lora_driver, channel_size);
+            let lora_queue_length = lora_network.queue_length();
+            (
+                lora_network.run().await,
+                lora_link_name,
+                lora_tx,
+                lora_rx,
+                lora_queue_length,
+            )
+        } else {
+            unreachable!("LoRa radio section validated at load time");
+        };
+
+    let mut router = Router::new();
+    let mut extra_run_handles = Vec::new();
+    let mut radio_status_reporter = config.radio_status.as_ref().map(|section| {
+        let mut reporter = RadioStatusReporter::new(
+            radio_statistics,
+            &lora_link_name,
+            lora_tx.clone(),
+            lora_queue_length,
+            Some(section.into()),
+        );
+        reporter.add_output(autopilot_link_name, autopilot_tx.clone());
+        reporter
+    });
+    if let Some(section) = &config.metrics {
+        let metrics_server = MetricsServer::new(Some(section.into())).await;
+        extra_run_handles.push(metrics_server.run().await);
+    }
+    if let Some(section) = &config.tcp_server {
+        let tcp_driver = Arc::new(TcpServerDriver::new(Some(section.into())).await);
+        let (tcp_network, tcp_tx, tcp_rx) = FullDuplexNetwork::new(tcp_driver, channel_size);
+        extra_run_handles.extend(tcp_network.run().await);
+        if let Some(reporter) = &mut radio_status_reporter {
+            reporter.add_output(TCP_SERVER_DRIVER, tcp_tx.clone());
+        }
+        router.add_link_with_filter(
+            TCP_SERVER_DRIVER,
+            tcp_tx,
+            tcp_rx,
+            config.filter_chain(TCP_SERVER_DRIVER),
+        );
+    }
+    if let Some(section) = &config.tcp_client {
+        let tcp_driver = Arc::new(TcpClientDriver::new(Some(section.into())));
+        let (tcp_network, tcp_tx, tcp_rx) = FullDuplexNetwork::new(tcp_driver, channel_size);
+        extra_run_handles.extend(tcp_network.run().await);
+        if let Some(reporter) = &mut radio_status_reporter {
+            reporter.add_output(TCP_CLIENT_DRIVER, tcp_tx.clone());
+        }
+        router.add_link_with_filter(
+            TCP_CLIENT_DRIVER,
+            tcp_tx,
+            tcp_rx,
+            config.filter_chain(TCP_CLIENT_DRIVER),
+        );
+    }
+    if let Some(section) = &config.websocket {
+        let websocket_driver = Arc::new(WebSocketDriver::new(Some(section.into())).await);
+        let (websocket_network, websocket_tx, websocket_rx) = FullDuplexNetwork::new(websocket_driver, channel_size);
+        extra_run_handles.extend(websocket_network.run().await);
+        if let Some(reporter) = &mut radio_status_reporter {
+            reporter.add_output(WEBSOCKET_DRIVER, websocket_tx.clone());
+        }
+        router.add_link_with_filter(
+            WEBSOCKET_DRIVER,
+            websocket_tx,
+            websocket_rx,
+            config.filter_chain(WEBSOCKET_DRIVER),
+        );
+    }
+    router.add_link_with_filter(
+        autopilot_link_name,
+        autopilot_tx.clone(),
+        autopilot_rx,
+        config.filter_chain_or(
+            autopilot_link_name,
+            (autopilot_link_name == UDP_DRIVER).then(default_udp_filter),
+        ),
+    );
+    router.add_link_with_filter(&lora_link_name, lora_tx, lora_rx, config.filter_chain(&lora_link_name));
+    let router_run_handle = router.run().await;
+    if let Some(reporter) = radio_status_reporter {
+        extra_run_handles.push(reporter.run().await);
+    }
+
+    let autopilot_heartbeat = tokio::spawn(send_heartbeat_to_network(autopilot_tx, autopilot_link_name, 1000));
+
+    autopilot_run_handle
+        .into_iter()
+        .chain(lora_run_handle)
+        .chain(extra_run_handles)
+        .chain(router_run_handle)
+        .chain(std::iter::once(autopilot_heartbeat))
+        .collect()
+}
+
+/// Stacks the configured link layers and transmit guards on a LoRa SPI radio and wraps it in the configured
+/// drivers, together with the TDMA scheduler whose guard it got
+fn wrap_lora_driver(
+    mut lora_driver: impl LoRaRadio + 'static,
+    config: &NodeConfig,
+    max_payload_length: Option<u8>,
+    radio_statistics: &Arc<RadioStatistics>,
+) -> (Arc<dyn Driver<MavFramePacket> + Send + Sync>, Option<TdmaScheduler>) {
+    if let Some(signing) = &config.signing {
+        lora_driver = lora_driver.with_signing(MavlinkSigning::new(Some(signing.into())));
+    }
+    // Closest to the frames, so the layer sees the messages it delivers reliably
+    if let Some(arq) = &config.arq {
+        lora_driver = lora_driver.with_link_layer(ArqLayer::new(config.node.node_type, Some(arq.into())));
+    }
+    let adr = configured_adr(config, lora_driver.modulation());
+    if let Some(adr) = &adr {
+        lora_driver = lora_driver.with_link_layer(adr.link_layer());
+    }
+    if let Some(encryption) = configured_encryption(config) {
+        lora_driver = lora_driver.with_link_layer(encryption);
+    }
+    if let Some(fragmenter) = configured_fragmenter(config, max_payload_length.map(usize::from)) {
+        lora_driver = lora_driver.with_link_layer(fragmenter);
+    }
+    let tdma = configured_tdma(config, &lora_driver.to_string());
+    if let Some(tdma) = &tdma {
+        lora_driver = lora_driver.with_transmit_guard(tdma.transmit_guard());
+    }
+    let duty_cycle = configured_duty_cycle(config);
+    if let Some(duty_cycle) = &duty_cycle {
+        lora_driver = lora_driver.with_transmit_guard(duty_cycle.transmit_guard());
+    }
+    let lora_driver = with_configured_watchdog(Arc::new(lora_driver), config);
+    let lora_driver = with_configured_duty_cycle(with_configured_channel_access(lora_driver, config), duty_cycle);
+    let lora_driver = with_configured_adr(lora_driver, adr);
+    (
+        with_configured_radio_status(lora_driver, config, radio_statistics),
+        tdma,
+    )
+}
+
+/// Fragmenter sized for the radio payload unless the config sets a fragment length
+fn configured_fragmenter(config: &NodeConfig, max_payload_length: Option<usize>) -> Option<Fragmenter> {
+    let section = config.fragmentation.as_ref()?;
+    let mut fragmenter_config = FragmenterOptionalConfig::from(section);
+    if section.max_fragment_length.is_none() {
+        fragmenter_config.max_fragment_length = max_payload_length;
+    }
+    Some(Fragmenter::new(config.node.node_type, Some(fragmenter_config)))
+}
+
+fn configured_encryption(config: &NodeConfig) -> Option<EncryptionLayer> {
+    let section = config.encryption.as_ref()?;
+    Some(EncryptionLayer::new(config.node.node_type, Some(section.into())))
+}
+
+fn configured_duty_cycle(config: &NodeConfig) -> Option<Arc<DutyCycle>> {
+    let section = config.duty_cycle.as_ref()?;
+    Some(DutyCycle::new(Some(section.into())))
+}
+
+fn with_configured_duty_cycle(
+    driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+    duty_cycle: Option<Arc<DutyCycle>>,
+) -> Arc<dyn Driver<MavFramePacket> + Send + Sync> {
+    match duty_cycle {
+        Some(duty_cycle) => Arc::new(DutyCycleDriver::new(driver, duty_cycle)),
+        None => driver,
+    }
+}
+
+fn with_configured_watchdog(
+    driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+    config: &NodeConfig,
+) -> Arc<dyn Driver<MavFramePacket> + Send + Sync> {
+    match &config.watchdog {
+        Some(watchdog) => Arc::new(WatchdogDriver::new(driver, Some(watchdog.into()))),
+        None => driver,
+    }
+}
+
+fn with_configured_channel_access(
+    driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+    config: &NodeConfig,
+) -> Arc<dyn Driver<MavFramePacket> + Send + Sync> {
+    match &config.channel_access {
+        Some(channel_access) => Arc::new(ChannelAccessDriver::new(driver, Some(channel_access.into()))),
+        None => driver,
+    }
+}
+
+/// Adaptive data rate falling back to the configured modulation, proposed by the gateway unless the
+/// config picks the controller
+fn configured_adr(config: &NodeConfig, modulation: Option<LoRaModulation>) -> Option<Arc<AdaptiveDataRate>> {
+    let section = config.adr.as_ref()?;
+    let mut adr_config = AdrOptionalConfig::from(section);
+    if section.controller.is_none() {
+        adr_config.controller = Some(matches!(config.node.node_type, NodeType::Gateway));
+    }
+    Some(AdaptiveDataRate::new(modulation?, Some(adr_config)))
+}
+
+fn with_configured_adr(
+    driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+    adr: Option<Arc<AdaptiveDataRate>>,
+) -> Arc<dyn Driver<MavFramePacket> + Send + Sync> {
+    match adr {
+        Some(adr) => Arc::new(AdaptiveDataRateDriver::new(driver, adr)),
+        None => driver,
+    }
+}
+
+/// Gathers the measurements of the LoRa link for the RADIO_STATUS reports when they are configured
+fn with_configured_radio_status(
+    driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+    config: &NodeConfig,
+    statistics: &Arc<RadioStatistics>,
+) -> Arc<dyn Driver<MavFramePacket> + Send + Sync> {
+    match &config.radio_status {
+        Some(_) => Arc::new(RadioStatusDriver::new(driver, statistics.clone())),
+        None => driver,
+    }
+}
+
+/// TDMA slots around the MAVLink scheduler when both are configured, created before the radio is
+/// wrapped as the radio needs its guard
+fn configured_tdma(config: &NodeConfig, link: &str) -> Option<TdmaScheduler> {
+    let tdma = config.tdma.as_ref()?;
+    let node_type = config.node.node_type;
+    Some(match &config.scheduler {
+        Some(scheduler) => TdmaScheduler::new(
+            link,
+            MavlinkScheduler::new(link, &SchedulerConfig::from(scheduler)),
+            node_type,
+            Some(tdma.into()),
+        ),
+        None => TdmaScheduler::new(link, FifoScheduler::default(), node_type, Some(tdma.into())),
+    })
+}
+
+fn with_configured_scheduler(
+    network: HalfDuplexNetwork<MavFramePacket>,
+    config: &NodeConfig,
+    link: &str,
+    tdma: Option<TdmaScheduler>,
+) -> HalfDuplexNetwork<MavFramePacket> {
+    match (tdma, &config.scheduler) {
+        (Some(tdma), _) => network.with_scheduler(tdma),
+        (None, Some(scheduler)) => {
+            network.with_scheduler(MavlinkScheduler::new(link, &SchedulerConfig::from(scheduler)))
+        }
+        (None, None) => network,
+    }
+}
+
+async fn send_heartbeat_to_network(transmit_tx: mpsc::Sender<MavFramePacket>, driver: &str, interval_ms: u64) {
+    let generator = MavlinkHeaderGenerator::new();
+
+    loop {
+        log_debug_send_to_network(driver);
+        transmit_tx
+            .send(generator.create_mavlink_heartbeat_frame())
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(interval_ms)).await;
+    }
+}
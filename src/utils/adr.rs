@@ -0,0 +1,706 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::{timeout_at, Instant};
+
+use super::config::AdrSection;
+use super::link_layer::LinkLayer;
+use super::logging_utils::{
+    log_adr_fallback, log_adr_modulation_change, log_debug_adr_proposal, log_debug_adr_proposal_failed,
+};
+use super::lora_airtime::LoRaModulation;
+use super::metrics::metrics;
+use super::types::MavFramePacket;
+use crate::define_struct_with_defaults;
+use crate::driver::{Driver, DriverError, DriverWrapper, SignalQuality};
+
+pub const ADR_MODULATION_CHANGES_METRIC: &str = "adr_modulation_changes_total";
+pub const ADR_PROPOSALS_FAILED_METRIC: &str = "adr_proposals_failed_total";
+pub const ADR_SPREADING_FACTOR_METRIC: &str = "adr_spreading_factor";
+pub const ADR_BANDWIDTH_METRIC: &str = "adr_bandwidth_hz";
+
+/// Kind and link sequence in front of every payload
+pub const ADR_HEADER_LENGTH: usize = 2;
+const DATA_KIND: u8 = 0x01;
+const PROPOSE_KIND: u8 = 0x02;
+const ACK_KIND: u8 = 0x03;
+const CONFIRM_KIND: u8 = 0x04;
+/// Sequence gaps from this length on are a restart of the peer rather than lost payloads
+const MAX_SEQUENCE_GAP: u8 = 64;
+/// Bandwidths the controller widens the modulation through once at the lowest spreading factor
+const BANDWIDTHS_HZ: [u32; 10] = [
+    7_810, 10_420, 15_630, 20_830, 31_250, 41_670, 62_500, 125_000, 250_000, 500_000,
+];
+
+define_struct_with_defaults! {
+    AdrOptionalConfig, AdrConfig {
+        // The node proposing the changes, the other end of the link accepts or denies them
+        controller: bool = false,
+        // Fastest modulation stepped up to, first by lowering the spreading factor from the configured
+        // one, then by widening the bandwidth
+        min_spreading_factor: u8 = 7,
+        max_bandwidth_hz: u32 = 250_000,
+        // Packets received the SNR and the loss are averaged over, a change needs a full window
+        window: usize = 16,
+        // SNR above the demodulation floor of a faster modulation required to step up to it
+        snr_margin_db: f64 = 8.0,
+        // SNR above the demodulation floor below which a more robust modulation is taken
+        min_snr_margin_db: f64 = 3.0,
+        // Share of the payloads of the peer lost above which a more robust modulation is taken
+        max_loss: f64 = 0.2,
+        // Time after a change or a failed proposal before the next proposal
+        hold_ms: u64 = 10_000,
+        // Time a proposal waits for its acknowledgement before it is sent again
+        proposal_timeout_ms: u64 = 2000,
+        max_proposal_attempts: u32 = 3,
+        // Wait before acknowledging, so the controller is back in receive mode after its transmission
+        ack_delay_ms: u64 = 20,
+        // Time without any payload from the peer after which a node returns to the configured
+        // modulation, keep it above the longest quiet period of the link
+        fallback_timeout_ms: u64 = 15_000,
+    }
+}
+
+impl From<&AdrSection> for AdrOptionalConfig {
+    fn from(section: &AdrSection) -> Self {
+        Self {
+            controller: section.controller,
+            min_spreading_factor: section.min_spreading_factor,
+            max_bandwidth_hz: section.max_bandwidth_hz,
+            window: section.window,
+            snr_margin_db: section.snr_margin_db,
+            min_snr_margin_db: section.min_snr_margin_db,
+            max_loss: section.max_loss,
+            hold_ms: section.hold_ms,
+            proposal_timeout_ms: section.proposal_timeout_ms,
+            max_proposal_attempts: section.max_proposal_attempts,
+            ack_delay_ms: section.ack_delay_ms,
+            fallback_timeout_ms: section.fallback_timeout_ms,
+        }
+    }
+}
+
+/// Relative data rate of a modulation, the coding rate being the same on every step
+fn data_rate(modulation: &LoRaModulation) -> f64 {
+    modulation.spreading_factor as f64 * modulation.bandwidth_hz as f64 / (1u64 << modulation.spreading_factor) as f64
+}
+
+/// SNR expected with `target` from one measured with `current`, a wider bandwidth lets in more noise
+fn snr_with(snr_db: f64, current: &LoRaModulation, target: &LoRaModulation) -> f64 {
+    snr_db - 10.0 * (target.bandwidth_hz as f64 / current.bandwidth_hz as f64).log10()
+}
+
+fn same_step(a: &LoRaModulation, b: &LoRaModulation) -> bool {
+    a.spreading_factor == b.spreading_factor && a.bandwidth_hz == b.bandwidth_hz && a.coding_rate == b.coding_rate
+}
+
+/// Measurement of one payload received from the peer
+struct Sample {
+    snr_db: f64,
+    // Payloads of the peer missing before this one
+    lost: u32,
+}
+
+struct Proposal {
+    id: u8,
+    target: LoRaModulation,
+    attempts: u32,
+    due: Instant,
+}
+
+struct PendingAck {
+    id: u8,
+    target: LoRaModulation,
+    accepted: bool,
+    due: Instant,
+}
+
+/// Switch of the peer the controller did not confirm yet, with the modulation it came from
+struct Unconfirmed {
+    id: u8,
+    previous: LoRaModulation,
+    deadline: Instant,
+}
+
+/// Why the driver changes the modulation
+#[derive(Clone, Copy)]
+enum SwitchReason {
+    // Through `Driver::set_modulation`
+    Change,
+    // The controller on the acceptance of the peer
+    Confirmed,
+    // The peer once its acceptance of the proposal was transmitted
+    Accepted(u8),
+    // The peer without a confirmation of the proposal
+    Revert(u8),
+    Fallback,
+}
+
+struct AdrState {
+    current: LoRaModulation,
+    samples: VecDeque<Sample>,
+    next_sequence: u8,
+    last_sequence: Option<u8>,
+    // Payloads lost before the last one received, until the driver adds its signal quality
+    reception: Option<u32>,
+    last_heard: Instant,
+    hold_until: Instant,
+    next_proposal_id: u8,
+    proposal: Option<Proposal>,
+    ack: Option<PendingAck>,
+    // Taken by the controller once the peer accepted
+    switch: Option<LoRaModulation>,
+    // Taken by the peer once its acceptance was transmitted
+    switch_after_transmit: Option<(u8, LoRaModulation)>,
+    // Transmitted by the controller on the modulation the peer accepted
+    confirm: bool,
+    unconfirmed: Option<Unconfirmed>,
+    // Proposal the peer reverted, accepted again when the controller repeats it
+    reverted: Option<u8>,
+}
+
+impl AdrState {
+    fn next_sequence(&mut self) -> u8 {
+        let sequence = self.next_sequence;
+        self.next_sequence = sequence.wrapping_add(1);
+        sequence
+    }
+
+    fn heard(&mut self, sequence: u8, now: Instant) {
+        let lost = self.last_sequence.map_or(0, |last| {
+            let gap = sequence.wrapping_sub(last).wrapping_sub(1);
+            if gap < MAX_SEQUENCE_GAP {
+                gap as u32
+            } else {
+                0
+            }
+        });
+        self.last_sequence = Some(sequence);
+        self.last_heard = now;
+        // Anything heard on the modulation the peer switched to confirms it
+        self.unconfirmed = None;
+        self.reception = Some(lost);
+    }
+
+    fn average_snr(&self) -> f64 {
+        self.samples.iter().map(|sample| sample.snr_db).sum::<f64>() / self.samples.len().max(1) as f64
+    }
+
+    fn loss(&self) -> f64 {
+        let lost: u32 = self.samples.iter().map(|sample| sample.lost).sum();
+        lost as f64 / (lost as usize + self.samples.len()).max(1) as f64
+    }
+}
+
+/// Adaptive data rate of a LoRa link between two nodes, shared by its [`AdrLayer`] and its
+/// [`AdaptiveDataRateDriver`].
+///
+/// Steps through modulations from the one the driver is configured with, the most robust and the
+/// fallback, down to `min_spreading_factor` and then up to `max_bandwidth_hz`. The controller tracks
+/// the SNR and the loss of the payloads of the peer over a rolling window. It steps up one modulation
+/// once the SNR keeps `snr_margin_db` above the demodulation floor of the next one, and down to the
+/// fastest one keeping that margin once the SNR falls within `min_snr_margin_db` of the floor of the
+/// current one or the loss exceeds `max_loss`.
+///
+/// A change is proposed to the peer, which checks it against its own measurements. The peer switches
+/// once its acknowledgement is transmitted and the controller once it receives it, so both ends change
+/// between the same two packets. The controller confirms the change by transmitting on the new
+/// modulation right away. A peer hearing nothing within half the proposal timeout reverts, in time for
+/// the repeated proposal when the acknowledgement was lost. A node hearing nothing from the peer for
+/// `fallback_timeout_ms` returns to the fallback, which brings both ends back together after a lost
+/// confirmation or when the peer moves out of range of a fast modulation.
+pub struct AdaptiveDataRate {
+    controller: bool,
+    steps: Vec<LoRaModulation>,
+    window: usize,
+    snr_margin_db: f64,
+    min_snr_margin_db: f64,
+    max_loss: f64,
+    hold: Duration,
+    proposal_timeout: Duration,
+    max_proposal_attempts: u32,
+    ack_delay: Duration,
+    fallback_timeout: Duration,
+    state: Mutex<AdrState>,
+}
+
+impl AdaptiveDataRate {
+    /// `fallback` is the modulation the LoRa driver is configured with, which both ends start with
+    pub fn new(fallback: LoRaModulation, config: Option<AdrOptionalConfig>) -> Arc<Self> {
+        let config = config.unwrap_or_default().build();
+
+        let mut steps = vec![fallback];
+        let mut step = fallback;
+        while step.spreading_factor > config.min_spreading_factor {
+            step.spreading_factor -= 1;
+            steps.push(step);
+        }
+        for bandwidth_hz in BANDWIDTHS_HZ
+            .into_iter()
+            .filter(|hz| *hz > fallback.bandwidth_hz && *hz <= config.max_bandwidth_hz)
+        {
+            steps.push(LoRaModulation { bandwidth_hz, ..step });
+        }
+
+        let now = Instant::now();
+        Arc::new(Self {
+            controller: config.controller,
+            steps,
+            window: config.window.max(1),
+            snr_margin_db: config.snr_margin_db,
+            min_snr_margin_db: config.min_snr_margin_db,
+            max_loss: config.max_loss,
+            hold: Duration::from_millis(config.hold_ms),
+            proposal_timeout: Duration::from_millis(config.proposal_timeout_ms),
+            max_proposal_attempts: config.max_proposal_attempts,
+            ack_delay: Duration::from_millis(config.ack_delay_ms),
+            fallback_timeout: Duration::from_millis(config.fallback_timeout_ms),
+            state: Mutex::new(AdrState {
+                current: fallback,
+                samples: VecDeque::new(),
+                next_sequence: 0,
+                last_sequence: None,
+                reception: None,
+                last_heard: now,
+                hold_until: now + Duration::from_millis(config.hold_ms),
+                next_proposal_id: 0,
+                proposal: None,
+                ack: None,
+                switch: None,
+                switch_after_transmit: None,
+                confirm: false,
+                unconfirmed: None,
+                reverted: None,
+            }),
+        })
+    }
+
+    /// Link layer numbering the payloads and carrying the proposals, both ends of the link need it
+    pub fn link_layer(self: &Arc<Self>) -> AdrLayer {
+        AdrLayer { adr: self.clone() }
+    }
+
+    fn fallback(&self) -> LoRaModulation {
+        self.steps[0]
+    }
+
+    fn step_index(&self, modulation: &LoRaModulation) -> Option<usize> {
+        self.steps.iter().position(|step| same_step(step, modulation))
+    }
+
+    /// Whether the measurements hold `margin_db` above the demodulation floor of `target`
+    fn fits(&self, state: &AdrState, target: &LoRaModulation, margin_db: f64) -> bool {
+        snr_with(state.average_snr(), &state.current, target) >= target.demodulation_floor_db() + margin_db
+    }
+
+    /// Modulation the controller moves to from the measurements of a full window, if any
+    fn next_step(&self, state: &AdrState) -> Option<LoRaModulation> {
+        let current = self.step_index(&state.current).unwrap_or(0);
+        let loss = state.loss();
+        if loss > self.max_loss || !self.fits(state, &self.steps[current], self.min_snr_margin_db) {
+            // Straight down to the fastest modulation keeping the margin, as packets are being lost
+            let target = (0..current)
+                .rev()
+                .find(|index| self.fits(state, &self.steps[*index], self.snr_margin_db))
+                .unwrap_or(0);
+            return (target < current).then(|| self.steps[target]);
+        }
+        // Up one step at a time, each proving itself over a window before the next
+        let next = self.steps.get(current + 1)?;
+        (loss <= self.max_loss / 2.0 && self.fits(state, next, self.snr_margin_db)).then_some(*next)
+    }
+
+    /// Whether the peer takes a proposed modulation, checked against its own measurements
+    fn accepts(&self, state: &AdrState, target: &LoRaModulation) -> bool {
+        if self.step_index(target).is_none() {
+            return false;
+        }
+        // A more robust modulation is always taken, the controller saw the link degrade
+        data_rate(target) <= data_rate(&state.current)
+            || (state.samples.len() >= self.window
+                && state.loss() <= self.max_loss
+                && self.fits(state, target, self.snr_margin_db))
+    }
+
+    fn evaluate(&self, state: &mut AdrState, now: Instant) {
+        if !self.controller || state.proposal.is_some() || now < state.hold_until || state.samples.len() < self.window {
+            return;
+        }
+        if let Some(target) = self.next_step(state) {
+            let id = state.next_proposal_id;
+            state.next_proposal_id = id.wrapping_add(1);
+            state.proposal = Some(Proposal {
+                id,
+                target,
+                attempts: 0,
+                due: now,
+            });
+        }
+    }
+
+    /// Adds the signal quality the driver measured for the payload just received to the window
+    fn record_signal(&self, signal: Option<SignalQuality>) {
+        let mut state = self.state.lock().unwrap();
+        let Some(lost) = state.reception.take() else {
+            return;
+        };
+        let Some(signal) = signal else {
+            return;
+        };
+        state.samples.push_back(Sample {
+            snr_db: signal.snr_db as f64,
+            lost,
+        });
+        while state.samples.len() > self.window {
+            state.samples.pop_front();
+        }
+        self.evaluate(&mut state, Instant::now());
+    }
+
+    fn receive_proposal(&self, state: &mut AdrState, body: &[u8], now: Instant) {
+        // Only one end of the link proposes
+        if self.controller {
+            return;
+        }
+        let [id, spreading_factor, coding_rate, bandwidth @ ..] = body else {
+            return;
+        };
+        let Ok(bandwidth) = <[u8; 4]>::try_from(bandwidth) else {
+            return;
+        };
+        let target = LoRaModulation {
+            spreading_factor: *spreading_factor,
+            bandwidth_hz: u32::from_le_bytes(bandwidth),
+            coding_rate: *coding_rate,
+            ..state.current
+        };
+        // Accepted again when the proposal is repeated, as the first acknowledgement may be the one lost
+        let accepted =
+            same_step(&target, &state.current) || state.reverted == Some(*id) || self.accepts(state, &target);
+        state.ack = Some(PendingAck {
+            id: *id,
+            target,
+            accepted,
+            due: now + self.ack_delay,
+        });
+    }
+
+    fn receive_ack(&self, state: &mut AdrState, body: &[u8], now: Instant) {
+        let [id, accepted] = body else {
+            return;
+        };
+        let answered = if state.proposal.as_ref().is_some_and(|proposal| proposal.id == *id) {
+            state.proposal.take()
+        } else {
+            None
+        };
+        let Some(proposal) = answered else {
+            return;
+        };
+        if *accepted != 0 {
+            state.switch = Some(proposal.target);
+        } else {
+            metrics().increment_counter(ADR_PROPOSALS_FAILED_METRIC, &[("reason", "denied")]);
+            log_debug_adr_proposal_failed(proposal.target.spreading_factor, proposal.target.bandwidth_hz, "denied");
+            state.hold_until = now + self.hold;
+        }
+    }
+
+    /// Modulation the controller switches to now that the peer accepted it
+    fn take_switch(&self) -> Option<LoRaModulation> {
+        self.state.lock().unwrap().switch.take()
+    }
+
+    /// Modulation the peer switches to now that its acceptance was transmitted. A failed transmission
+    /// drops it, the controller then proposes again.
+    fn take_switch_after_transmit(&self, transmitted: bool) -> Option<(u8, LoRaModulation)> {
+        let switch = self.state.lock().unwrap().switch_after_transmit.take();
+        switch.filter(|_| transmitted)
+    }
+
+    fn fallback_deadline(&self, state: &AdrState) -> Option<Instant> {
+        (!same_step(&state.current, &self.fallback())).then_some(state.last_heard + self.fallback_timeout)
+    }
+
+    /// When the node changes the modulation on its own next, `None` while it waits for nothing
+    fn deadline(&self) -> Option<Instant> {
+        let state = self.state.lock().unwrap();
+        let revert = state.unconfirmed.as_ref().map(|unconfirmed| unconfirmed.deadline);
+        revert.into_iter().chain(self.fallback_deadline(&state)).min()
+    }
+
+    /// Modulation before an unconfirmed switch, or the fallback once contact with the peer was lost on
+    /// a faster one
+    fn due_switch(&self) -> Option<(LoRaModulation, SwitchReason)> {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if let Some(unconfirmed) = state
+            .unconfirmed
+            .as_ref()
+            .filter(|unconfirmed| unconfirmed.deadline <= now)
+        {
+            return Some((unconfirmed.previous, SwitchReason::Revert(unconfirmed.id)));
+        }
+        self.fallback_deadline(&state)
+            .filter(|deadline| *deadline <= now)
+            .map(|_| (self.fallback(), SwitchReason::Fallback))
+    }
+
+    /// Starts over the measurements and the hold time with a modulation the driver switched to
+    fn switched(&self, driver: &str, modulation: LoRaModulation, reason: SwitchReason) {
+        let mut state = self.state.lock().unwrap();
+        let label = match reason {
+            SwitchReason::Fallback => "fallback",
+            SwitchReason::Revert(_) => "revert",
+            _ if data_rate(&modulation) > data_rate(&state.current) => "upgrade",
+            _ => "downgrade",
+        };
+        let previous = state.current;
+        let now = Instant::now();
+        state.current = modulation;
+        state.samples.clear();
+        state.reception = None;
+        state.last_sequence = None;
+        state.last_heard = now;
+        state.hold_until = now + self.hold;
+        state.proposal = None;
+        state.switch = None;
+        state.confirm = matches!(reason, SwitchReason::Confirmed);
+        state.unconfirmed = match reason {
+            SwitchReason::Accepted(id) => Some(Unconfirmed {
+                id,
+                previous,
+                deadline: now + self.proposal_timeout / 2,
+            }),
+            _ => None,
+        };
+        state.reverted = match reason {
+            SwitchReason::Revert(id) => Some(id),
+            _ => None,
+        };
+        drop(state);
+
+        metrics().increment_counter(ADR_MODULATION_CHANGES_METRIC, &[("driver", driver), ("reason", label)]);
+        self.update_metrics(driver, &modulation);
+        if matches!(reason, SwitchReason::Fallback) {
+            log_adr_fallback(driver, modulation.spreading_factor, modulation.bandwidth_hz);
+        } else {
+            log_adr_modulation_change(driver, modulation.spreading_factor, modulation.bandwidth_hz, label);
+        }
+    }
+
+    fn update_metrics(&self, driver: &str, modulation: &LoRaModulation) {
+        metrics().set_gauge(
+            ADR_SPREADING_FACTOR_METRIC,
+            &[("driver", driver)],
+            modulation.spreading_factor as f64,
+        );
+        metrics().set_gauge(
+            ADR_BANDWIDTH_METRIC,
+            &[("driver", driver)],
+            modulation.bandwidth_hz as f64,
+        );
+    }
+}
+
+/// Link layer of the adaptive data rate, putting a kind and a link sequence in front of every payload.
+///
+/// The sequences tell the receiver how many payloads of the peer were lost, whatever the MAVLink frames
+/// inside were, and the proposals and their acknowledgements travel as payloads of their own. Both ends
+/// of the link need the layer, payloads without its header are dropped.
+pub struct AdrLayer {
+    adr: Arc<AdaptiveDataRate>,
+}
+
+impl LinkLayer for AdrLayer {
+    fn encode(&self, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        let sequence = self.adr.state.lock().unwrap().next_sequence();
+        let mut data_payload = Vec::with_capacity(ADR_HEADER_LENGTH + payload.len());
+        data_payload.extend_from_slice(&[DATA_KIND, sequence]);
+        data_payload.extend(payload);
+        vec![data_payload]
+    }
+
+    fn decode(&self, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        let [kind, sequence, body @ ..] = payload.as_slice() else {
+            return Vec::new();
+        };
+        if ![DATA_KIND, PROPOSE_KIND, ACK_KIND, CONFIRM_KIND].contains(kind) {
+            return Vec::new();
+        }
+        let mut state = self.adr.state.lock().unwrap();
+        let now = Instant::now();
+        state.heard(*sequence, now);
+        match *kind {
+            DATA_KIND => vec![body.to_vec()],
+            PROPOSE_KIND => {
+                self.adr.receive_proposal(&mut state, body, now);
+                Vec::new()
+            }
+            ACK_KIND => {
+                self.adr.receive_ack(&mut state, body, now);
+                Vec::new()
+            }
+            // Only heard, which confirms the switch
+            _ => Vec::new(),
+        }
+    }
+
+    fn overhead(&self) -> usize {
+        ADR_HEADER_LENGTH
+    }
+
+    fn poll_transmit(&self, allows: &mut dyn FnMut(usize) -> bool) -> Vec<Vec<u8>> {
+        let adr = &self.adr;
+        let mut state = adr.state.lock().unwrap();
+        let now = Instant::now();
+        let mut payloads = Vec::new();
+
+        // A payload takes its link sequence once allowed, a skipped sequence would be counted as lost by the peer
+        if let Some(proposal) = state.proposal.as_ref().filter(|proposal| proposal.due <= now) {
+            let (id, target, attempts) = (proposal.id, proposal.target, proposal.attempts);
+            if attempts >= adr.max_proposal_attempts {
+                // The peer may have switched with its acknowledgement lost, the fallback brings both back
+                state.proposal = None;
+                state.hold_until = now + adr.hold;
+                metrics().increment_counter(ADR_PROPOSALS_FAILED_METRIC, &[("reason", "timeout")]);
+                log_debug_adr_proposal_failed(target.spreading_factor, target.bandwidth_hz, "timeout");
+            } else {
+                let mut payload = vec![
+                    PROPOSE_KIND,
+                    state.next_sequence,
+                    id,
+                    target.spreading_factor,
+                    target.coding_rate,
+                ];
+                payload.extend_from_slice(&target.bandwidth_hz.to_le_bytes());
+                if allows(payload.len()) {
+                    state.next_sequence();
+                    state.proposal = Some(Proposal {
+                        id,
+                        target,
+                        attempts: attempts + 1,
+                        due: now + adr.proposal_timeout,
+                    });
+                    payloads.push(payload);
+                    log_debug_adr_proposal(target.spreading_factor, target.bandwidth_hz, attempts + 1);
+                }
+            }
+        }
+
+        let due_ack = state
+            .ack
+            .as_ref()
+            .filter(|ack| ack.due <= now)
+            .map(|ack| (ack.id, ack.target, ack.accepted));
+        if let Some((id, target, accepted)) = due_ack {
+            let payload = vec![ACK_KIND, state.next_sequence, id, accepted as u8];
+            if allows(payload.len()) {
+                state.ack = None;
+                state.next_sequence();
+                payloads.push(payload);
+                if accepted && !same_step(&target, &state.current) {
+                    state.switch_after_transmit = Some((id, target));
+                }
+            }
+        }
+
+        if state.confirm {
+            let payload = vec![CONFIRM_KIND, state.next_sequence];
+            if allows(payload.len()) {
+                state.confirm = false;
+                state.next_sequence();
+                payloads.push(payload);
+            }
+        }
+        payloads
+    }
+
+    fn next_transmit(&self) -> Option<Instant> {
+        let state = self.adr.state.lock().unwrap();
+        let proposal_due = state.proposal.as_ref().map(|proposal| proposal.due);
+        let ack_due = state.ack.as_ref().map(|ack| ack.due);
+        let confirm_due = state.confirm.then(Instant::now);
+        proposal_due.into_iter().chain(ack_due).chain(confirm_due).min()
+    }
+}
+
+/// Driver wrapper applying the modulation changes of an [`AdaptiveDataRate`] to a LoRa driver, whose
+/// link layers need the [`AdrLayer`] of the same instance.
+///
+/// Hands the signal quality of each payload received to the measurements. The radio is set up for
+/// receiving again right away when a change or the fallback happens while it listens.
+pub struct AdaptiveDataRateDriver {
+    driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+    adr: Arc<AdaptiveDataRate>,
+}
+
+impl AdaptiveDataRateDriver {
+    pub fn new(driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>, adr: Arc<AdaptiveDataRate>) -> Self {
+        adr.update_metrics(&driver.to_string(), &adr.fallback());
+        Self { driver, adr }
+    }
+
+    async fn switch(&self, modulation: LoRaModulation, reason: SwitchReason) -> Result<(), DriverError> {
+        self.driver.set_modulation(modulation).await?;
+        self.adr.switched(&self.driver.to_string(), modulation, reason);
+        Ok(())
+    }
+}
+
+impl Display for AdaptiveDataRateDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.driver)
+    }
+}
+
+#[async_trait::async_trait]
+impl DriverWrapper<MavFramePacket> for AdaptiveDataRateDriver {
+    fn inner(&self) -> &Arc<dyn Driver<MavFramePacket> + Send + Sync> {
+        &self.driver
+    }
+
+    async fn send(&self, packet_to_send: &MavFramePacket) -> Result<(), DriverError> {
+        DriverWrapper::send_batch(self, std::slice::from_ref(packet_to_send)).await
+    }
+
+    async fn send_batch(&self, packets_to_send: &[MavFramePacket]) -> Result<(), DriverError> {
+        let result = self.driver.send_batch(packets_to_send).await;
+        if let Some((id, modulation)) = self.adr.take_switch_after_transmit(result.is_ok()) {
+            // The network prepares the radio for receiving after each transmission
+            self.switch(modulation, SwitchReason::Accepted(id)).await?;
+        }
+        result
+    }
+
+    async fn receive(&self) -> Result<Option<MavFramePacket>, DriverError> {
+        if let Some((modulation, reason)) = self.adr.due_switch() {
+            self.switch(modulation, reason).await?;
+            self.driver.prepare_to_receive().await?;
+            return Ok(None);
+        }
+        let received = self.driver.receive().await;
+        self.adr.record_signal(self.driver.signal_quality());
+        if let Some(modulation) = self.adr.take_switch() {
+            self.switch(modulation, SwitchReason::Confirmed).await?;
+            self.driver.prepare_to_receive().await?;
+        }
+        received
+    }
+
+    async fn ready_to_receive(&self) -> Result<(), DriverError> {
+        // Ready once a switch of its own is due, the following receive makes it
+        match self.adr.deadline() {
+            Some(deadline) => timeout_at(deadline, self.driver.ready_to_receive())
+                .await
+                .unwrap_or(Ok(())),
+            None => self.driver.ready_to_receive().await,
+        }
+    }
+
+    async fn set_modulation(&self, modulation: LoRaModulation) -> Result<(), DriverError> {
+        self.switch(modulation, SwitchReason::Change).await
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use mavlink::ardupilotmega::MavMessage;
+use mavlink::Message;
+use tokio::time::Instant;
+
+use super::config::ArqSection;
+use super::link_layer::LinkLayer;
+use super::logging_utils::{log_arq_delivery_failed, log_debug_arq_retransmission};
+use super::mavlink_utils::{frame_message_id, node_system_id, split_frames};
+use super::metrics::metrics;
+use super::types::NodeType;
+use crate::define_struct_with_defaults;
+
+pub const ARQ_RETRANSMISSIONS_METRIC: &str = "arq_retransmissions_total";
+pub const ARQ_ACKS_SENT_METRIC: &str = "arq_acks_sent_total";
+pub const ARQ_DUPLICATES_DROPPED_METRIC: &str = "arq_duplicates_dropped_total";
+pub const ARQ_DELIVERY_FAILED_METRIC: &str = "arq_delivery_failed_total";
+pub const ARQ_TIMEOUT_METRIC: &str = "arq_timeout_seconds";
+
+/// Kind, sender tag and link sequence of a reliable payload
+pub const ARQ_HEADER_LENGTH: usize = 3;
+const RELIABLE_KIND: u8 = 0x01;
+const ACK_KIND: u8 = 0x02;
+/// Previous sequences of the same sender an acknowledgement reports in its bitmap
+const ACK_BITMAP_LENGTH: u8 = 8;
+/// Sequences behind the highest one of a sender that are still told apart from duplicates
+const RECEIVE_WINDOW_LENGTH: u8 = 64;
+
+const DEFAULT_RELIABLE_MESSAGES: [&str; 5] = [
+    "COMMAND_LONG",
+    "COMMAND_INT",
+    "MISSION_COUNT",
+    "MISSION_ITEM_INT",
+    "PARAM_SET",
+];
+
+define_struct_with_defaults! {
+    ArqOptionalConfig, ArqConfig {
+        // MAVLink messages delivered with acknowledgements and retransmissions, the others stay best effort
+        reliable_messages: Vec<String> = DEFAULT_RELIABLE_MESSAGES.iter().map(|name| name.to_string()).collect(),
+        // Retransmissions of an unacknowledged payload before it is given up
+        max_retransmissions: u32 = 3,
+        // Acknowledgement timeout until a round trip was measured
+        initial_timeout_ms: u64 = 1000,
+        // Wait before acknowledging, so the sender is back in receive mode after its transmission
+        ack_delay_ms: u64 = 20,
+        // Bounds of the timeout adapted to the measured round trips, also of its backoff
+        min_timeout_ms: u64 = 200,
+        max_timeout_ms: u64 = 10_000,
+        // Tells apart the nodes sharing the channel, defaults to the system ID of the node type
+        sender_tag: Option<u8> = None,
+    }
+}
+
+impl From<&ArqSection> for ArqOptionalConfig {
+    fn from(section: &ArqSection) -> Self {
+        Self {
+            reliable_messages: section.reliable_messages.clone(),
+            max_retransmissions: section.max_retransmissions,
+            initial_timeout_ms: section.initial_timeout_ms,
+            ack_delay_ms: section.ack_delay_ms,
+            min_timeout_ms: section.min_timeout_ms,
+            max_timeout_ms: section.max_timeout_ms,
+            sender_tag: Some(section.sender_tag),
+        }
+    }
+}
+
+struct Unacknowledged {
+    payload: Vec<u8>,
+    sent_at: Instant,
+    due: Instant,
+    retransmissions: u32,
+}
+
+/// Sequences received from one sender, bit `n` of `seen` stands for `highest - n`
+struct ReceiveWindow {
+    highest: u8,
+    seen: u64,
+}
+
+impl ReceiveWindow {
+    fn new(sequence: u8) -> Self {
+        Self {
+            highest: sequence,
+            seen: 1,
+        }
+    }
+
+    /// Records a received sequence, false when it was received before
+    fn record(&mut self, sequence: u8) -> bool {
+        let ahead = sequence.wrapping_sub(self.highest) as i8;
+        if ahead > 0 {
+            self.seen = self.seen.checked_shl(ahead as u32).unwrap_or(0) | 1;
+            self.highest = sequence;
+            return true;
+        }
+        let behind = self.highest.wrapping_sub(sequence);
+        if behind >= RECEIVE_WINDOW_LENGTH {
+            // Too old to tell, a late retransmission is rather delivered twice than lost
+            return true;
+        }
+        let first_time = self.seen & (1 << behind) == 0;
+        self.seen |= 1 << behind;
+        first_time
+    }
+
+    fn contains(&self, sequence: u8) -> bool {
+        let behind = self.highest.wrapping_sub(sequence);
+        behind < RECEIVE_WINDOW_LENGTH && self.seen & (1 << behind) != 0
+    }
+}
+
+struct ArqState {
+    next_sequence: u8,
+    unacknowledged: HashMap<u8, Unacknowledged>,
+    smoothed_round_trip: Option<Duration>,
+    round_trip_variation: Duration,
+    timeout: Duration,
+    received: HashMap<u8, ReceiveWindow>,
+    // Sender tag and sequence of the reliable payloads to acknowledge, and when
+    pending_acks: Vec<(u8, u8, Instant)>,
+}
+
+/// Link layer delivering critical MAVLink messages reliably with link sequence numbers,
+/// acknowledgements and selective retransmission.
+///
+/// Payloads carrying a reliable message get a header with the sender tag and a link sequence, and
+/// are retransmitted until acknowledged, up to `max_retransmissions` times. The timeout adapts to
+/// the measured round trips the way TCP does and doubles with each retransmission. Receivers
+/// acknowledge each reliable payload with 4 bytes also reporting the 8 sequences before it, and
+/// drop the duplicates of payloads they already handed out. Best effort payloads are left
+/// untouched, so telemetry costs no extra airtime, and both ends of the link have to use the layer.
+/// The sender tag is the system ID of the node, so acknowledgements still match after a restart.
+pub struct ArqLayer {
+    reliable_message_ids: HashSet<u32>,
+    max_retransmissions: u32,
+    ack_delay: Duration,
+    min_timeout: Duration,
+    max_timeout: Duration,
+    sender_tag: u8,
+    state: Mutex<ArqState>,
+}
+
+impl ArqLayer {
+    pub fn new(node_type: NodeType, config: Option<ArqOptionalConfig>) -> Self {
+        let config = config.unwrap_or_default().build();
+        let min_timeout = Duration::from_millis(config.min_timeout_ms);
+        let max_timeout = Duration::from_millis(config.max_timeout_ms);
+
+        Self {
+            reliable_message_ids: config
+                .reliable_messages
+                .iter()
+                .filter_map(|name| MavMessage::message_id_from_name(name).ok())
+                .collect(),
+            max_retransmissions: config.max_retransmissions,
+            ack_delay: Duration::from_millis(config.ack_delay_ms),
+            min_timeout,
+            max_timeout,
+            // Tells apart the nodes sharing the channel, which number their payloads independently
+            sender_tag: config.sender_tag.unwrap_or_else(|| node_system_id(node_type)),
+            state: Mutex::new(ArqState {
+                next_sequence: 0,
+                unacknowledged: HashMap::new(),
+                smoothed_round_trip: None,
+                round_trip_variation: Duration::ZERO,
+                timeout: Duration::from_millis(config.initial_timeout_ms).clamp(min_timeout, max_timeout),
+                received: HashMap::new(),
+                pending_acks: Vec::new(),
+            }),
+        }
+    }
+
+    fn is_reliable(&self, payload: &[u8]) -> bool {
+        split_frames(payload)
+            .iter()
+            .filter_map(|frame| frame_message_id(frame))
+            .any(|message_id| self.reliable_message_ids.contains(&message_id))
+    }
+
+    /// Timeout of a payload after the given number of retransmissions
+    fn backoff(&self, state: &ArqState, retransmissions: u32) -> Duration {
+        state
+            .timeout
+            .saturating_mul(2u32.saturating_pow(retransmissions))
+            .min(self.max_timeout)
+    }
+
+    /// Adapts the timeout to a measured round trip, following RFC 6298
+    fn sample_round_trip(&self, state: &mut ArqState, round_trip: Duration) {
+        match state.smoothed_round_trip {
+            None => {
+                state.smoothed_round_trip = Some(round_trip);
+                state.round_trip_variation = round_trip / 2;
+            }
+            Some(smoothed) => {
+                let deviation = if smoothed > round_trip {
+                    smoothed - round_trip
+                } else {
+                    round_trip - smoothed
+                };
+                state.round_trip_variation = state.round_trip_variation * 3 / 4 + deviation / 4;
+                state.smoothed_round_trip = Some(smoothed * 7 / 8 + round_trip / 8);
+            }
+        }
+        let smoothed = state.smoothed_round_trip.unwrap_or(round_trip);
+        state.timeout = (smoothed + state.round_trip_variation * 4).clamp(self.min_timeout, self.max_timeout);
+        metrics().set_gauge(ARQ_TIMEOUT_METRIC, &[], state.timeout.as_secs_f64());
+    }
+
+    fn acknowledge(&self, state: &mut ArqState, sequence: u8, now: Instant) {
+        if let Some(entry) = state.unacknowledged.remove(&sequence) {
+            // Round trips of retransmitted payloads are ambiguous and not sampled, as Karn's algorithm does
+            if entry.retransmissions == 0 {
+                self.sample_round_trip(state, now.duration_since(entry.sent_at));
+            }
+        }
+    }
+
+    fn give_up(&self, state: &mut ArqState, sequence: u8, reason: &str) {
+        if let Some(entry) = state.unacknowledged.remove(&sequence) {
+            metrics().increment_counter(ARQ_DELIVERY_FAILED_METRIC, &[("reason", reason)]);
+            log_arq_delivery_failed(sequence, entry.retransmissions, reason);
+        }
+    }
+
+    /// Gives up the payloads whose last retransmission timed out
+    fn expire(&self, state: &mut ArqState, now: Instant) {
+        let expired: Vec<u8> = state
+            .unacknowledged
+            .iter()
+            .filter(|(_, entry)| entry.retransmissions >= self.max_retransmissions && entry.due <= now)
+            .map(|(sequence, _)| *sequence)
+            .collect();
+        for sequence in expired {
+            self.give_up(state, sequence, "timeout");
+        }
+    }
+
+    fn encode_ack(&self, state: &ArqState, sender_tag: u8, sequence: u8) -> Vec<u8> {
+        let bitmap = state.received.get(&sender_tag).map_or(0, |window| {
+            (1..=ACK_BITMAP_LENGTH)
+                .filter(|offset| window.contains(sequence.wrapping_sub(*offset)))
+                .fold(0u8, |bitmap, offset| bitmap | 1 << (offset - 1))
+        });
+        vec![ACK_KIND, sender_tag, sequence, bitmap]
+    }
+}
+
+impl LinkLayer for ArqLayer {
+    fn encode(&self, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        if !self.is_reliable(&payload) {
+            return vec![payload];
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let sequence = state.next_sequence;
+        state.next_sequence = sequence.wrapping_add(1);
+        // The sequence wrapped around onto a payload that is still waiting for its acknowledgement
+        self.give_up(&mut state, sequence, "superseded");
+
+        let mut reliable_payload = Vec::with_capacity(ARQ_HEADER_LENGTH + payload.len());
+        reliable_payload.extend_from_slice(&[RELIABLE_KIND, self.sender_tag, sequence]);
+        reliable_payload.extend(payload);
+        let now = Instant::now();
+        let due = now + state.timeout;
+        state.unacknowledged.insert(
+            sequence,
+            Unacknowledged {
+                payload: reliable_payload.clone(),
+                sent_at: now,
+                due,
+                retransmissions: 0,
+            },
+        );
+        vec![reliable_payload]
+    }
+
+    fn decode(&self, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        match payload.as_slice() {
+            [RELIABLE_KIND, sender_tag, sequence, ..] => {
+                let (sender_tag, sequence) = (*sender_tag, *sequence);
+                let mut state = self.state.lock().unwrap();
+                // Acknowledged again when a duplicate arrives, as the first acknowledgement may be the one lost
+                if !state
+                    .pending_acks
+                    .iter()
+                    .any(|(tag, pending, _)| (*tag, *pending) == (sender_tag, sequence))
+                {
+                    state
+                        .pending_acks
+                        .push((sender_tag, sequence, Instant::now() + self.ack_delay));
+                }
+                let first_time = match state.received.get_mut(&sender_tag) {
+                    Some(window) => window.record(sequence),
+                    None => {
+                        state.received.insert(sender_tag, ReceiveWindow::new(sequence));
+                        true
+                    }
+                };
+                if !first_time {
+                    metrics().increment_counter(ARQ_DUPLICATES_DROPPED_METRIC, &[]);
+                    return Vec::new();
+                }
+                vec![payload[ARQ_HEADER_LENGTH..].to_vec()]
+            }
+            [ACK_KIND, sender_tag, sequence, bitmap] => {
+                // Acknowledgements of the payloads of other nodes sharing the channel
+                if *sender_tag != self.sender_tag {
+                    return Vec::new();
+                }
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                self.acknowledge(&mut state, *sequence, now);
+                for offset in (1..=ACK_BITMAP_LENGTH).filter(|offset| bitmap & 1 << (offset - 1) != 0) {
+                    self.acknowledge(&mut state, sequence.wrapping_sub(offset), now);
+                }
+                Vec::new()
+            }
+            // Best effort payloads start with the STX of their first MAVLink frame
+            _ => vec![payload],
+        }
+    }
+
+    fn overhead(&self) -> usize {
+        ARQ_HEADER_LENGTH
+    }
+
+    fn poll_transmit(&self, allows: &mut dyn FnMut(usize) -> bool) -> Vec<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        self.expire(&mut state, now);
+
+        let mut payloads = Vec::new();
+        let mut pending_acks = Vec::new();
+        for (sender_tag, sequence, due) in std::mem::take(&mut state.pending_acks) {
+            if due <= now {
+                let ack = self.encode_ack(&state, sender_tag, sequence);
+                if allows(ack.len()) {
+                    payloads.push(ack);
+                    continue;
+                }
+            }
+            pending_acks.push((sender_tag, sequence, due));
+        }
+        state.pending_acks = pending_acks;
+        metrics().add_counter(ARQ_ACKS_SENT_METRIC, &[], payloads.len() as u64);
+
+        let mut due: Vec<(u8, Instant)> = state
+            .unacknowledged
+            .iter()
+            .filter(|(_, entry)| entry.due <= now)
+            .map(|(sequence, entry)| (*sequence, entry.due))
+            .collect();
+        due.sort_by_key(|(_, due)| *due);
+        for (sequence, _) in due {
+            if !allows(state.unacknowledged[&sequence].payload.len()) {
+                continue;
+            }
+            let retransmissions = state.unacknowledged[&sequence].retransmissions + 1;
+            let timeout = self.backoff(&state, retransmissions);
+            let entry = state.unacknowledged.get_mut(&sequence).unwrap();
+            entry.retransmissions = retransmissions;
+            entry.sent_at = now;
+            entry.due = now + timeout;
+            payloads.push(entry.payload.clone());
+            metrics().increment_counter(ARQ_RETRANSMISSIONS_METRIC, &[]);
+            log_debug_arq_retransmission(sequence, retransmissions, timeout);
+        }
+        payloads
+    }
+
+    fn next_transmit(&self) -> Option<Instant> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        self.expire(&mut state, now);
+        let acks_due = state.pending_acks.iter().map(|(_, _, due)| *due);
+        let retransmissions_due = state.unacknowledged.values().map(|entry| entry.due);
+        acks_due.chain(retransmissions_due).min()
+    }
+}
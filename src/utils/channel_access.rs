@@ -0,0 +1,136 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::time::sleep;
+
+use super::config::ChannelAccessSection;
+use super::logging_utils::{log_channel_access_failed, log_debug_channel_busy};
+use super::metrics::metrics;
+use super::types::MavFramePacket;
+use crate::define_struct_with_defaults;
+use crate::driver::{Driver, DriverError, DriverWrapper};
+
+pub const CHANNEL_BUSY_DEFERRED_METRIC: &str = "channel_busy_deferred_total";
+pub const CHANNEL_BUSY_DROPPED_METRIC: &str = "channel_busy_dropped_total";
+
+define_struct_with_defaults! {
+    ChannelAccessOptionalConfig, ChannelAccessConfig {
+        // Channel checks before the packets are dropped, the first one included
+        max_attempts: u32 = 5,
+        // Upper bound of the random wait before the first check, so nodes woken by the same packet
+        // do not check the channel at the same time
+        initial_backoff_ms: u64 = 20,
+        // Upper bound of the random wait after a busy check, doubling with each busy check up to
+        // `max_backoff_ms`
+        min_backoff_ms: u64 = 50,
+        max_backoff_ms: u64 = 1000,
+    }
+}
+
+impl From<&ChannelAccessSection> for ChannelAccessOptionalConfig {
+    fn from(section: &ChannelAccessSection) -> Self {
+        Self {
+            max_attempts: section.max_attempts,
+            initial_backoff_ms: section.initial_backoff_ms,
+            min_backoff_ms: section.min_backoff_ms,
+            max_backoff_ms: section.max_backoff_ms,
+        }
+    }
+}
+
+/// Driver wrapper listening before talking, so LoRa nodes sharing a channel do not transmit over each other.
+///
+/// Before each batch the channel is checked through `Driver::channel_busy`, which is channel
+/// activity detection on the SX126x/SX127x and RSSI sensing on the E22. A busy channel is checked
+/// again after a random backoff, and the batch fails with `DriverError::Busy` once `max_attempts`
+/// checks found it busy.
+pub struct ChannelAccessDriver {
+    driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    min_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl ChannelAccessDriver {
+    pub fn new(
+        driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+        config: Option<ChannelAccessOptionalConfig>,
+    ) -> Self {
+        let config = config.unwrap_or_default().build();
+
+        Self {
+            driver,
+            max_attempts: config.max_attempts,
+            initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+            min_backoff: Duration::from_millis(config.min_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+        }
+    }
+
+    /// Upper bound of the random wait after the given number of busy checks
+    fn backoff_window(&self, busy_checks: u32) -> Duration {
+        self.min_backoff
+            .saturating_mul(2u32.saturating_pow(busy_checks.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+
+    /// Waits until the channel is clear, false if it stayed busy for all attempts
+    async fn acquire_channel(&self) -> Result<bool, DriverError> {
+        let driver = self.driver.to_string();
+        sleep(random_duration(self.initial_backoff)).await;
+        for attempt in 1..=self.max_attempts {
+            if !self.driver.channel_busy().await? {
+                return Ok(true);
+            }
+            if attempt == self.max_attempts {
+                break;
+            }
+            let backoff = random_duration(self.backoff_window(attempt));
+            metrics().increment_counter(CHANNEL_BUSY_DEFERRED_METRIC, &[("driver", &driver)]);
+            log_debug_channel_busy(&driver, attempt, backoff);
+            sleep(backoff).await;
+        }
+        Ok(false)
+    }
+}
+
+fn random_duration(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    Duration::from_micros(rand::thread_rng().gen_range(0..=max.as_micros() as u64))
+}
+
+impl Display for ChannelAccessDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.driver)
+    }
+}
+
+#[async_trait::async_trait]
+impl DriverWrapper<MavFramePacket> for ChannelAccessDriver {
+    fn inner(&self) -> &Arc<dyn Driver<MavFramePacket> + Send + Sync> {
+        &self.driver
+    }
+
+    async fn send(&self, packet_to_send: &MavFramePacket) -> Result<(), DriverError> {
+        DriverWrapper::send_batch(self, std::slice::from_ref(packet_to_send)).await
+    }
+
+    async fn send_batch(&self, packets_to_send: &[MavFramePacket]) -> Result<(), DriverError> {
+        if !self.acquire_channel().await? {
+            let driver = self.driver.to_string();
+            metrics().add_counter(
+                CHANNEL_BUSY_DROPPED_METRIC,
+                &[("driver", &driver)],
+                packets_to_send.len() as u64,
+            );
+            log_channel_access_failed(&driver, self.max_attempts, packets_to_send.len());
+            return Err(DriverError::Busy("channel stayed busy".to_string()));
+        }
+        self.driver.send_batch(packets_to_send).await
+    }
+}
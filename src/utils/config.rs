@@ -0,0 +1,958 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use mavlink::ardupilotmega::MavMessage;
+use mavlink::Message;
+use serde::Deserialize;
+
+use super::filter::{FilterChain, MessageFilter};
+use super::fragmentation::FRAGMENT_HEADER_LENGTH;
+use super::mavlink_utils::{node_system_id, parse_secret_key};
+use super::types::{
+    MavFramePacket, NodeType, Priority, SerialFlowControl, SerialParity, TdmaTimeSource, UdpMode, WebSocketEncoding,
+    WebSocketRole,
+};
+
+const DEFAULT_CHANNEL_SIZE: usize = 100;
+
+pub const LORA_SX1276_CS_PIN: u8 = 25;
+pub const LORA_SX1276_RESET_PIN: u8 = 17;
+pub const LORA_SX1276_DIO0_PIN: u8 = 4;
+
+pub const LORA_SX1262_CS_PIN: u8 = 21;
+pub const LORA_SX1262_RESET_PIN: u8 = 18;
+pub const LORA_SX1262_DIO1_PIN: u8 = 16;
+pub const LORA_SX1262_DIO4_PIN: u8 = 6;
+pub const LORA_SX1262_BUSY_PIN: u8 = 20;
+
+pub const LORA_E22_M0_PIN: u8 = 22;
+pub const LORA_E22_M1_PIN: u8 = 27;
+pub const LORA_E22_AUX_PIN: u8 = 7;
+
+/// Highest BCM GPIO number exposed on the Raspberry Pi header
+const MAX_GPIO_PIN: u8 = 27;
+const LORA_BANDWIDTHS_HZ: [u32; 10] = [
+    7_810, 10_420, 15_630, 20_830, 31_250, 41_670, 62_500, 125_000, 250_000, 500_000,
+];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid { field: String, reason: String },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "failed to read node config: {}", err),
+            ConfigError::Parse(err) => write!(f, "failed to parse node config: {}", err),
+            ConfigError::Invalid { field, reason } => write!(f, "invalid node config, {}: {}", field, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Node configuration, usually loaded from a TOML file so one build can run on every board of the fleet
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    pub node: NodeSection,
+    pub udp: Option<UdpSection>,
+    /// Flight controller on a serial port, the autopilot link instead of UDP
+    pub serial: Option<SerialSection>,
+    /// Ground stations connecting over TCP, as an additional link
+    pub tcp_server: Option<TcpServerSection>,
+    /// TCP MAVLink endpoint to connect to, as an additional link
+    pub tcp_client: Option<TcpClientSection>,
+    /// Browser dashboards exchanging MAVLink over WebSocket, as an additional link
+    pub websocket: Option<WebSocketSection>,
+    pub lora_sx1276_spi: Option<LoRaSpiSection<Sx1276PinMap>>,
+    pub lora_sx1262_spi: Option<LoRaSpiSection<Sx1262PinMap>>,
+    pub lora_sx1262_uart: Option<LoRaSx1262UartSection>,
+    /// Packet filters keyed by the name of the driver or link they apply to
+    #[serde(default)]
+    pub filters: HashMap<String, MessageFilter>,
+    /// Ordering of the packets waiting for a LoRa SPI link, FIFO when unset
+    pub scheduler: Option<SchedulerSection>,
+    /// EU868 duty cycle enforcement on a LoRa SPI radio, disabled when unset
+    pub duty_cycle: Option<DutyCycleSection>,
+    /// Fragmentation of frames larger than the LoRa payload, both ends of the link need the same setting
+    pub fragmentation: Option<FragmentationSection>,
+    /// Re-initialization of a LoRa SPI radio that stopped raising IRQs, disabled when unset
+    pub watchdog: Option<WatchdogSection>,
+    /// Listen before talk on the LoRa driver, disabled when unset
+    pub channel_access: Option<ChannelAccessSection>,
+    /// Time slots of the nodes sharing a LoRa channel through SPI radios, disabled when unset
+    pub tdma: Option<TdmaSection>,
+    /// Acknowledged delivery of critical messages over a LoRa SPI link, both ends of the link need the
+    /// same setting
+    pub arq: Option<ArqSection>,
+    /// MAVLink v2 signing of the frames on the LoRa link, the other links stay unsigned
+    pub signing: Option<SigningSection>,
+    /// Authenticated encryption of the LoRa payloads, every node on the channel needs the same key
+    pub encryption: Option<EncryptionSection>,
+    /// Prometheus scrape endpoint on `/metrics`, disabled when unset
+    pub metrics: Option<MetricsSection>,
+    /// RADIO_STATUS reports of the LoRa link to the autopilot and ground station links, both ends of
+    /// the link need it for the remote fields
+    pub radio_status: Option<RadioStatusSection>,
+    /// Adaptive data rate of a LoRa SPI link between two nodes, starting from and falling back to the
+    /// configured modulation. Both ends of the link need the same setting, the gateway proposes the
+    /// changes unless `controller` says otherwise.
+    pub adr: Option<AdrSection>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeSection {
+    pub node_type: NodeType,
+    #[serde(default = "default_channel_size")]
+    pub channel_size: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UdpSection {
+    pub addr: String,
+    #[serde(default)]
+    pub mode: UdpMode,
+    /// Target of a client, optional when `unicast_addrs` is set
+    pub dest_addr: Option<String>,
+    #[serde(default)]
+    pub broadcast: bool,
+    /// Additional targets of a client
+    #[serde(default)]
+    pub unicast_addrs: Vec<String>,
+    /// How long a server keeps replying to a peer it no longer hears from
+    pub peer_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpServerSection {
+    pub addr: Option<String>,
+    pub write_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpClientSection {
+    pub addr: Option<String>,
+    pub initial_backoff_ms: Option<u64>,
+    pub max_backoff_ms: Option<u64>,
+}
+
+/// `url` is only used by clients and `addr` by servers
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebSocketSection {
+    pub role: Option<WebSocketRole>,
+    pub url: Option<String>,
+    pub addr: Option<String>,
+    pub encoding: Option<WebSocketEncoding>,
+    pub initial_backoff_ms: Option<u64>,
+    pub max_backoff_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SerialSection {
+    pub path: Option<String>,
+    pub baud_rate: Option<u32>,
+    pub flow_control: Option<SerialFlowControl>,
+    pub parity: Option<SerialParity>,
+    pub reconnect_interval_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sx1276PinMap {
+    pub cs: u8,
+    pub reset: u8,
+    pub dio0: u8,
+}
+
+impl Default for Sx1276PinMap {
+    fn default() -> Self {
+        Self {
+            cs: LORA_SX1276_CS_PIN,
+            reset: LORA_SX1276_RESET_PIN,
+            dio0: LORA_SX1276_DIO0_PIN,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sx1262PinMap {
+    pub cs: u8,
+    pub reset: u8,
+    pub dio1: u8,
+    pub dio4: u8,
+    pub busy: u8,
+}
+
+impl Default for Sx1262PinMap {
+    fn default() -> Self {
+        Self {
+            cs: LORA_SX1262_CS_PIN,
+            reset: LORA_SX1262_RESET_PIN,
+            dio1: LORA_SX1262_DIO1_PIN,
+            dio4: LORA_SX1262_DIO4_PIN,
+            busy: LORA_SX1262_BUSY_PIN,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct E22PinMap {
+    pub m0: u8,
+    pub m1: u8,
+    pub aux: u8,
+}
+
+impl Default for E22PinMap {
+    fn default() -> Self {
+        Self {
+            m0: LORA_E22_M0_PIN,
+            m1: LORA_E22_M1_PIN,
+            aux: LORA_E22_AUX_PIN,
+        }
+    }
+}
+
+/// Modulation and packet settings shared by the SPI LoRa drivers, unset fields keep the driver defaults.
+/// The radios only differ in their pin map `P`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoRaSpiSection<P> {
+    pub spreading_factor: Option<u8>,
+    pub bandwidth_hz: Option<u32>,
+    pub coding_rate: Option<u8>,
+    pub frequency_hz: Option<u32>,
+    pub max_payload_length: Option<u8>,
+    pub pack_frames: Option<bool>,
+    pub tx_power: Option<i32>,
+    pub tx_boost: Option<bool>,
+    pub preamble_length: Option<u16>,
+    pub implicit_header: Option<bool>,
+    pub crc_enabled: Option<bool>,
+    pub iq_inverted: Option<bool>,
+    #[serde(default)]
+    pub pins: P,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoRaSx1262UartSection {
+    pub serial_port: Option<String>,
+    pub frequency_mhz: Option<u32>,
+    pub address: Option<u16>,
+    pub target_address: Option<u16>,
+    pub net_id: Option<u16>,
+    pub power_dbm: Option<u8>,
+    pub air_speed: Option<u32>,
+    pub package_size: Option<u16>,
+    pub crypt: Option<u16>,
+    /// Channel noise above which the channel counts as busy for listen before talk
+    pub lbt_rssi_threshold_dbm: Option<i16>,
+    #[serde(default)]
+    pub pins: E22PinMap,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DutyCycleSection {
+    pub window_s: Option<u64>,
+    pub max_defer_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchdogSection {
+    pub rx_stall_timeout_ms: Option<u64>,
+    pub tx_done_margin_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelAccessSection {
+    pub max_attempts: Option<u32>,
+    pub initial_backoff_ms: Option<u64>,
+    pub min_backoff_ms: Option<u64>,
+    pub max_backoff_ms: Option<u64>,
+}
+
+/// Slot of a node taken from its position in `slot_table`, which needs to list its system ID, or from
+/// the system ID modulo `slot_count` without a table
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TdmaSection {
+    pub slot_count: Option<u32>,
+    /// System IDs in slot order, also sets the slot count
+    #[serde(default)]
+    pub slot_table: Vec<u8>,
+    /// System ID of this node, defaults to the one of its node type
+    pub system_id: Option<u8>,
+    /// Defaults to the time on air of a full payload plus the guard intervals
+    pub slot_duration_ms: Option<u64>,
+    pub guard_margin_ms: Option<u64>,
+    /// Same on every node, beacons are only sent and followed with the `Beacon` time source
+    pub time_source: Option<TdmaTimeSource>,
+    pub beacon_interval_ms: Option<u64>,
+    pub beacon_system_id: Option<u8>,
+}
+
+/// Sender tag defaults to the system ID of the node type
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArqSection {
+    /// MAVLink message names, replaces the default list of commands, mission items and parameter writes
+    pub reliable_messages: Option<Vec<String>>,
+    pub max_retransmissions: Option<u32>,
+    pub initial_timeout_ms: Option<u64>,
+    pub ack_delay_ms: Option<u64>,
+    pub min_timeout_ms: Option<u64>,
+    pub max_timeout_ms: Option<u64>,
+    pub sender_tag: Option<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SigningSection {
+    /// 64 hexadecimal digits, shared by every node of the link
+    pub secret_key: String,
+    pub link_id: Option<u8>,
+    pub require_signatures: Option<bool>,
+    pub replay_window_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsSection {
+    pub addr: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RadioStatusSection {
+    pub interval_ms: Option<u64>,
+    pub peer_interval_ms: Option<u64>,
+    pub stale_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdrSection {
+    pub controller: Option<bool>,
+    pub min_spreading_factor: Option<u8>,
+    pub max_bandwidth_hz: Option<u32>,
+    pub window: Option<usize>,
+    pub snr_margin_db: Option<f64>,
+    pub min_snr_margin_db: Option<f64>,
+    pub max_loss: Option<f64>,
+    pub hold_ms: Option<u64>,
+    pub proposal_timeout_ms: Option<u64>,
+    pub max_proposal_attempts: Option<u32>,
+    pub ack_delay_ms: Option<u64>,
+    pub fallback_timeout_ms: Option<u64>,
+}
+
+/// Sender ID defaults to the system ID of the node type
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionSection {
+    /// 64 hexadecimal digits
+    pub key: String,
+    pub sender_id: Option<u8>,
+    pub replay_window_ms: Option<u64>,
+}
+
+/// Fragment length defaults to the payload length of the configured LoRa driver, sender tag to the system
+/// ID of the node type
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FragmentationSection {
+    pub max_fragment_length: Option<usize>,
+    pub reassembly_timeout_ms: Option<u64>,
+    pub sender_tag: Option<u8>,
+}
+
+/// Scheduling policy of a LoRa link, message types are referred to by their MAVLink name
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchedulerSection {
+    pub max_queue_length: Option<usize>,
+    /// Highest rate at which each source may send a message type over the link
+    #[serde(default)]
+    pub rate_limits_hz: HashMap<String, f64>,
+    /// Priority class of each message type, unlisted types are `Normal`.
+    /// Replaces the default list, which puts heartbeats, commands and mission items first.
+    pub priorities: Option<HashMap<String, Priority>>,
+    /// Telemetry streams where a queued packet is replaced by a newer one of the same source
+    #[serde(default)]
+    pub coalesce: HashSet<String>,
+}
+
+impl SchedulerSection {
+    /// Every message name the section refers to
+    pub fn message_names(&self) -> impl Iterator<Item = &String> {
+        self.rate_limits_hz
+            .keys()
+            .chain(self.priorities.iter().flat_map(|priorities| priorities.keys()))
+            .chain(self.coalesce.iter())
+    }
+}
+
+fn default_channel_size() -> usize {
+    DEFAULT_CHANNEL_SIZE
+}
+
+impl NodeConfig {
+    /// Reads, parses and validates a node config file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::from_toml(&content)
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        let config: NodeConfig = toml::from_str(content).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Filter chain of a driver or link, empty when the config has no filter for it
+    pub fn filter_chain(&self, name: &str) -> FilterChain<MavFramePacket> {
+        self.filter_chain_or(name, None)
+    }
+
+    /// Filter chain of a driver or link, with `default` when the config has no filter for it
+    pub fn filter_chain_or(&self, name: &str, default: Option<MessageFilter>) -> FilterChain<MavFramePacket> {
+        let chain = FilterChain::new(name);
+        match self.filters.get(name).cloned().or(default) {
+            Some(filter) => chain.with_filter(filter),
+            None => chain,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.node.channel_size == 0 {
+            return Err(invalid("node.channel_size", "must be greater than 0"));
+        }
+
+        match (&self.udp, &self.serial) {
+            (None, None) => return Err(invalid("node", "needs [udp] or [serial] for the autopilot link")),
+            (Some(_), Some(_)) => {
+                return Err(invalid(
+                    "serial",
+                    "conflicts with [udp], a node has a single autopilot link",
+                ))
+            }
+            _ => {}
+        }
+
+        if let Some(udp) = &self.udp {
+            validate_socket_addr("udp.addr", &udp.addr)?;
+            if let Some(dest_addr) = &udp.dest_addr {
+                validate_socket_addr("udp.dest_addr", dest_addr)?;
+            }
+            for (index, unicast_addr) in udp.unicast_addrs.iter().enumerate() {
+                validate_socket_addr(&format!("udp.unicast_addrs[{}]", index), unicast_addr)?;
+            }
+            if udp.mode == UdpMode::Client && udp.dest_addr.is_none() && udp.unicast_addrs.is_empty() {
+                return Err(invalid("udp.dest_addr", "a client needs dest_addr or unicast_addrs"));
+            }
+            if udp.peer_timeout_ms == Some(0) {
+                return Err(invalid("udp.peer_timeout_ms", "must be greater than 0"));
+            }
+        }
+
+        if let Some(tcp_server) = &self.tcp_server {
+            if let Some(addr) = &tcp_server.addr {
+                validate_socket_addr("tcp_server.addr", addr)?;
+            }
+            validate_range(
+                "tcp_server",
+                "write_timeout_ms",
+                tcp_server.write_timeout_ms,
+                1..=60_000,
+            )?;
+        }
+
+        if let Some(tcp_client) = &self.tcp_client {
+            if let Some(addr) = &tcp_client.addr {
+                validate_socket_addr("tcp_client.addr", addr)?;
+            }
+            validate_range(
+                "tcp_client",
+                "initial_backoff_ms",
+                tcp_client.initial_backoff_ms,
+                1..=60_000,
+            )?;
+            validate_range("tcp_client", "max_backoff_ms", tcp_client.max_backoff_ms, 1..=600_000)?;
+        }
+
+        if let Some(websocket) = &self.websocket {
+            if let Some(url) = &websocket.url {
+                if !url.starts_with("ws://") {
+                    return Err(invalid("websocket.url", &format!("{} is not a ws:// URL", url)));
+                }
+            }
+            if let Some(addr) = &websocket.addr {
+                validate_socket_addr("websocket.addr", addr)?;
+            }
+            validate_range(
+                "websocket",
+                "initial_backoff_ms",
+                websocket.initial_backoff_ms,
+                1..=60_000,
+            )?;
+            validate_range("websocket", "max_backoff_ms", websocket.max_backoff_ms, 1..=600_000)?;
+        }
+
+        if let Some(serial) = &self.serial {
+            if serial.path.as_ref().is_some_and(|path| path.is_empty()) {
+                return Err(invalid("serial.path", "must not be empty"));
+            }
+            validate_range("serial", "baud_rate", serial.baud_rate, 50..=4_000_000)?;
+            validate_range(
+                "serial",
+                "reconnect_interval_ms",
+                serial.reconnect_interval_ms,
+                1..=60_000,
+            )?;
+        }
+
+        let lora_sections: Vec<&str> = [
+            ("lora_sx1276_spi", self.lora_sx1276_spi.is_some()),
+            ("lora_sx1262_spi", self.lora_sx1262_spi.is_some()),
+            ("lora_sx1262_uart", self.lora_sx1262_uart.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(section, present)| present.then_some(section))
+        .collect();
+        match lora_sections[..] {
+            [] => {
+                return Err(invalid(
+                    "node",
+                    "needs [lora_sx1276_spi], [lora_sx1262_spi] or [lora_sx1262_uart] for the LoRa link",
+                ))
+            }
+            [first, second, ..] => {
+                return Err(invalid(
+                    second,
+                    &format!("conflicts with [{}], a node drives a single LoRa radio", first),
+                ))
+            }
+            _ => {}
+        }
+
+        if let Some(lora) = &self.lora_sx1276_spi {
+            let section = "lora_sx1276_spi";
+            validate_lora_modulation(
+                section,
+                6..=12,
+                lora.spreading_factor,
+                lora.bandwidth_hz,
+                lora.coding_rate,
+                lora.frequency_hz,
+                lora.max_payload_length,
+            )?;
+            validate_range(section, "tx_power", lora.tx_power, 2..=20)?;
+            validate_pins(
+                section,
+                &[
+                    ("cs", lora.pins.cs),
+                    ("reset", lora.pins.reset),
+                    ("dio0", lora.pins.dio0),
+                ],
+            )?;
+        }
+
+        if let Some(lora) = &self.lora_sx1262_spi {
+            let section = "lora_sx1262_spi";
+            validate_lora_modulation(
+                section,
+                5..=12,
+                lora.spreading_factor,
+                lora.bandwidth_hz,
+                lora.coding_rate,
+                lora.frequency_hz,
+                lora.max_payload_length,
+            )?;
+            validate_range(section, "tx_power", lora.tx_power, -9..=22)?;
+            validate_pins(
+                section,
+                &[
+                    ("cs", lora.pins.cs),
+                    ("reset", lora.pins.reset),
+                    ("dio1", lora.pins.dio1),
+                    ("dio4", lora.pins.dio4),
+                    ("busy", lora.pins.busy),
+                ],
+            )?;
+        }
+
+        if let Some(lora) = &self.lora_sx1262_uart {
+            let section = "lora_sx1262_uart";
+            if let Some(frequency_mhz) = lora.frequency_mhz {
+                if !(410..=493).contains(&frequency_mhz) && !(850..=930).contains(&frequency_mhz) {
+                    return Err(invalid(
+                        &format!("{}.frequency_mhz", section),
+                        &format!("must be within 410-493 or 850-930 MHz, got {}", frequency_mhz),
+                    ));
+                }
+            }
+            validate_one_of(section, "power_dbm", lora.power_dbm, &[10, 13, 17, 22])?;
+            validate_one_of(
+                section,
+                "air_speed",
+                lora.air_speed,
+                &[1200, 2400, 4800, 9600, 19200, 38400, 62500],
+            )?;
+            validate_one_of(section, "package_size", lora.package_size, &[32, 64, 128, 240])?;
+            validate_range(
+                section,
+                "lbt_rssi_threshold_dbm",
+                lora.lbt_rssi_threshold_dbm,
+                -140..=-40,
+            )?;
+            validate_pins(
+                section,
+                &[("m0", lora.pins.m0), ("m1", lora.pins.m1), ("aux", lora.pins.aux)],
+            )?;
+        }
+
+        if let Some(duty_cycle) = &self.duty_cycle {
+            self.validate_lora_spi("duty_cycle", "other radios have no time on air to book")?;
+            validate_range("duty_cycle", "window_s", duty_cycle.window_s, 1..=86_400)?;
+        }
+
+        if let Some(watchdog) = &self.watchdog {
+            self.validate_lora_spi("watchdog", "other radios raise no IRQs to watch")?;
+            validate_range(
+                "watchdog",
+                "rx_stall_timeout_ms",
+                watchdog.rx_stall_timeout_ms,
+                1000..=3_600_000,
+            )?;
+            validate_range(
+                "watchdog",
+                "tx_done_margin_ms",
+                watchdog.tx_done_margin_ms,
+                100..=60_000,
+            )?;
+        }
+
+        if let Some(channel_access) = &self.channel_access {
+            let section = "channel_access";
+            validate_range(section, "max_attempts", channel_access.max_attempts, 1..=100)?;
+            validate_range(
+                section,
+                "initial_backoff_ms",
+                channel_access.initial_backoff_ms,
+                0..=10_000,
+            )?;
+            validate_range(section, "min_backoff_ms", channel_access.min_backoff_ms, 1..=10_000)?;
+            validate_range(section, "max_backoff_ms", channel_access.max_backoff_ms, 1..=60_000)?;
+            if let (Some(min_backoff_ms), Some(max_backoff_ms)) =
+                (channel_access.min_backoff_ms, channel_access.max_backoff_ms)
+            {
+                if min_backoff_ms > max_backoff_ms {
+                    return Err(invalid(
+                        "channel_access.max_backoff_ms",
+                        &format!("must not be below min_backoff_ms ({})", min_backoff_ms),
+                    ));
+                }
+            }
+        }
+
+        if let Some(tdma) = &self.tdma {
+            let section = "tdma";
+            self.validate_lora_spi(section, "other radios run full duplex, without a slot scheduler")?;
+            validate_range(section, "slot_count", tdma.slot_count, 1..=64)?;
+            validate_range(section, "slot_duration_ms", tdma.slot_duration_ms, 10..=60_000)?;
+            validate_range(section, "guard_margin_ms", tdma.guard_margin_ms, 0..=1000)?;
+            validate_range(section, "beacon_interval_ms", tdma.beacon_interval_ms, 0..=600_000)?;
+            if tdma.time_source == Some(TdmaTimeSource::Gps) {
+                if tdma.beacon_interval_ms.is_some() {
+                    return Err(invalid(
+                        "tdma.beacon_interval_ms",
+                        "beacons are not used with the Gps time source",
+                    ));
+                }
+                if tdma.beacon_system_id.is_some() {
+                    return Err(invalid(
+                        "tdma.beacon_system_id",
+                        "beacons are not used with the Gps time source",
+                    ));
+                }
+            }
+            if tdma.slot_table.len() > 64 {
+                return Err(invalid("tdma.slot_table", "must not list more than 64 slots"));
+            }
+            if let Some(slot_count) = tdma.slot_count {
+                if !tdma.slot_table.is_empty() && tdma.slot_table.len() != slot_count as usize {
+                    return Err(invalid(
+                        "tdma.slot_table",
+                        &format!(
+                            "lists {} slots but slot_count is {}, set only one of them or make them agree",
+                            tdma.slot_table.len(),
+                            slot_count
+                        ),
+                    ));
+                }
+            }
+            let mut listed = HashSet::new();
+            if let Some(system_id) = tdma.slot_table.iter().find(|system_id| !listed.insert(**system_id)) {
+                return Err(invalid(
+                    "tdma.slot_table",
+                    &format!("lists system ID {} more than once", system_id),
+                ));
+            }
+            let system_id = tdma.system_id.unwrap_or_else(|| node_system_id(self.node.node_type));
+            if !tdma.slot_table.is_empty() && !listed.contains(&system_id) {
+                return Err(invalid(
+                    "tdma.slot_table",
+                    &format!("does not list system ID {} of this node", system_id),
+                ));
+            }
+        }
+
+        if let Some(arq) = &self.arq {
+            let section = "arq";
+            self.validate_lora_spi(
+                section,
+                "other radios run full duplex, which does not transmit the acknowledgements of the link layers",
+            )?;
+            validate_range(section, "max_retransmissions", arq.max_retransmissions, 0..=10)?;
+            validate_range(section, "initial_timeout_ms", arq.initial_timeout_ms, 10..=60_000)?;
+            validate_range(section, "ack_delay_ms", arq.ack_delay_ms, 0..=1000)?;
+            validate_range(section, "min_timeout_ms", arq.min_timeout_ms, 10..=60_000)?;
+            validate_range(section, "max_timeout_ms", arq.max_timeout_ms, 10..=600_000)?;
+            if let (Some(min_timeout_ms), Some(max_timeout_ms)) = (arq.min_timeout_ms, arq.max_timeout_ms) {
+                if min_timeout_ms > max_timeout_ms {
+                    return Err(invalid(
+                        "arq.max_timeout_ms",
+                        &format!("must not be below min_timeout_ms ({})", min_timeout_ms),
+                    ));
+                }
+            }
+            for message_name in arq.reliable_messages.iter().flatten() {
+                validate_message_name("arq.reliable_messages", message_name)?;
+            }
+        }
+
+        if let Some(signing) = &self.signing {
+            if parse_secret_key(&signing.secret_key).is_none() {
+                return Err(invalid("signing.secret_key", "must be 64 hexadecimal digits"));
+            }
+            validate_range("signing", "replay_window_ms", signing.replay_window_ms, 1000..=600_000)?;
+        }
+
+        if let Some(addr) = self.metrics.as_ref().and_then(|metrics| metrics.addr.as_ref()) {
+            validate_socket_addr("metrics.addr", addr)?;
+        }
+
+        if let Some(radio_status) = &self.radio_status {
+            let section = "radio_status";
+            validate_range(section, "interval_ms", radio_status.interval_ms, 100..=60_000)?;
+            validate_range(section, "peer_interval_ms", radio_status.peer_interval_ms, 0..=600_000)?;
+            validate_range(
+                section,
+                "stale_timeout_ms",
+                radio_status.stale_timeout_ms,
+                1000..=600_000,
+            )?;
+        }
+
+        if let Some(adr) = &self.adr {
+            let section = "adr";
+            self.validate_lora_spi(section, "other radios cannot change their modulation")?;
+            validate_range(section, "min_spreading_factor", adr.min_spreading_factor, 5..=12)?;
+            validate_one_of(section, "max_bandwidth_hz", adr.max_bandwidth_hz, &LORA_BANDWIDTHS_HZ)?;
+            validate_range(section, "window", adr.window, 1..=256)?;
+            validate_range(section, "snr_margin_db", adr.snr_margin_db, 0.0..=30.0)?;
+            validate_range(section, "min_snr_margin_db", adr.min_snr_margin_db, 0.0..=30.0)?;
+            validate_range(section, "max_loss", adr.max_loss, 0.0..=1.0)?;
+            validate_range(section, "hold_ms", adr.hold_ms, 0..=600_000)?;
+            validate_range(section, "proposal_timeout_ms", adr.proposal_timeout_ms, 10..=60_000)?;
+            validate_range(section, "max_proposal_attempts", adr.max_proposal_attempts, 1..=10)?;
+            validate_range(section, "ack_delay_ms", adr.ack_delay_ms, 0..=1000)?;
+            validate_range(
+                section,
+                "fallback_timeout_ms",
+                adr.fallback_timeout_ms,
+                1000..=3_600_000,
+            )?;
+            if let (Some(snr_margin_db), Some(min_snr_margin_db)) = (adr.snr_margin_db, adr.min_snr_margin_db) {
+                if min_snr_margin_db > snr_margin_db {
+                    return Err(invalid(
+                        "adr.min_snr_margin_db",
+                        &format!("must not be above snr_margin_db ({})", snr_margin_db),
+                    ));
+                }
+            }
+        }
+
+        if let Some(encryption) = &self.encryption {
+            if parse_secret_key(&encryption.key).is_none() {
+                return Err(invalid("encryption.key", "must be 64 hexadecimal digits"));
+            }
+            validate_range(
+                "encryption",
+                "replay_window_ms",
+                encryption.replay_window_ms,
+                1000..=600_000,
+            )?;
+            if self.lora_sx1262_uart.is_some() && self.fragmentation.is_none() {
+                // The module splits longer payloads into packets of its own, which do not decrypt one by one
+                return Err(invalid(
+                    "encryption",
+                    "needs [fragmentation] with lora_sx1262_uart, so every payload fits one module packet",
+                ));
+            }
+        }
+
+        if let Some(fragmentation) = &self.fragmentation {
+            validate_range(
+                "fragmentation",
+                "max_fragment_length",
+                fragmentation.max_fragment_length,
+                FRAGMENT_HEADER_LENGTH + 1..=255,
+            )?;
+            validate_range(
+                "fragmentation",
+                "reassembly_timeout_ms",
+                fragmentation.reassembly_timeout_ms,
+                1..=60_000,
+            )?;
+        }
+
+        if let Some(scheduler) = &self.scheduler {
+            self.validate_lora_spi("scheduler", "other radios run full duplex, without a scheduler")?;
+            if scheduler.max_queue_length == Some(0) {
+                return Err(invalid("scheduler.max_queue_length", "must be greater than 0"));
+            }
+            for (name, rate) in &scheduler.rate_limits_hz {
+                if rate.is_nan() || *rate <= 0.0 {
+                    return Err(invalid(
+                        &format!("scheduler.rate_limits_hz.{}", name),
+                        &format!("must be greater than 0, got {}", rate),
+                    ));
+                }
+            }
+            for message_name in scheduler.message_names() {
+                validate_message_name("scheduler", message_name)?;
+            }
+        }
+
+        for (name, filter) in &self.filters {
+            for (index, rule) in filter.rules.iter().enumerate() {
+                for message_name in rule.message_names.iter().flatten() {
+                    validate_message_name(
+                        &format!("filters.{}.rules[{}].message_names", name, index),
+                        message_name,
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sections driving the radio through the half duplex network only apply to the LoRa SPI radios
+    fn validate_lora_spi(&self, section: &str, reason: &str) -> Result<(), ConfigError> {
+        if self.lora_sx1276_spi.is_some() || self.lora_sx1262_spi.is_some() {
+            return Ok(());
+        }
+        Err(invalid(
+            section,
+            &format!("needs [lora_sx1276_spi] or [lora_sx1262_spi], {}", reason),
+        ))
+    }
+}
+
+fn invalid(field: &str, reason: &str) -> ConfigError {
+    ConfigError::Invalid {
+        field: field.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+fn validate_socket_addr(field: &str, addr: &str) -> Result<(), ConfigError> {
+    addr.parse::<SocketAddr>()
+        .map(|_| ())
+        .map_err(|err| invalid(field, &format!("{} is not a socket address, {}", addr, err)))
+}
+
+fn validate_range<T: PartialOrd + Display>(
+    section: &str,
+    field: &str,
+    value: Option<T>,
+    range: std::ops::RangeInclusive<T>,
+) -> Result<(), ConfigError> {
+    match value {
+        Some(value) if !range.contains(&value) => Err(invalid(
+            &format!("{}.{}", section, field),
+            &format!("must be between {} and {}, got {}", range.start(), range.end(), value),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn validate_one_of<T: PartialEq + Display + std::fmt::Debug>(
+    section: &str,
+    field: &str,
+    value: Option<T>,
+    allowed: &[T],
+) -> Result<(), ConfigError> {
+    match value {
+        Some(value) if !allowed.contains(&value) => Err(invalid(
+            &format!("{}.{}", section, field),
+            &format!("must be one of {:?}, got {}", allowed, value),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn validate_lora_modulation(
+    section: &str,
+    spreading_factors: std::ops::RangeInclusive<u8>,
+    spreading_factor: Option<u8>,
+    bandwidth_hz: Option<u32>,
+    coding_rate: Option<u8>,
+    frequency_hz: Option<u32>,
+    max_payload_length: Option<u8>,
+) -> Result<(), ConfigError> {
+    validate_range(section, "spreading_factor", spreading_factor, spreading_factors)?;
+    validate_one_of(section, "bandwidth_hz", bandwidth_hz, &LORA_BANDWIDTHS_HZ)?;
+    validate_range(section, "coding_rate", coding_rate, 5..=8)?;
+    validate_range(section, "frequency_hz", frequency_hz, 137_000_000..=1_020_000_000)?;
+    validate_range(section, "max_payload_length", max_payload_length, 1..=255)
+}
+
+fn validate_message_name(field: &str, message_name: &str) -> Result<(), ConfigError> {
+    MavMessage::message_id_from_name(message_name)
+        .map(|_| ())
+        .map_err(|_| invalid(field, &format!("unknown MAVLink message {}", message_name)))
+}
+
+fn validate_pins(section: &str, pins: &[(&str, u8)]) -> Result<(), ConfigError> {
+    let mut used_pins = HashSet::new();
+    for (name, pin) in pins {
+        let field = format!("{}.pins.{}", section, name);
+        if *pin > MAX_GPIO_PIN {
+            return Err(invalid(&field, &format!("GPIO {} does not exist", pin)));
+        }
+        if !used_pins.insert(*pin) {
+            return Err(invalid(&field, &format!("GPIO {} is assigned twice", pin)));
+        }
+    }
+    Ok(())
+}
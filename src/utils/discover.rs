@@ -49,7 +49,7 @@ impl DiscoveryService {
                             continue;
                         }
                         discovered_nodes.lock().unwrap().insert(src.to_string(), Instant::now());
-                        println!("Discovered new node: {}", src.to_string());
+                        println!("Discovered new node: {}", src);
                         match discover_notifier.send(src.to_string()).await {
                             Ok(_) => {}
                             Err(e) => {
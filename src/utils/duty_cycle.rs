@@ -0,0 +1,312 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::{sleep_until, Instant};
+use tracing::warn;
+
+use super::config::DutyCycleSection;
+use super::logging_utils::log_duty_cycle_exceeded;
+use super::lora_airtime::LoRaModulation;
+use super::metrics::metrics;
+use super::transmit_guard::TransmitGuard;
+use super::types::MavFramePacket;
+use crate::define_struct_with_defaults;
+use crate::driver::{Driver, DriverError, DriverWrapper};
+
+pub const DUTY_CYCLE_REMAINING_METRIC: &str = "duty_cycle_remaining_seconds";
+pub const DUTY_CYCLE_DEFERRED_METRIC: &str = "duty_cycle_deferred_total";
+pub const DUTY_CYCLE_DROPPED_METRIC: &str = "duty_cycle_dropped_total";
+
+/// Regulated frequency range with its maximum share of transmission time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubBand {
+    pub name: &'static str,
+    pub min_frequency_hz: u32,
+    pub max_frequency_hz: u32,
+    pub duty_cycle: f64,
+}
+
+/// EU863-870 sub-bands of ETSI EN 300 220 and ERC Recommendation 70-03 for non-specific short range devices
+pub const EU868_SUB_BANDS: [SubBand; 6] = [
+    SubBand {
+        name: "h1.3",
+        min_frequency_hz: 863_000_000,
+        max_frequency_hz: 865_000_000,
+        duty_cycle: 0.001,
+    },
+    SubBand {
+        name: "h1.4",
+        min_frequency_hz: 865_000_000,
+        max_frequency_hz: 868_000_000,
+        duty_cycle: 0.01,
+    },
+    SubBand {
+        name: "h1.5",
+        min_frequency_hz: 868_000_000,
+        max_frequency_hz: 868_600_000,
+        duty_cycle: 0.01,
+    },
+    SubBand {
+        name: "h1.6",
+        min_frequency_hz: 868_700_000,
+        max_frequency_hz: 869_200_000,
+        duty_cycle: 0.001,
+    },
+    SubBand {
+        name: "h1.7",
+        min_frequency_hz: 869_400_000,
+        max_frequency_hz: 869_650_000,
+        duty_cycle: 0.1,
+    },
+    SubBand {
+        name: "h1.9",
+        min_frequency_hz: 869_700_000,
+        max_frequency_hz: 870_000_000,
+        duty_cycle: 0.01,
+    },
+];
+
+pub fn eu868_sub_band(frequency_hz: u32) -> Option<&'static SubBand> {
+    EU868_SUB_BANDS
+        .iter()
+        .find(|sub_band| (sub_band.min_frequency_hz..sub_band.max_frequency_hz).contains(&frequency_hz))
+}
+
+/// Airtime used per sub-band over a rolling window, one hour for the EU regulations
+pub struct DutyCycleAccountant {
+    window: Duration,
+    transmissions: HashMap<&'static str, VecDeque<(Instant, Duration)>>,
+}
+
+impl DutyCycleAccountant {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            transmissions: HashMap::new(),
+        }
+    }
+
+    pub fn budget(&self, sub_band: &SubBand) -> Duration {
+        self.window.mul_f64(sub_band.duty_cycle)
+    }
+
+    fn forget_expired(&mut self, sub_band: &SubBand, now: Instant) {
+        if let Some(transmissions) = self.transmissions.get_mut(sub_band.name) {
+            while transmissions
+                .front()
+                .is_some_and(|(start, _)| now.duration_since(*start) >= self.window)
+            {
+                transmissions.pop_front();
+            }
+        }
+    }
+
+    pub fn used(&mut self, sub_band: &SubBand, now: Instant) -> Duration {
+        self.forget_expired(sub_band, now);
+        self.transmissions
+            .get(sub_band.name)
+            .map_or(Duration::ZERO, |transmissions| {
+                transmissions.iter().map(|(_, airtime)| *airtime).sum()
+            })
+    }
+
+    pub fn remaining(&mut self, sub_band: &SubBand, now: Instant) -> Duration {
+        self.budget(sub_band).saturating_sub(self.used(sub_band, now))
+    }
+
+    /// Earliest time a transmission of `airtime` fits the budget, `None` if it never does
+    pub fn next_available(&mut self, sub_band: &SubBand, airtime: Duration, now: Instant) -> Option<Instant> {
+        let budget = self.budget(sub_band);
+        if airtime > budget {
+            return None;
+        }
+        let mut used = self.used(sub_band, now);
+        if used + airtime <= budget {
+            return Some(now);
+        }
+        for (start, transmission_airtime) in self.transmissions.get(sub_band.name)?.iter() {
+            used -= *transmission_airtime;
+            if used + airtime <= budget {
+                return Some(*start + self.window);
+            }
+        }
+        None
+    }
+
+    pub fn record(&mut self, sub_band: &SubBand, airtime: Duration, now: Instant) {
+        self.transmissions
+            .entry(sub_band.name)
+            .or_default()
+            .push_back((now, airtime));
+    }
+}
+
+define_struct_with_defaults! {
+    DutyCycleOptionalConfig, DutyCycleConfig {
+        window_s: u64 = 3600,
+        max_defer_ms: u64 = 1000,
+    }
+}
+
+impl From<&DutyCycleSection> for DutyCycleOptionalConfig {
+    fn from(section: &DutyCycleSection) -> Self {
+        Self {
+            window_s: section.window_s,
+            max_defer_ms: section.max_defer_ms,
+        }
+    }
+}
+
+/// EU868 duty cycle of a LoRa radio, shared by its [`DutyCycleGuard`] and its [`DutyCycleDriver`].
+///
+/// The guard books every payload the radio transmits with its time on air once encoded, including
+/// the acknowledgements and retransmissions of the link layers, and drops the payloads that do not
+/// fit the remaining budget. The driver holds a batch back beforehand, if the budget frees up for it
+/// within `max_defer_ms`. Frequencies outside the EU868 sub-bands are not limited.
+pub struct DutyCycle {
+    accountant: Mutex<DutyCycleAccountant>,
+    max_defer: Duration,
+    warned_unregulated: AtomicBool,
+}
+
+impl DutyCycle {
+    pub fn new(config: Option<DutyCycleOptionalConfig>) -> Arc<Self> {
+        let config = config.unwrap_or_default().build();
+
+        Arc::new(Self {
+            accountant: Mutex::new(DutyCycleAccountant::new(Duration::from_secs(config.window_s))),
+            max_defer: Duration::from_millis(config.max_defer_ms),
+            warned_unregulated: AtomicBool::new(false),
+        })
+    }
+
+    /// Guard booking the payloads of the radio, which the radio driver needs
+    pub fn transmit_guard(self: &Arc<Self>) -> DutyCycleGuard {
+        DutyCycleGuard {
+            duty_cycle: self.clone(),
+        }
+    }
+
+    fn sub_band(&self, driver: &str, modulation: &LoRaModulation) -> Option<&'static SubBand> {
+        let sub_band = eu868_sub_band(modulation.frequency_hz);
+        if sub_band.is_none() && !self.warned_unregulated.swap(true, Ordering::Relaxed) {
+            warn!(
+                "{} transmits on {} Hz, outside the EU868 sub-bands, duty cycle is not limited",
+                driver, modulation.frequency_hz
+            );
+        }
+        sub_band
+    }
+
+    fn update_remaining_metric(&self, driver: &str, sub_band: &SubBand, now: Instant) {
+        let remaining = self.accountant.lock().unwrap().remaining(sub_band, now);
+        metrics().set_gauge(
+            DUTY_CYCLE_REMAINING_METRIC,
+            &[("driver", driver), ("sub_band", sub_band.name)],
+            remaining.as_secs_f64(),
+        );
+    }
+
+    /// Waits until a transmission of `airtime` fits the budget, unless that takes longer than `max_defer_ms`
+    async fn defer(&self, driver: &str, modulation: &LoRaModulation, airtime: Duration) {
+        let Some(sub_band) = self.sub_band(driver, modulation) else {
+            return;
+        };
+        let now = Instant::now();
+        let available_at = self.accountant.lock().unwrap().next_available(sub_band, airtime, now);
+        match available_at {
+            Some(available_at) if available_at > now && available_at - now <= self.max_defer => {
+                metrics().increment_counter(
+                    DUTY_CYCLE_DEFERRED_METRIC,
+                    &[("driver", driver), ("sub_band", sub_band.name)],
+                );
+                sleep_until(available_at).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Books the payloads of a LoRa radio against its [`DutyCycle`] once they went on air
+pub struct DutyCycleGuard {
+    duty_cycle: Arc<DutyCycle>,
+}
+
+impl TransmitGuard for DutyCycleGuard {
+    fn allows(&self, driver: &str, modulation: &LoRaModulation, at: Instant, airtime: Duration) -> bool {
+        let Some(sub_band) = self.duty_cycle.sub_band(driver, modulation) else {
+            return true;
+        };
+        let available_at = self
+            .duty_cycle
+            .accountant
+            .lock()
+            .unwrap()
+            .next_available(sub_band, airtime, at);
+        if available_at.is_some_and(|available_at| available_at <= at) {
+            return true;
+        }
+        metrics().increment_counter(
+            DUTY_CYCLE_DROPPED_METRIC,
+            &[("driver", driver), ("sub_band", sub_band.name)],
+        );
+        log_duty_cycle_exceeded(driver, sub_band.name, airtime);
+        self.duty_cycle.update_remaining_metric(driver, sub_band, at);
+        false
+    }
+
+    fn book(&self, driver: &str, modulation: &LoRaModulation, at: Instant, airtime: Duration) {
+        if let Some(sub_band) = self.duty_cycle.sub_band(driver, modulation) {
+            self.duty_cycle.accountant.lock().unwrap().record(sub_band, airtime, at);
+            self.duty_cycle.update_remaining_metric(driver, sub_band, at);
+        }
+    }
+}
+
+/// Driver wrapper holding a batch back until it fits the [`DutyCycle`] of a LoRa driver, whose radio
+/// needs the [`DutyCycleGuard`] of the same instance.
+///
+/// Placed outside the watchdog, so the wait does not count as a stalled transmission.
+pub struct DutyCycleDriver {
+    driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+    duty_cycle: Arc<DutyCycle>,
+}
+
+impl DutyCycleDriver {
+    pub fn new(driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>, duty_cycle: Arc<DutyCycle>) -> Self {
+        Self { driver, duty_cycle }
+    }
+}
+
+impl Display for DutyCycleDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.driver)
+    }
+}
+
+#[async_trait::async_trait]
+impl DriverWrapper<MavFramePacket> for DutyCycleDriver {
+    fn inner(&self) -> &Arc<dyn Driver<MavFramePacket> + Send + Sync> {
+        &self.driver
+    }
+
+    async fn send(&self, packet_to_send: &MavFramePacket) -> Result<(), DriverError> {
+        DriverWrapper::send_batch(self, std::slice::from_ref(packet_to_send)).await
+    }
+
+    async fn send_batch(&self, packets_to_send: &[MavFramePacket]) -> Result<(), DriverError> {
+        if let Some(modulation) = self.driver.modulation() {
+            let airtime = packets_to_send
+                .iter()
+                .filter_map(|packet| self.driver.time_on_air(packet))
+                .sum();
+            self.duty_cycle
+                .defer(&self.driver.to_string(), &modulation, airtime)
+                .await;
+        }
+        self.driver.send_batch(packets_to_send).await
+    }
+}
@@ -0,0 +1,195 @@
+use std::sync::Mutex;
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use super::config::EncryptionSection;
+use super::link_layer::LinkLayer;
+use super::logging_utils::log_encrypted_payload_rejected;
+use super::mavlink_utils::{node_system_id, parse_secret_key, signing_timestamp_now, ReplayProtection};
+use super::metrics::metrics;
+use super::types::NodeType;
+use crate::define_struct_with_defaults;
+
+pub const ENCRYPTED_PAYLOADS_REJECTED_METRIC: &str = "encrypted_payloads_rejected_total";
+
+/// Sender ID and 48 bit counter in front of the ciphertext, they make up the nonce
+pub const ENCRYPTION_HEADER_LENGTH: usize = 7;
+/// Poly1305 tag after the ciphertext
+pub const ENCRYPTION_TAG_LENGTH: usize = 16;
+const COUNTER_LENGTH: usize = 6;
+pub const ENCRYPTION_KEY_LENGTH: usize = 32;
+
+define_struct_with_defaults! {
+    EncryptionOptionalConfig, EncryptionConfig {
+        // Pre-shared key of the nodes on the channel, the all zero default only suits simulations
+        key: [u8; ENCRYPTION_KEY_LENGTH] = [0; ENCRYPTION_KEY_LENGTH],
+        // Part of every nonce, nodes sharing a key need distinct IDs so their nonces never collide.
+        // The system ID of the node when unset.
+        sender_id: Option<u8> = None,
+        // How far behind the newest counter of a sender a payload not seen before is still accepted
+        replay_window_ms: u64 = 30_000,
+    }
+}
+
+impl From<&EncryptionSection> for EncryptionOptionalConfig {
+    fn from(section: &EncryptionSection) -> Self {
+        Self {
+            key: parse_secret_key(&section.key),
+            sender_id: Some(section.sender_id),
+            replay_window_ms: section.replay_window_ms,
+        }
+    }
+}
+
+struct EncryptionState {
+    counter: u64,
+    replay_protection: ReplayProtection<u8>,
+}
+
+/// Link layer encrypting and authenticating payloads with ChaCha20-Poly1305 and a pre-shared key.
+///
+/// The nonce is the sender ID followed by a counter taken from the clock in MAVLink signing
+/// timestamp units and increased past the last one used, so it does not repeat across restarts as
+/// long as the clock does not go back. Only the 7 bytes of sender ID and counter go on air, in front
+/// of the ciphertext and its tag. Payloads failing authentication are dropped, and so are replays,
+/// told apart by the counter of their sender as [`ReplayProtection`] does for signatures, and payloads
+/// carrying the own sender ID.
+pub struct EncryptionLayer {
+    cipher: ChaCha20Poly1305,
+    sender_id: u8,
+    state: Mutex<EncryptionState>,
+}
+
+impl EncryptionLayer {
+    pub fn new(node_type: NodeType, config: Option<EncryptionOptionalConfig>) -> Self {
+        let config = config.unwrap_or_default().build();
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&config.key)),
+            sender_id: config.sender_id.unwrap_or_else(|| node_system_id(node_type)),
+            state: Mutex::new(EncryptionState {
+                counter: 0,
+                replay_protection: ReplayProtection::new(config.replay_window_ms),
+            }),
+        }
+    }
+
+    fn reject(&self, reason: &str) -> Vec<Vec<u8>> {
+        metrics().increment_counter(ENCRYPTED_PAYLOADS_REJECTED_METRIC, &[("reason", reason)]);
+        log_encrypted_payload_rejected(reason);
+        Vec::new()
+    }
+}
+
+/// Nonce of a payload from its on-air header
+fn nonce(header: &[u8]) -> Nonce {
+    let mut nonce = Nonce::default();
+    nonce[..ENCRYPTION_HEADER_LENGTH].copy_from_slice(header);
+    nonce
+}
+
+impl LinkLayer for EncryptionLayer {
+    fn encode(&self, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        let counter = {
+            let mut state = self.state.lock().unwrap();
+            state.counter = signing_timestamp_now().max(state.counter + 1);
+            state.counter
+        };
+        let mut encrypted = Vec::with_capacity(ENCRYPTION_HEADER_LENGTH + payload.len() + ENCRYPTION_TAG_LENGTH);
+        encrypted.push(self.sender_id);
+        encrypted.extend_from_slice(&counter.to_le_bytes()[..COUNTER_LENGTH]);
+        // Encryption only fails for payloads far beyond any radio packet
+        let Ok(ciphertext) = self.cipher.encrypt(&nonce(&encrypted), &payload[..]) else {
+            return Vec::new();
+        };
+        encrypted.extend(ciphertext);
+        vec![encrypted]
+    }
+
+    fn decode(&self, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        if payload.len() < ENCRYPTION_HEADER_LENGTH + ENCRYPTION_TAG_LENGTH {
+            return self.reject("malformed");
+        }
+        let (header, ciphertext) = payload.split_at(ENCRYPTION_HEADER_LENGTH);
+        let Ok(plaintext) = self.cipher.decrypt(&nonce(header), ciphertext) else {
+            return self.reject("authentication");
+        };
+        if header[0] == self.sender_id {
+            // A payload of this node sent back, or a peer with the same sender ID whose nonces may collide
+            return self.reject("reflected");
+        }
+        let mut counter_bytes = [0; 8];
+        counter_bytes[..COUNTER_LENGTH].copy_from_slice(&header[1..]);
+        let counter = u64::from_le_bytes(counter_bytes);
+        let mut state = self.state.lock().unwrap();
+        if !state.replay_protection.record(header[0], counter) {
+            return self.reject("replayed");
+        }
+        // Keeps the own counter ahead of the clock of every peer, as signing does with its timestamps
+        state.counter = state.counter.max(counter);
+        vec![plaintext]
+    }
+
+    fn overhead(&self) -> usize {
+        ENCRYPTION_HEADER_LENGTH + ENCRYPTION_TAG_LENGTH
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &[u8] = b"serialized MAVLink frame";
+
+    fn layer(sender_id: u8) -> EncryptionLayer {
+        EncryptionLayer::new(
+            NodeType::Uav,
+            Some(EncryptionOptionalConfig {
+                key: Some([7; ENCRYPTION_KEY_LENGTH]),
+                sender_id: Some(Some(sender_id)),
+                ..Default::default()
+            }),
+        )
+    }
+
+    fn encrypt(layer: &EncryptionLayer) -> Vec<u8> {
+        let mut payloads = layer.encode(PAYLOAD.to_vec());
+        assert_eq!(payloads.len(), 1);
+        payloads.remove(0)
+    }
+
+    #[test]
+    fn decrypts_payload_of_peer() {
+        let encrypted = encrypt(&layer(201));
+
+        assert_eq!(encrypted.len(), PAYLOAD.len() + layer(201).overhead());
+        assert_eq!(layer(101).decode(encrypted), vec![PAYLOAD.to_vec()]);
+    }
+
+    #[test]
+    fn rejects_tampered_payload() {
+        let mut encrypted = encrypt(&layer(201));
+        encrypted[ENCRYPTION_HEADER_LENGTH] ^= 0x01;
+
+        assert!(layer(101).decode(encrypted).is_empty());
+    }
+
+    #[test]
+    fn rejects_payload_with_own_sender_id() {
+        let uav = layer(201);
+        let encrypted = encrypt(&uav);
+
+        // Sent back to this node, or from a peer sharing its sender ID
+        assert!(uav.decode(encrypted.clone()).is_empty());
+        assert!(layer(201).decode(encrypted).is_empty());
+    }
+
+    #[test]
+    fn rejects_replayed_payload() {
+        let encrypted = encrypt(&layer(201));
+        let gateway = layer(101);
+
+        assert_eq!(gateway.decode(encrypted.clone()).len(), 1);
+        assert!(gateway.decode(encrypted).is_empty());
+    }
+}
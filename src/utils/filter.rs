@@ -0,0 +1,187 @@
+use std::fmt::{Debug, Display};
+use std::sync::Arc;
+
+use mavlink::Message;
+use serde::Deserialize;
+
+use super::logging_utils::log_debug_packet_filtered;
+use super::metrics::metrics;
+use super::types::{Direction, MavFramePacket};
+use crate::driver::{Driver, DriverError, DriverWrapper};
+
+pub const PACKETS_FILTERED_METRIC: &str = "packets_filtered_total";
+
+/// Decides whether a packet may pass in a given direction
+pub trait PacketFilter<P>: Send + Sync {
+    fn accept(&self, packet: &P, direction: Direction) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum FilterAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// Filter rule matching MAVLink packets, unset criteria match every packet
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterRule {
+    pub action: FilterAction,
+    pub direction: Option<Direction>,
+    pub message_names: Option<Vec<String>>,
+    pub message_ids: Option<Vec<u32>>,
+    pub system_ids: Option<Vec<u8>>,
+    pub component_ids: Option<Vec<u8>>,
+}
+
+impl FilterRule {
+    pub fn matches(&self, packet: &MavFramePacket, direction: Direction) -> bool {
+        self.direction
+            .map_or(true, |rule_direction| rule_direction == direction)
+            && self
+                .message_names
+                .as_ref()
+                .map_or(true, |names| names.iter().any(|name| name == packet.msg.message_name()))
+            && self
+                .message_ids
+                .as_ref()
+                .map_or(true, |ids| ids.contains(&packet.msg.message_id()))
+            && self
+                .system_ids
+                .as_ref()
+                .map_or(true, |ids| ids.contains(&packet.header.system_id))
+            && self
+                .component_ids
+                .as_ref()
+                .map_or(true, |ids| ids.contains(&packet.header.component_id))
+    }
+}
+
+/// Allow/deny list over MAVLink packets where the first matching rule decides
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageFilter {
+    #[serde(default)]
+    pub default_action: FilterAction,
+    #[serde(default)]
+    pub rules: Vec<FilterRule>,
+}
+
+impl MessageFilter {
+    /// Filter dropping the given message ids in one direction and letting everything else through
+    pub fn deny_message_ids(direction: Direction, message_ids: Vec<u32>) -> Self {
+        Self {
+            default_action: FilterAction::Allow,
+            rules: vec![FilterRule {
+                action: FilterAction::Deny,
+                direction: Some(direction),
+                message_names: None,
+                message_ids: Some(message_ids),
+                system_ids: None,
+                component_ids: None,
+            }],
+        }
+    }
+}
+
+impl PacketFilter<MavFramePacket> for MessageFilter {
+    fn accept(&self, packet: &MavFramePacket, direction: Direction) -> bool {
+        let action = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(packet, direction))
+            .map_or(self.default_action, |rule| rule.action);
+        action == FilterAction::Allow
+    }
+}
+
+/// Composition of filters, a packet passes only if every filter accepts it.
+/// Dropped packets are counted in the metrics and logged under the chain name.
+pub struct FilterChain<P> {
+    name: String,
+    filters: Vec<Box<dyn PacketFilter<P>>>,
+}
+
+impl<P: Debug> FilterChain<P> {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            filters: Vec::new(),
+        }
+    }
+
+    pub fn with_filter(mut self, filter: impl PacketFilter<P> + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+impl<P: Debug> PacketFilter<P> for FilterChain<P> {
+    fn accept(&self, packet: &P, direction: Direction) -> bool {
+        if self.filters.iter().all(|filter| filter.accept(packet, direction)) {
+            return true;
+        }
+        let direction_label = format!("{:?}", direction);
+        metrics().increment_counter(
+            PACKETS_FILTERED_METRIC,
+            &[("chain", &self.name), ("direction", &direction_label)],
+        );
+        log_debug_packet_filtered(&self.name, packet, direction);
+        false
+    }
+}
+
+/// Driver wrapper applying a filter chain to everything the wrapped driver sends and receives
+pub struct FilteredDriver<P> {
+    driver: Arc<dyn Driver<P> + Send + Sync>,
+    filter: FilterChain<P>,
+}
+
+impl<P> FilteredDriver<P> {
+    pub fn new(driver: Arc<dyn Driver<P> + Send + Sync>, filter: FilterChain<P>) -> Self {
+        Self { driver, filter }
+    }
+}
+
+impl<P> Display for FilteredDriver<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.driver)
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: Clone + Debug + Send + Sync> DriverWrapper<P> for FilteredDriver<P> {
+    fn inner(&self) -> &Arc<dyn Driver<P> + Send + Sync> {
+        &self.driver
+    }
+
+    async fn send(&self, packet_to_send: &P) -> Result<(), DriverError> {
+        if !self.filter.accept(packet_to_send, Direction::Outgoing) {
+            return Ok(());
+        }
+        self.driver.send(packet_to_send).await
+    }
+
+    async fn send_batch(&self, packets_to_send: &[P]) -> Result<(), DriverError> {
+        let accepted: Vec<P> = packets_to_send
+            .iter()
+            .filter(|packet| self.filter.accept(packet, Direction::Outgoing))
+            .cloned()
+            .collect();
+        // A batch without packets still goes through, it transmits the payloads of the link layers
+        if accepted.is_empty() && !packets_to_send.is_empty() {
+            return Ok(());
+        }
+        self.driver.send_batch(&accepted).await
+    }
+
+    async fn receive(&self) -> Result<Option<P>, DriverError> {
+        let packet = self.driver.receive().await?;
+        Ok(packet.filter(|packet| self.filter.accept(packet, Direction::Incoming)))
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::config::FragmentationSection;
+use super::link_layer::LinkLayer;
+use super::logging_utils::log_debug_fragments_discarded;
+use super::mavlink_utils::node_system_id;
+use super::metrics::metrics;
+use super::types::NodeType;
+use crate::define_struct_with_defaults;
+
+pub const FRAGMENTS_SENT_METRIC: &str = "fragments_sent_total";
+pub const FRAGMENT_SETS_DISCARDED_METRIC: &str = "fragment_sets_discarded_total";
+
+/// Sender tag, message sequence, then fragment index and count minus one in one nibble each
+pub const FRAGMENT_HEADER_LENGTH: usize = 3;
+const MAX_FRAGMENTS: usize = 16;
+
+define_struct_with_defaults! {
+    FragmenterOptionalConfig, FragmenterConfig {
+        // Largest payload the radio accepts, fragment header included
+        max_fragment_length: usize = 255,
+        reassembly_timeout_ms: u64 = 2000,
+        // Tells apart the nodes sharing the channel, defaults to the system ID of the node type
+        sender_tag: Option<u8> = None,
+    }
+}
+
+impl From<&FragmentationSection> for FragmenterOptionalConfig {
+    fn from(section: &FragmentationSection) -> Self {
+        Self {
+            max_fragment_length: section.max_fragment_length,
+            reassembly_timeout_ms: section.reassembly_timeout_ms,
+            sender_tag: Some(section.sender_tag),
+        }
+    }
+}
+
+struct PartialFrame {
+    fragments: Vec<Option<Vec<u8>>>,
+    started: Instant,
+}
+
+/// Link layer splitting frames larger than the radio payload into numbered fragments.
+///
+/// Every frame gets the fragment header, including the ones that fit a single fragment, so both
+/// ends of the link have to use it. Fragments are reassembled per sender and message sequence,
+/// sets still incomplete after the reassembly timeout are discarded. The sender is told apart by its
+/// sender tag, the system ID of its node unless configured, so it stays the same across restarts.
+pub struct Fragmenter {
+    max_fragment_payload: usize,
+    reassembly_timeout: Duration,
+    sender_tag: u8,
+    sequence: AtomicU8,
+    partial_frames: Mutex<HashMap<(u8, u8), PartialFrame>>,
+}
+
+impl Fragmenter {
+    pub fn new(node_type: NodeType, config: Option<FragmenterOptionalConfig>) -> Self {
+        let config = config.unwrap_or_default().build();
+        assert!(
+            config.max_fragment_length > FRAGMENT_HEADER_LENGTH,
+            "Fragments need room for a payload after the header"
+        );
+
+        Self {
+            max_fragment_payload: config.max_fragment_length - FRAGMENT_HEADER_LENGTH,
+            reassembly_timeout: Duration::from_millis(config.reassembly_timeout_ms),
+            // Tells apart the fragments of nodes sharing the channel, which number their messages independently
+            sender_tag: config.sender_tag.unwrap_or_else(|| node_system_id(node_type)),
+            sequence: AtomicU8::new(0),
+            partial_frames: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn discard(&self, count: u64, reason: &str) {
+        metrics().add_counter(FRAGMENT_SETS_DISCARDED_METRIC, &[("reason", reason)], count);
+        log_debug_fragments_discarded(count, reason);
+    }
+}
+
+impl LinkLayer for Fragmenter {
+    fn encode(&self, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        let fragment_count = payload.len().div_ceil(self.max_fragment_payload).max(1);
+        if fragment_count > MAX_FRAGMENTS {
+            self.discard(1, "too_large");
+            return Vec::new();
+        }
+
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let fragments: Vec<Vec<u8>> = (0..fragment_count)
+            .map(|index| {
+                let start = index * self.max_fragment_payload;
+                let end = (start + self.max_fragment_payload).min(payload.len());
+                let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_LENGTH + end - start);
+                fragment.push(self.sender_tag);
+                fragment.push(sequence);
+                fragment.push(((index as u8) << 4) | (fragment_count as u8 - 1));
+                fragment.extend_from_slice(&payload[start..end]);
+                fragment
+            })
+            .collect();
+        metrics().add_counter(FRAGMENTS_SENT_METRIC, &[], fragments.len() as u64);
+        fragments
+    }
+
+    fn decode(&self, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        if payload.len() < FRAGMENT_HEADER_LENGTH {
+            self.discard(1, "malformed");
+            return Vec::new();
+        }
+        let key = (payload[0], payload[1]);
+        let index = (payload[2] >> 4) as usize;
+        let count = (payload[2] & 0x0f) as usize + 1;
+        if index >= count {
+            self.discard(1, "malformed");
+            return Vec::new();
+        }
+        if count == 1 {
+            return vec![payload[FRAGMENT_HEADER_LENGTH..].to_vec()];
+        }
+
+        let mut partial_frames = self.partial_frames.lock().unwrap();
+        let now = Instant::now();
+        let expired_count = partial_frames.len();
+        partial_frames.retain(|_, partial| now.duration_since(partial.started) < self.reassembly_timeout);
+        let expired_count = expired_count - partial_frames.len();
+        if expired_count > 0 {
+            self.discard(expired_count as u64, "timeout");
+        }
+
+        let partial = partial_frames.entry(key).or_insert_with(|| PartialFrame {
+            fragments: vec![None; count],
+            started: now,
+        });
+        if partial.fragments.len() != count {
+            // The sequence wrapped around onto a set that never completed
+            *partial = PartialFrame {
+                fragments: vec![None; count],
+                started: now,
+            };
+            self.discard(1, "superseded");
+        }
+        partial.fragments[index] = Some(payload[FRAGMENT_HEADER_LENGTH..].to_vec());
+
+        if partial.fragments.iter().all(Option::is_some) {
+            let partial = partial_frames.remove(&key).unwrap();
+            return vec![partial.fragments.into_iter().flatten().flatten().collect()];
+        }
+        Vec::new()
+    }
+
+    fn overhead(&self) -> usize {
+        FRAGMENT_HEADER_LENGTH
+    }
+
+    fn encoded_lengths(&self, length: usize) -> Vec<usize> {
+        let full_fragments = length / self.max_fragment_payload;
+        let mut lengths = vec![self.max_fragment_payload + FRAGMENT_HEADER_LENGTH; full_fragments];
+        if length % self.max_fragment_payload != 0 || length == 0 {
+            lengths.push(length % self.max_fragment_payload + FRAGMENT_HEADER_LENGTH);
+        }
+        lengths
+    }
+}
@@ -1,13 +1,23 @@
-use std::sync::Arc;
-
-use embedded_hal::digital::OutputPin;
+use embedded_hal::digital::{InputPin, OutputPin};
 use embedded_hal_async::delay::DelayNs;
 use embedded_hal_async::digital::Wait;
 use lora_phy::mod_params::RadioError;
 use lora_phy::mod_params::RadioError::*;
 use lora_phy::mod_traits::InterfaceVariant;
 use tokio::sync::mpsc::Receiver;
-use tokio::sync::Notify;
+
+use super::lora_utils::IrqEvents;
+
+/// Waits for the IRQ of an operation lora-phy started right before. The IRQ pin stays high until the
+/// IRQ is cleared, so an edge raised before the wait is seen through the pin level, the ones after it
+/// through the IRQ events.
+async fn wait_for_irq(irq_pin: &mut impl InputPin, irq_events: &mut IrqEvents) -> Result<(), RadioError> {
+    irq_events.borrow_and_update();
+    if irq_pin.is_high().map_err(|_| DIO1)? {
+        return Ok(());
+    }
+    irq_events.changed().await.map_err(|_| DIO1)
+}
 
 #[allow(dead_code)]
 /// Base for the InterfaceVariant implementation for a generic Sx127x LoRa board
@@ -16,7 +26,7 @@ pub struct GenericSx127xInterfaceVariant<CTRL, WAIT> {
     dio0: WAIT,
     rf_switch_rx: Option<CTRL>,
     rf_switch_tx: Option<CTRL>,
-    interrupt_rx: Receiver<()>,
+    interrupt_rx: IrqEvents,
 }
 
 impl<CTRL, WAIT> GenericSx127xInterfaceVariant<CTRL, WAIT>
@@ -29,7 +39,7 @@ where
         dio0: WAIT,
         rf_switch_rx: Option<CTRL>,
         rf_switch_tx: Option<CTRL>,
-        interrupt_rx: Receiver<()>,
+        interrupt_rx: IrqEvents,
     ) -> Result<Self, RadioError> {
         Ok(Self {
             reset,
@@ -44,7 +54,7 @@ where
 impl<CTRL, WAIT> InterfaceVariant for GenericSx127xInterfaceVariant<CTRL, WAIT>
 where
     CTRL: OutputPin,
-    WAIT: Wait,
+    WAIT: Wait + InputPin,
 {
     async fn reset(&mut self, delay: &mut impl DelayNs) -> Result<(), RadioError> {
         delay.delay_ms(10).await;
@@ -58,9 +68,7 @@ where
         Ok(())
     }
     async fn await_irq(&mut self) -> Result<(), RadioError> {
-        // self.dio0.wait_for_high().await.map_err(|_| DIO1)?;
-        self.interrupt_rx.recv().await;
-        Ok(())
+        wait_for_irq(&mut self.dio0, &mut self.interrupt_rx).await
     }
 
     async fn enable_rf_switch_rx(&mut self) -> Result<(), RadioError> {
@@ -103,7 +111,7 @@ pub struct GenericSx126xInterfaceVariant<CTRL, WAIT> {
     busy: WAIT,
     rf_switch_rx: Option<CTRL>,
     rf_switch_tx: Option<CTRL>,
-    interrupt_rx: Arc<Notify>,
+    interrupt_rx: IrqEvents,
     interrupt_busy: Receiver<()>,
 }
 
@@ -119,7 +127,7 @@ where
         busy: WAIT,
         rf_switch_rx: Option<CTRL>,
         rf_switch_tx: Option<CTRL>,
-        interrupt_rx: Arc<Notify>,
+        interrupt_rx: IrqEvents,
         interrupt_busy: Receiver<()>,
     ) -> Result<Self, RadioError> {
         Ok(Self {
@@ -137,7 +145,7 @@ where
 impl<CTRL, WAIT> InterfaceVariant for GenericSx126xInterfaceVariant<CTRL, WAIT>
 where
     CTRL: OutputPin,
-    WAIT: Wait,
+    WAIT: Wait + InputPin,
 {
     async fn reset(&mut self, delay: &mut impl DelayNs) -> Result<(), RadioError> {
         delay.delay_ms(100).await;
@@ -149,19 +157,14 @@ where
         Ok(())
     }
     async fn wait_on_busy(&mut self) -> Result<(), RadioError> {
-        tracing::info!("Waiting on busy");
         let _ = self.busy.wait_for_low().await;
         Ok(())
     }
     async fn await_irq(&mut self) -> Result<(), RadioError> {
-        tracing::info!("Waiting for interrupt");
-        self.dio1.wait_for_high().await.map_err(|_| DIO1)?;
-        // self.interrupt_rx.notified().await;
-        Ok(())
+        wait_for_irq(&mut self.dio1, &mut self.interrupt_rx).await
     }
 
     async fn enable_rf_switch_rx(&mut self) -> Result<(), RadioError> {
-        tracing::info!("Enabling RX switch");
         match &mut self.rf_switch_tx {
             Some(pin) => pin.set_low().map_err(|_| RfSwitchTx)?,
             None => (),
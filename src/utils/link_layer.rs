@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::lora_airtime::LoRaModulation;
+use super::mavlink_utils::{pack_frames, split_frames, MavlinkSigning};
+
+/// Byte level transformation between a serialized MAVLink frame and the payloads a radio transmits
+pub trait LinkLayer: Send + Sync {
+    /// Turns one outgoing payload into the payloads to pass down to the next layer
+    fn encode(&self, payload: Vec<u8>) -> Vec<Vec<u8>>;
+
+    /// Turns one incoming payload into the payloads to pass up, none while more input is needed
+    fn decode(&self, payload: Vec<u8>) -> Vec<Vec<u8>>;
+
+    /// Bytes the layer adds to a payload that fits a single radio packet
+    fn overhead(&self) -> usize {
+        0
+    }
+
+    /// Lengths of the payloads `encode` turns a payload of `length` bytes into, without encoding it
+    fn encoded_lengths(&self, length: usize) -> Vec<usize> {
+        vec![length + self.overhead()]
+    }
+
+    /// Payloads the layer transmits on its own once due, such as acknowledgements and retransmissions.
+    /// `allows` is asked with the length of each payload before the layer commits to it, a payload it
+    /// refuses stays due.
+    fn poll_transmit(&self, _allows: &mut dyn FnMut(usize) -> bool) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+
+    /// When the layer next has payloads of its own to transmit, `None` when it has none
+    fn next_transmit(&self) -> Option<Instant> {
+        None
+    }
+}
+
+/// Ordered link layers of a driver, the first layer is the closest to the MAVLink frames.
+///
+/// Several frames can be packed into one payload when packing is enabled. Received payloads are
+/// always split back into frames along the MAVLink framing, and the frames that could not be
+/// handed out yet are kept until the driver asks for them, so a driver returning one frame per
+/// `receive` call does not lose any.
+///
+/// With signing, frames are signed before they are packed and received frames failing verification
+/// are dropped after splitting.
+#[derive(Default)]
+pub struct LinkLayerStack {
+    layers: Vec<Box<dyn LinkLayer>>,
+    packing_limit: Option<usize>,
+    signing: Option<Arc<MavlinkSigning>>,
+    decoded: Mutex<VecDeque<Vec<u8>>>,
+}
+
+impl LinkLayerStack {
+    pub fn push(&mut self, layer: impl LinkLayer + 'static) {
+        self.layers.push(Box::new(layer));
+    }
+
+    /// Packs the frames of a batch into payloads of up to `max_payload_length` bytes once encoded
+    pub fn enable_packing(&mut self, max_payload_length: usize) {
+        self.packing_limit = Some(max_payload_length);
+    }
+
+    /// Signs the outgoing frames and verifies the incoming ones
+    pub fn set_signing(&mut self, signing: Arc<MavlinkSigning>) {
+        self.signing = Some(signing);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    fn overhead(&self) -> usize {
+        self.layers.iter().map(|layer| layer.overhead()).sum()
+    }
+
+    fn sign(&self, frame: Vec<u8>) -> Vec<u8> {
+        match &self.signing {
+            Some(signing) => signing.sign(frame),
+            None => frame,
+        }
+    }
+
+    /// Payloads to transmit for one serialized frame
+    pub fn encode(&self, frame: Vec<u8>) -> Vec<Vec<u8>> {
+        self.encode_from(0, self.sign(frame))
+    }
+
+    /// Runs a payload down through the layers from the one at `first_layer`
+    fn encode_from(&self, first_layer: usize, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        self.layers[first_layer..]
+            .iter()
+            .fold(vec![payload], |payloads, layer| {
+                payloads.into_iter().flat_map(|payload| layer.encode(payload)).collect()
+            })
+    }
+
+    /// Time on air of one serialized frame once encoded, with the preamble of every payload it is split into.
+    /// Frames packed together share a preamble, so this overestimates them.
+    pub fn time_on_air(&self, modulation: &LoRaModulation, frame: &[u8]) -> Duration {
+        let length = match &self.signing {
+            Some(signing) => signing.signed_length(frame),
+            None => frame.len(),
+        };
+        self.time_on_air_from(0, modulation, length)
+    }
+
+    /// Time on air of a payload of `length` bytes run down through the layers from the one at `first_layer`
+    fn time_on_air_from(&self, first_layer: usize, modulation: &LoRaModulation, length: usize) -> Duration {
+        self.layers[first_layer..]
+            .iter()
+            .fold(vec![length], |lengths, layer| {
+                lengths
+                    .into_iter()
+                    .flat_map(|length| layer.encoded_lengths(length))
+                    .collect()
+            })
+            .into_iter()
+            .map(|length| modulation.time_on_air(length))
+            .sum()
+    }
+
+    /// Payloads to transmit for serialized frames sent back to back, grouped by the frame, or the frames
+    /// packed together, they carry. A group only arrives whole, such as the fragments of a frame.
+    ///
+    /// `allows` decides on each group from its time on air before the layers encode it, so a group it
+    /// refuses is dropped without leaving a trace in their state.
+    pub fn encode_batch(
+        &self,
+        modulation: &LoRaModulation,
+        frames: Vec<Vec<u8>>,
+        mut allows: impl FnMut(Duration) -> bool,
+    ) -> Vec<Vec<Vec<u8>>> {
+        let frames = frames.into_iter().map(|frame| self.sign(frame)).collect();
+        let payloads = match self.packing_limit {
+            Some(max_payload_length) => pack_frames(frames, max_payload_length.saturating_sub(self.overhead())),
+            None => frames,
+        };
+        payloads
+            .into_iter()
+            .filter(|payload| allows(self.time_on_air_from(0, modulation, payload.len())))
+            .map(|payload| self.encode_from(0, payload))
+            .collect()
+    }
+
+    /// Payloads the layers transmit on their own and that are due, each run down through the layers below it
+    /// into a group of its own. `allows` decides on each from its time on air, the ones it refuses stay due.
+    pub fn poll_transmit(
+        &self,
+        modulation: &LoRaModulation,
+        mut allows: impl FnMut(Duration) -> bool,
+    ) -> Vec<Vec<Vec<u8>>> {
+        let mut groups = Vec::new();
+        for (index, layer) in self.layers.iter().enumerate() {
+            let payloads =
+                layer.poll_transmit(&mut |length| allows(self.time_on_air_from(index + 1, modulation, length)));
+            groups.extend(payloads.into_iter().map(|payload| self.encode_from(index + 1, payload)));
+        }
+        groups
+    }
+
+    /// When a layer next has payloads of its own to transmit
+    pub fn next_transmit(&self) -> Option<Instant> {
+        self.layers.iter().filter_map(|layer| layer.next_transmit()).min()
+    }
+
+    /// Runs a received payload up the stack without splitting the result into frames, for byte
+    /// stream drivers that parse it themselves, and verify the signatures while doing so
+    pub fn decode_payloads(&self, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        self.layers.iter().rev().fold(vec![payload], |payloads, layer| {
+            payloads.into_iter().flat_map(|payload| layer.decode(payload)).collect()
+        })
+    }
+
+    /// Runs a received payload up the stack and returns the first complete frame, if any
+    pub fn decode(&self, payload: Vec<u8>) -> Option<Vec<u8>> {
+        let payloads = self.decode_payloads(payload);
+        let mut frames: Vec<Vec<u8>> = payloads.iter().flat_map(|payload| split_frames(payload)).collect();
+        if let Some(signing) = &self.signing {
+            frames.retain(|frame| signing.verify(frame));
+        }
+        let mut decoded = self.decoded.lock().unwrap();
+        decoded.extend(frames);
+        decoded.pop_front()
+    }
+
+    /// Next frame left over from a payload that decoded into several
+    pub fn pop_decoded(&self) -> Option<Vec<u8>> {
+        self.decoded.lock().unwrap().pop_front()
+    }
+
+    pub fn has_decoded(&self) -> bool {
+        !self.decoded.lock().unwrap().is_empty()
+    }
+}
@@ -4,13 +4,14 @@ use chrono::Utc;
 use mavlink::Message;
 use serde::Serialize;
 use serde_json::to_value;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use tracing_appender::rolling::{self, RollingFileAppender};
 use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::{fmt, EnvFilter, Registry};
 
-use super::types::{MavFramePacket, NodeType};
+use super::metrics::{record_packet_received, record_packet_sent};
+use super::types::{Direction, MavFramePacket, NodeType};
 use super::websocket_layer::WebSocketMakeWriter;
 
 // Constants for log messages
@@ -26,6 +27,38 @@ const SEND_TO_MAIN_MSG: &str = "Send to main";
 const SEND_TO_NETWORK_MSG: &str = "Send to network";
 const NETWORK_INTERFACE_CREATION_MSG: &str = "Network interface created";
 const NETWORK_INTERFACE_RUNNING_MSG: &str = "Running network interface";
+const PACKET_FILTERED_MSG: &str = "Packet filtered";
+const PACKET_SCHEDULER_DROP_MSG: &str = "Packet dropped by scheduler";
+const FRAGMENTS_DISCARDED_MSG: &str = "Fragment sets discarded";
+const DUTY_CYCLE_EXCEEDED_MSG: &str = "Duty cycle budget exceeded, dropping packet";
+const SERIAL_CONNECTED_MSG: &str = "Serial device connected";
+const SERIAL_DISCONNECTED_MSG: &str = "Serial device unavailable";
+const TCP_CONNECTED_MSG: &str = "TCP peer connected";
+const TCP_DISCONNECTED_MSG: &str = "TCP peer disconnected";
+const WEBSOCKET_CONNECTED_MSG: &str = "WebSocket peer connected";
+const WEBSOCKET_DISCONNECTED_MSG: &str = "WebSocket peer disconnected";
+const UDP_PEER_LEARNED_MSG: &str = "UDP peer learned";
+const UDP_PEER_EXPIRED_MSG: &str = "UDP peer expired";
+const RECEIVED_DATA_DROPPED_MSG: &str = "Received data dropped";
+const TRANSMIT_RETRY_MSG: &str = "Retrying transmit";
+const DRIVER_BACKOFF_MSG: &str = "Backing off after driver error";
+const CHANNEL_BUSY_MSG: &str = "Channel busy, backing off";
+const CHANNEL_ACCESS_FAILED_MSG: &str = "Channel stayed busy, dropping packets";
+const TDMA_SYNC_MSG: &str = "TDMA clock synchronized";
+const ARQ_RETRANSMISSION_MSG: &str = "Retransmitting unacknowledged payload";
+const ARQ_DELIVERY_FAILED_MSG: &str = "Payload not acknowledged, giving up";
+const SIGNATURE_REJECTED_MSG: &str = "Dropping frame failing signature verification";
+const ENCRYPTED_PAYLOAD_REJECTED_MSG: &str = "Dropping encrypted payload failing authentication or replayed";
+const RADIO_STATE_MSG: &str = "Radio state changed";
+const METRICS_SERVER_RUNNING_MSG: &str = "Serving metrics";
+const METRICS_REQUEST_FAILED_MSG: &str = "Metrics request failed";
+const PEER_RADIO_STATUS_MSG: &str = "Radio status received from peer";
+const RADIO_RECOVERY_MSG: &str = "Radio stalled, re-initialized";
+const RADIO_RECOVERY_FAILED_MSG: &str = "Radio stalled, re-initialization failed";
+const ADR_MODULATION_CHANGE_MSG: &str = "LoRa modulation changed";
+const ADR_FALLBACK_MSG: &str = "Contact with peer lost, back to the fallback modulation";
+const ADR_PROPOSAL_MSG: &str = "Proposing modulation change";
+const ADR_PROPOSAL_FAILED_MSG: &str = "Modulation change not accepted";
 
 /// Initialization of the logging system
 pub fn init_logging(
@@ -34,7 +67,7 @@ pub fn init_logging(
     let node_type = NodeType::from_str(&std::env::var("NODE_TYPE").unwrap()).unwrap();
 
     let file_name = format!("{:?}_{}.json", node_type, Utc::now().format("%Y-%m-%d_%H-%M-%S%.3f"));
-    let file_appender: RollingFileAppender = RollingFileAppender::new(rolling::Rotation::NEVER, "./logs", &file_name);
+    let file_appender: RollingFileAppender = RollingFileAppender::new(rolling::Rotation::NEVER, "./logs", file_name);
     let (non_blocking_file_writer, _guard) = tracing_appender::non_blocking(file_appender);
 
     let filter_layer = EnvFilter::try_from_default_env()
@@ -81,10 +114,7 @@ pub fn log_packet_received(
         target: "network",
         driver,
         size = size,
-        src_addr = match src_addr {
-            Some(addr) => Some(addr.to_string()),
-            None => None,
-        },
+        src_addr = src_addr.map(|addr| addr.to_string()),
         message_type = &mavlink_frame.msg.message_name(),
         message_seq = mavlink_frame.header.sequence,
         "{}",
@@ -96,10 +126,7 @@ pub fn log_packet_sent(size: usize, dest_addr: Option<&str>, packet: &MavFramePa
         target: "network",
         driver,
         size,
-        dest_addr = match dest_addr {
-            Some(addr) => Some(addr.to_string()),
-            None => None,
-        },
+        dest_addr = dest_addr.map(|addr| addr.to_string()),
         message_type = &packet.msg.message_name(),
         message_seq = packet.header.sequence,
         "Sending packet",
@@ -108,6 +135,8 @@ pub fn log_packet_sent(size: usize, dest_addr: Option<&str>, packet: &MavFramePa
 
 // Log the contents of a packet being sent with DEBUG level
 pub fn log_debug_send_packet<Packet: Debug + Serialize>(driver: &str, packet: &Packet) {
+    // Every driver logs each packet it sends here, so the metric is recorded along
+    record_packet_sent(driver);
     match to_value(packet) {
         Ok(json_packet) => {
             debug!(target: "network", driver, %json_packet, "{}", SEND_PACKET_MSG);
@@ -125,6 +154,8 @@ pub fn log_debug_receive_packet<Packet: Debug + Serialize>(
     rssi: Option<i16>,
     snr: Option<i16>,
 ) {
+    // Every driver logs each packet it receives here, so the metrics are recorded along
+    record_packet_received(driver, rssi, snr);
     match to_value(packet) {
         Ok(json_packet) => match (rssi, snr) {
             (Some(rssi_value), Some(snr_value)) => {
@@ -185,3 +216,163 @@ pub fn log_network_interface_creation(driver: &str) {
 pub fn log_network_interface_running(driver: &str) {
     info!(target: "network", driver, "{}", NETWORK_INTERFACE_RUNNING_MSG);
 }
+
+// Log a packet dropped by a filter chain with DEBUG level
+pub fn log_debug_packet_filtered<Packet: Debug>(chain: &str, packet: &Packet, direction: Direction) {
+    debug!(target: "network", chain, ?direction, ?packet, "{}", PACKET_FILTERED_MSG);
+}
+
+// Log a packet the scheduler dropped or replaced with a newer one with DEBUG level
+pub fn log_debug_scheduler_drop(link: &str, message_type: &str, reason: &str) {
+    debug!(target: "network", link, message_type, reason, "{}", PACKET_SCHEDULER_DROP_MSG);
+}
+
+// Log a transmission dropped because the sub-band duty cycle budget is used up with WARN level
+pub fn log_duty_cycle_exceeded(driver: &str, sub_band: &str, airtime: std::time::Duration) {
+    warn!(target: "network", driver, sub_band, airtime_ms = airtime.as_millis() as u64, "{}", DUTY_CYCLE_EXCEEDED_MSG);
+}
+
+// Log fragment sets that could not be reassembled with DEBUG level
+pub fn log_debug_fragments_discarded(count: u64, reason: &str) {
+    debug!(target: "network", count, reason, "{}", FRAGMENTS_DISCARDED_MSG);
+}
+
+// Log a serial device opened by a driver with INFO level
+pub fn log_serial_connected(driver: &str, path: &str) {
+    info!(target: "network", driver, path, "{}", SERIAL_CONNECTED_MSG);
+}
+
+// Log a serial device that failed to open or went away with WARN level
+pub fn log_serial_disconnected(driver: &str, path: &str, error: &str) {
+    warn!(target: "network", driver, path, %error, "{}", SERIAL_DISCONNECTED_MSG);
+}
+
+// Log a TCP client accepted by a server or a connection made by a client with INFO level
+pub fn log_tcp_connected(driver: &str, peer: &str) {
+    info!(target: "network", driver, peer, "{}", TCP_CONNECTED_MSG);
+}
+
+// Log a TCP connection that closed, failed or could not be made with WARN level
+pub fn log_tcp_disconnected(driver: &str, peer: &str, reason: &str) {
+    warn!(target: "network", driver, peer, reason, "{}", TCP_DISCONNECTED_MSG);
+}
+
+// Log a WebSocket peer that completed its handshake with INFO level
+pub fn log_websocket_connected(driver: &str, peer: &str) {
+    info!(target: "network", driver, peer, "{}", WEBSOCKET_CONNECTED_MSG);
+}
+
+// Log a WebSocket connection that closed, failed or could not be made with WARN level
+pub fn log_websocket_disconnected(driver: &str, peer: &str, reason: &str) {
+    warn!(target: "network", driver, peer, reason, "{}", WEBSOCKET_DISCONNECTED_MSG);
+}
+
+// Log a new peer heard by a UDP server with INFO level
+pub fn log_udp_peer_learned(driver: &str, peer: &str) {
+    info!(target: "network", driver, peer, "{}", UDP_PEER_LEARNED_MSG);
+}
+
+// Log a UDP server peer forgotten after the peer timeout with INFO level
+pub fn log_udp_peer_expired(driver: &str, peer: &str) {
+    info!(target: "network", driver, peer, "{}", UDP_PEER_EXPIRED_MSG);
+}
+
+// Log received data that held no valid packet with DEBUG level
+pub fn log_debug_received_data_dropped(driver: &str, error: &str) {
+    debug!(target: "network", driver, %error, "{}", RECEIVED_DATA_DROPPED_MSG);
+}
+
+// Log a failed transmission attempted again with WARN level
+pub fn log_transmit_retry(driver: &str, error: &str, attempt: u32) {
+    warn!(target: "network", driver, %error, attempt, "{}", TRANSMIT_RETRY_MSG);
+}
+
+// Log a network interface pausing after a driver error with WARN level
+pub fn log_driver_backoff(driver: &str, delay: std::time::Duration) {
+    warn!(target: "network", driver, delay_ms = delay.as_millis() as u64, "{}", DRIVER_BACKOFF_MSG);
+}
+
+// Log a stalled radio that was re-initialized with WARN level
+pub fn log_radio_recovery(driver: &str, reason: &str, count: u64) {
+    warn!(target: "network", driver, reason, count, "{}", RADIO_RECOVERY_MSG);
+}
+
+// Log a stalled radio that could not be re-initialized with ERROR level
+pub fn log_radio_recovery_failed(driver: &str, reason: &str, error: &str) {
+    error!(target: "network", driver, reason, %error, "{}", RADIO_RECOVERY_FAILED_MSG);
+}
+
+// Log a transition of the half-duplex radio state machine with DEBUG level
+pub fn log_debug_radio_state<State: Debug>(driver: &str, from: State, to: State) {
+    debug!(target: "network", driver, ?from, ?to, "{}", RADIO_STATE_MSG);
+}
+
+// Log a transmission deferred because another node is using the channel with DEBUG level
+pub fn log_debug_channel_busy(driver: &str, attempt: u32, backoff: std::time::Duration) {
+    debug!(target: "network", driver, attempt, backoff_ms = backoff.as_millis() as u64, "{}", CHANNEL_BUSY_MSG);
+}
+
+// Log packets dropped because the channel stayed busy for all attempts with WARN level
+pub fn log_channel_access_failed(driver: &str, attempts: u32, packets: usize) {
+    warn!(target: "network", driver, attempts, packets, "{}", CHANNEL_ACCESS_FAILED_MSG);
+}
+
+// Log a correction of the TDMA network time from a time sample with DEBUG level
+pub fn log_debug_tdma_sync(link: &str, source: &str, correction_us: i64) {
+    debug!(target: "network", link, source, correction_us, "{}", TDMA_SYNC_MSG);
+}
+
+// Log a reliable payload retransmitted after its acknowledgement timed out with DEBUG level
+pub fn log_debug_arq_retransmission(sequence: u8, retransmission: u32, timeout: std::time::Duration) {
+    debug!(target: "network", sequence, retransmission, timeout_ms = timeout.as_millis() as u64, "{}", ARQ_RETRANSMISSION_MSG);
+}
+
+// Log a reliable payload given up without acknowledgement with WARN level
+pub fn log_arq_delivery_failed(sequence: u8, retransmissions: u32, reason: &str) {
+    warn!(target: "network", sequence, retransmissions, reason, "{}", ARQ_DELIVERY_FAILED_MSG);
+}
+
+// Log a received frame dropped for a missing, invalid or replayed signature with WARN level
+pub fn log_signature_rejected(system_id: u8, component_id: u8, reason: &str) {
+    warn!(target: "network", system_id, component_id, reason, "{}", SIGNATURE_REJECTED_MSG);
+}
+
+// Log a received payload dropped by the encryption layer with WARN level
+pub fn log_encrypted_payload_rejected(reason: &str) {
+    warn!(target: "network", reason, "{}", ENCRYPTED_PAYLOAD_REJECTED_MSG);
+}
+
+// Log the address the metrics endpoint listens on with INFO level
+pub fn log_metrics_server_running(addr: &str) {
+    info!(target: "network", addr, "{}", METRICS_SERVER_RUNNING_MSG);
+}
+
+// Log a scrape that could not be answered with DEBUG level
+pub fn log_debug_metrics_request_failed(error: &str) {
+    debug!(target: "network", error, "{}", METRICS_REQUEST_FAILED_MSG);
+}
+
+// Log the signal and noise a peer reported in its RADIO_STATUS with DEBUG level
+pub fn log_debug_peer_radio_status(driver: &str, rssi: u8, noise: u8) {
+    debug!(target: "network", driver, rssi, noise, "{}", PEER_RADIO_STATUS_MSG);
+}
+
+// Log a modulation change agreed with the peer with INFO level
+pub fn log_adr_modulation_change(driver: &str, spreading_factor: u8, bandwidth_hz: u32, reason: &str) {
+    info!(target: "network", driver, spreading_factor, bandwidth_hz, reason, "{}", ADR_MODULATION_CHANGE_MSG);
+}
+
+// Log a return to the fallback modulation after hearing nothing from the peer with WARN level
+pub fn log_adr_fallback(driver: &str, spreading_factor: u8, bandwidth_hz: u32) {
+    warn!(target: "network", driver, spreading_factor, bandwidth_hz, "{}", ADR_FALLBACK_MSG);
+}
+
+// Log a modulation change proposed to the peer with DEBUG level
+pub fn log_debug_adr_proposal(spreading_factor: u8, bandwidth_hz: u32, attempt: u32) {
+    debug!(target: "network", spreading_factor, bandwidth_hz, attempt, "{}", ADR_PROPOSAL_MSG);
+}
+
+// Log a proposal the peer denied or did not acknowledge with DEBUG level
+pub fn log_debug_adr_proposal_failed(spreading_factor: u8, bandwidth_hz: u32, reason: &str) {
+    debug!(target: "network", spreading_factor, bandwidth_hz, reason, "{}", ADR_PROPOSAL_FAILED_MSG);
+}
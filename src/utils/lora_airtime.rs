@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+/// Largest payload of a LoRa packet
+pub const MAX_LORA_PAYLOAD_LENGTH: usize = 255;
+/// Symbol durations from this value upwards require low data rate optimization
+const LOW_DATA_RATE_OPTIMIZE_SYMBOL_MICROS: u64 = 16_000;
+
+/// Hardware independent description of a LoRa modulation, used for time-on-air calculations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoRaModulation {
+    pub spreading_factor: u8,
+    pub bandwidth_hz: u32,
+    /// Denominator of the coding rate, 5 to 8 for 4/5 to 4/8
+    pub coding_rate: u8,
+    pub frequency_hz: u32,
+    pub preamble_length: u16,
+    pub implicit_header: bool,
+    pub crc_enabled: bool,
+}
+
+impl LoRaModulation {
+    pub fn symbol_duration(&self) -> Duration {
+        Duration::from_micros((1_000_000u64 << self.spreading_factor) / self.bandwidth_hz as u64)
+    }
+
+    pub fn low_data_rate_optimize(&self) -> bool {
+        self.symbol_duration().as_micros() as u64 >= LOW_DATA_RATE_OPTIMIZE_SYMBOL_MICROS
+    }
+
+    /// Lowest SNR a packet is still demodulated at with the spreading factor, following the Semtech datasheets
+    pub fn demodulation_floor_db(&self) -> f64 {
+        -2.5 * (self.spreading_factor as f64 - 4.0)
+    }
+
+    /// Time on air of a packet carrying `payload_length` bytes, following the Semtech SX127x/SX126x datasheets
+    pub fn time_on_air(&self, payload_length: usize) -> Duration {
+        let spreading_factor = self.spreading_factor as i64;
+        let low_data_rate_optimize = self.low_data_rate_optimize() as i64;
+
+        let numerator = 8 * payload_length as i64 - 4 * spreading_factor + 28 + 16 * self.crc_enabled as i64
+            - 20 * self.implicit_header as i64;
+        let denominator = 4 * (spreading_factor - 2 * low_data_rate_optimize);
+        let payload_symbols = 8 + (numerator.max(0) + denominator - 1) / denominator * self.coding_rate as i64;
+
+        // Preamble symbols plus 4.25 symbols of sync word, kept in quarter symbols to stay in integers
+        let quarter_symbols = (self.preamble_length as i64 + payload_symbols) * 4 + 17;
+        let symbol_nanos = (1_000_000_000u64 << self.spreading_factor) / self.bandwidth_hz as u64;
+        Duration::from_nanos(quarter_symbols as u64 * symbol_nanos / 4)
+    }
+}
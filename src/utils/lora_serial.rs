@@ -5,9 +5,7 @@ use std::{str, thread};
 use rppal::gpio::{Gpio, InputPin, OutputPin};
 use rppal::uart::{Parity, Uart};
 
-const M0_PIN: u8 = 22;
-const M1_PIN: u8 = 27;
-const AUX_PIN: u8 = 7;
+use super::config::E22PinMap;
 
 // Define UART Baud Rates as enum
 #[allow(dead_code)]
@@ -32,6 +30,18 @@ pub enum PackageSize {
     Size32Byte = 0xC0,
 }
 
+impl PackageSize {
+    pub fn from_bytes(bytes: u16) -> Option<Self> {
+        match bytes {
+            240 => Some(PackageSize::Size240Byte),
+            128 => Some(PackageSize::Size128Byte),
+            64 => Some(PackageSize::Size64Byte),
+            32 => Some(PackageSize::Size32Byte),
+            _ => None,
+        }
+    }
+}
+
 // Define Power Levels as enum
 #[derive(Debug, Clone, Copy)]
 pub enum PowerLevel {
@@ -40,6 +50,18 @@ pub enum PowerLevel {
     Power13dBm = 0x02,
     Power10dBm = 0x03,
 }
+
+impl PowerLevel {
+    pub fn from_dbm(dbm: u8) -> Option<Self> {
+        match dbm {
+            22 => Some(PowerLevel::Power22dBm),
+            17 => Some(PowerLevel::Power17dBm),
+            13 => Some(PowerLevel::Power13dBm),
+            10 => Some(PowerLevel::Power10dBm),
+            _ => None,
+        }
+    }
+}
 #[derive(Debug, Clone, Copy)]
 pub enum AirSpeed {
     Speed1200 = 0x01,
@@ -51,6 +73,21 @@ pub enum AirSpeed {
     Speed62500 = 0x07,
 }
 
+impl AirSpeed {
+    pub fn from_bps(bps: u32) -> Option<Self> {
+        match bps {
+            1200 => Some(AirSpeed::Speed1200),
+            2400 => Some(AirSpeed::Speed2400),
+            4800 => Some(AirSpeed::Speed4800),
+            9600 => Some(AirSpeed::Speed9600),
+            19200 => Some(AirSpeed::Speed19200),
+            38400 => Some(AirSpeed::Speed38400),
+            62500 => Some(AirSpeed::Speed62500),
+            _ => None,
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub struct Sx1262UartE22 {
     m0: OutputPin,
@@ -70,11 +107,11 @@ pub struct ReceiveResult {
 }
 
 impl Sx1262UartE22 {
-    pub fn new(serial_port: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(serial_port: &str, pins: &E22PinMap) -> Result<Self, Box<dyn std::error::Error>> {
         let gpio = Gpio::new()?;
-        let m0 = gpio.get(M0_PIN)?.into_output();
-        let m1 = gpio.get(M1_PIN)?.into_output();
-        let aux = gpio.get(AUX_PIN)?.into_input();
+        let m0 = gpio.get(pins.m0)?.into_output();
+        let m1 = gpio.get(pins.m1)?.into_output();
+        let aux = gpio.get(pins.aux)?.into_input();
         let cfg = vec![0xC2, 0x00, 0x09, 0x00, 0x00, 0x00, 0x62, 0x00, 0x12, 0x43, 0x00, 0x00];
 
         let uart = Uart::with_path(serial_port, 9600, Parity::None, 8, 1)?;
@@ -160,23 +197,21 @@ impl Sx1262UartE22 {
         Ok(())
     }
 
-    pub fn receive(&mut self) -> Option<ReceiveResult> {
-        if let Ok(r_buff) = self.read() {
-            if !r_buff.is_empty() {
-                let _addr = ((r_buff[0] as u16) << 8) + r_buff[1] as u16;
-                let _freq = r_buff[2] as u16 + self.start_freq;
-                let noise = r_buff[r_buff.len() - 1];
-                let rssi = self.get_channel_rssi().unwrap();
-                return Some(ReceiveResult {
-                    data: r_buff[3..r_buff.len()].to_vec(),
-                    rssi: Some(rssi),
-                    snr: Some(noise),
-                });
-            } else {
-                return None;
-            }
+    pub fn receive(&mut self) -> Result<Option<ReceiveResult>, Box<dyn std::error::Error>> {
+        let r_buff = self.read()?;
+        if r_buff.is_empty() {
+            return Ok(None);
         }
-        None
+        let _addr = ((r_buff[0] as u16) << 8) + r_buff[1] as u16;
+        let _freq = r_buff[2] as u16 + self.start_freq;
+        let noise = r_buff[r_buff.len() - 1];
+        // The packet is still worth handing out when the RSSI query fails
+        let rssi = self.get_channel_rssi().ok();
+        Ok(Some(ReceiveResult {
+            data: r_buff[3..r_buff.len()].to_vec(),
+            rssi,
+            snr: Some(noise),
+        }))
     }
 
     #[allow(dead_code)]
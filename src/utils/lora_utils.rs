@@ -1,56 +1,68 @@
 use std::error::Error;
-use std::sync::Arc;
 
 use embedded_hal_bus::spi::ExclusiveDevice;
+use lora_phy::mod_params::{Bandwidth, CodingRate, ModulationParams, PacketParams, SpreadingFactor};
+use lora_phy::mod_traits::RadioKind;
 use lora_phy::sx126x::{self, Sx126x, Sx126xVariant};
 use lora_phy::sx127x::{self, Sx127x, Sx127xVariant};
-use lora_phy::LoRa;
+use lora_phy::{DelayNs, LoRa};
 use rppal::gpio::{Gpio, Trigger};
 use rppal::hal::Delay;
 use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
-use tokio::sync::{mpsc, Notify};
+use tokio::sync::{mpsc, watch};
 
 use super::adapter::BlockingAsync;
+use super::config::{Sx1262PinMap, Sx1276PinMap};
 use super::delay_adapter::WithDelayNs;
 use super::iv::{GenericSx126xInterfaceVariant, GenericSx127xInterfaceVariant};
+use super::lora_airtime::LoRaModulation;
 use super::lora_types::{LoRaDeviceSx126x, LoRaDeviceSx127x, SpiDevice};
+use crate::driver::DriverError;
 
 pub const LORA_FREQUENCY_IN_HZ: u32 = 869_525_000;
 
-const LORA_SX1276_CS_PIN: u8 = 25;
-const LORA_SX1276_RESET_PIN: u8 = 17;
-const LORA_SX1276_DIO0_PIN: u8 = 4;
-// const LORA_BUSY_PIN: u8 = 11;
+/// Stream of the IRQs raised by a radio, owned by each consumer.
+///
+/// Holds the number of IRQs raised so far, so every receiver sees each new IRQ on its own and
+/// waiting for one with `changed` is cancellation safe.
+pub type IrqEvents = watch::Receiver<u64>;
 
-const LORA_SX1262_CS_PIN: u8 = 21;
-const LORA_SX1262_RESET_PIN: u8 = 18;
-const LORA_SX1262_DIO1_PIN: u8 = 16;
-const LORA_SX1262_DIO4_PIN: u8 = 6;
-const LORA_SX1262_BUSY_PIN: u8 = 20;
+fn irq_event_stream() -> (watch::Sender<u64>, IrqEvents) {
+    watch::channel(0)
+}
+
+fn raise_irq(irq_events: &watch::Sender<u64>) {
+    irq_events.send_modify(|count| *count = count.wrapping_add(1));
+}
 
-pub fn create_spi() -> Result<SpiDevice, Box<dyn Error>> {
+pub fn create_spi(cs_pin: u8) -> Result<SpiDevice, Box<dyn Error>> {
     let gpio = Gpio::new().unwrap();
-    let nss = gpio.get(LORA_SX1276_CS_PIN).unwrap().into_output();
+    let nss = gpio.get(cs_pin).unwrap().into_output();
     let spi_bus = BlockingAsync::new(Spi::new(Bus::Spi0, SlaveSelect::Ss0, 20_000, Mode::Mode0).unwrap());
     let spi = ExclusiveDevice::new(spi_bus, nss, WithDelayNs::new(Delay));
     Ok(spi)
 }
-pub fn create_spi_sx1262() -> Result<SpiDevice, Box<dyn Error>> {
+pub fn create_spi_sx1262(cs_pin: u8) -> Result<SpiDevice, Box<dyn Error>> {
     let gpio = Gpio::new().unwrap();
-    let nss = gpio.get(LORA_SX1262_CS_PIN).unwrap().into_output();
+    let nss = gpio.get(cs_pin).unwrap().into_output();
     let spi_bus = BlockingAsync::new(Spi::new(Bus::Spi0, SlaveSelect::Ss0, 20_000, Mode::Mode0).unwrap());
     let spi = ExclusiveDevice::new(spi_bus, nss, WithDelayNs::new(Delay));
     Ok(spi)
 }
 
-pub async fn create_lora_sx1276_spi(spi: SpiDevice) -> Result<LoRaDeviceSx127x, Box<dyn Error>> {
+/// Creates the LoRa instance of an SX1276 together with the stream of its IRQs
+pub async fn create_lora_sx1276_spi(
+    spi: SpiDevice,
+    pins: &Sx1276PinMap,
+) -> Result<(LoRaDeviceSx127x, IrqEvents), Box<dyn Error>> {
     let gpio = Gpio::new().unwrap();
-    let mut reset = gpio.get(LORA_SX1276_RESET_PIN).unwrap().into_output();
-    let mut dio0: rppal::gpio::InputPin = gpio.get(LORA_SX1276_DIO0_PIN).unwrap().into_input_pullup();
-    let (interrupt_tx, interrupt_rx) = mpsc::channel(3);
+    let mut reset = gpio.get(pins.reset).unwrap().into_output();
+    let mut dio0: rppal::gpio::InputPin = gpio.get(pins.dio0).unwrap().into_input_pullup();
+    let (irq_tx, irq_events) = irq_event_stream();
+    let interrupt_rx = irq_tx.subscribe();
 
     let _ = dio0.set_async_interrupt(Trigger::RisingEdge, move |_| {
-        interrupt_tx.try_send(()).unwrap();
+        raise_irq(&irq_tx);
     });
 
     reset.set_high();
@@ -68,21 +80,25 @@ pub async fn create_lora_sx1276_spi(spi: SpiDevice) -> Result<LoRaDeviceSx127x,
         .await
         .unwrap();
 
-    Ok(lora)
+    Ok((lora, irq_events))
 }
 
-pub async fn create_lora_sx1262_spi(spi: SpiDevice) -> Result<LoRaDeviceSx126x, Box<dyn Error>> {
+/// Creates the LoRa instance of an SX1262 together with the stream of its IRQs
+pub async fn create_lora_sx1262_spi(
+    spi: SpiDevice,
+    pins: &Sx1262PinMap,
+) -> Result<(LoRaDeviceSx126x, IrqEvents), Box<dyn Error>> {
     let gpio = Gpio::new().unwrap();
-    let reset = gpio.get(LORA_SX1262_RESET_PIN).unwrap().into_output();
-    let mut dio1: rppal::gpio::InputPin = gpio.get(LORA_SX1262_DIO1_PIN).unwrap().into_input_pullup();
-    let dio4: rppal::gpio::OutputPin = gpio.get(LORA_SX1262_DIO4_PIN).unwrap().into_output();
-    let mut busy: rppal::gpio::InputPin = gpio.get(LORA_SX1262_BUSY_PIN).unwrap().into_input_pullup();
+    let reset = gpio.get(pins.reset).unwrap().into_output();
+    let mut dio1: rppal::gpio::InputPin = gpio.get(pins.dio1).unwrap().into_input_pullup();
+    let dio4: rppal::gpio::OutputPin = gpio.get(pins.dio4).unwrap().into_output();
+    let mut busy: rppal::gpio::InputPin = gpio.get(pins.busy).unwrap().into_input_pullup();
     // let (interrupt_tx, interrupt_rx) = mpsc::channel(3);
     let (interrupt_busy_tx, interrupt_busy_rx) = mpsc::channel(3);
-    let notify = Arc::new(Notify::new());
-    let notify_for_interrupt = notify.clone();
+    let (irq_tx, irq_events) = irq_event_stream();
+    let interrupt_rx = irq_tx.subscribe();
     let _ = dio1.set_async_interrupt(Trigger::RisingEdge, move |_| {
-        notify_for_interrupt.notify_one();
+        raise_irq(&irq_tx);
     });
 
     // let _ = busy.set_async_interrupt(Trigger::FallingEdge, move |_| {
@@ -96,12 +112,177 @@ pub async fn create_lora_sx1262_spi(spi: SpiDevice) -> Result<LoRaDeviceSx126x,
         use_dio2_as_rfswitch: true,
     };
 
-    let iv =
-        GenericSx126xInterfaceVariant::new(reset, dio1, busy, None, Some(dio4), notify, interrupt_busy_rx).unwrap();
+    let iv = GenericSx126xInterfaceVariant::new(reset, dio1, busy, None, Some(dio4), interrupt_rx, interrupt_busy_rx)
+        .unwrap();
 
     let lora = LoRa::new(Sx126x::new(spi, iv, config), false, WithDelayNs::new(Delay))
         .await
         .unwrap();
 
-    Ok(lora)
+    Ok((lora, irq_events))
+}
+
+pub fn spreading_factor_from_value(value: u8) -> Option<SpreadingFactor> {
+    match value {
+        5 => Some(SpreadingFactor::_5),
+        6 => Some(SpreadingFactor::_6),
+        7 => Some(SpreadingFactor::_7),
+        8 => Some(SpreadingFactor::_8),
+        9 => Some(SpreadingFactor::_9),
+        10 => Some(SpreadingFactor::_10),
+        11 => Some(SpreadingFactor::_11),
+        12 => Some(SpreadingFactor::_12),
+        _ => None,
+    }
+}
+
+pub fn bandwidth_from_hz(hz: u32) -> Option<Bandwidth> {
+    match hz {
+        7_810 => Some(Bandwidth::_7KHz),
+        10_420 => Some(Bandwidth::_10KHz),
+        15_630 => Some(Bandwidth::_15KHz),
+        20_830 => Some(Bandwidth::_20KHz),
+        31_250 => Some(Bandwidth::_31KHz),
+        41_670 => Some(Bandwidth::_41KHz),
+        62_500 => Some(Bandwidth::_62KHz),
+        125_000 => Some(Bandwidth::_125KHz),
+        250_000 => Some(Bandwidth::_250KHz),
+        500_000 => Some(Bandwidth::_500KHz),
+        _ => None,
+    }
+}
+
+pub fn coding_rate_from_denominator(denominator: u8) -> Option<CodingRate> {
+    match denominator {
+        5 => Some(CodingRate::_4_5),
+        6 => Some(CodingRate::_4_6),
+        7 => Some(CodingRate::_4_7),
+        8 => Some(CodingRate::_4_8),
+        _ => None,
+    }
+}
+
+pub fn spreading_factor_value(spreading_factor: SpreadingFactor) -> u8 {
+    match spreading_factor {
+        SpreadingFactor::_5 => 5,
+        SpreadingFactor::_6 => 6,
+        SpreadingFactor::_7 => 7,
+        SpreadingFactor::_8 => 8,
+        SpreadingFactor::_9 => 9,
+        SpreadingFactor::_10 => 10,
+        SpreadingFactor::_11 => 11,
+        SpreadingFactor::_12 => 12,
+    }
+}
+
+pub fn bandwidth_hz(bandwidth: Bandwidth) -> u32 {
+    match bandwidth {
+        Bandwidth::_7KHz => 7_810,
+        Bandwidth::_10KHz => 10_420,
+        Bandwidth::_15KHz => 15_630,
+        Bandwidth::_20KHz => 20_830,
+        Bandwidth::_31KHz => 31_250,
+        Bandwidth::_41KHz => 41_670,
+        Bandwidth::_62KHz => 62_500,
+        Bandwidth::_125KHz => 125_000,
+        Bandwidth::_250KHz => 250_000,
+        Bandwidth::_500KHz => 500_000,
+    }
+}
+
+pub fn coding_rate_denominator(coding_rate: CodingRate) -> u8 {
+    match coding_rate {
+        CodingRate::_4_5 => 5,
+        CodingRate::_4_6 => 6,
+        CodingRate::_4_7 => 7,
+        CodingRate::_4_8 => 8,
+    }
+}
+
+/// Hardware independent modulation of the lora-phy settings, for airtime calculations
+pub fn lora_modulation(
+    spreading_factor: SpreadingFactor,
+    bandwidth: Bandwidth,
+    coding_rate: CodingRate,
+    frequency_hz: u32,
+    preamble_length: u16,
+    implicit_header: bool,
+    crc_enabled: bool,
+) -> LoRaModulation {
+    LoRaModulation {
+        spreading_factor: spreading_factor_value(spreading_factor),
+        bandwidth_hz: bandwidth_hz(bandwidth),
+        coding_rate: coding_rate_denominator(coding_rate),
+        frequency_hz,
+        preamble_length,
+        implicit_header,
+        crc_enabled,
+    }
+}
+
+/// Modulation and packet params of an SX1276 or SX1262 radio on SPI
+#[derive(Clone)]
+pub struct LoRaSpiConfig {
+    pub modulation: LoRaModulation,
+    pub modulation_params: ModulationParams,
+    pub rx_pkt_params: PacketParams,
+    pub tx_pkt_params: PacketParams,
+    pub tx_power: i32,
+    pub tx_boost: bool,
+    pub max_payload_length: u8,
+    pub iq_inverted: bool,
+}
+
+impl LoRaSpiConfig {
+    /// Modulation and packet params of the radio for a modulation
+    pub fn new<RK: RadioKind, DLY: DelayNs>(
+        lora: &mut LoRa<RK, DLY>,
+        modulation: LoRaModulation,
+        max_payload_length: u8,
+        iq_inverted: bool,
+        tx_power: i32,
+        tx_boost: bool,
+    ) -> Result<Self, DriverError> {
+        let spreading_factor = spreading_factor_from_value(modulation.spreading_factor).ok_or_else(|| {
+            DriverError::Radio(format!("unsupported spreading factor {}", modulation.spreading_factor))
+        })?;
+        let bandwidth = bandwidth_from_hz(modulation.bandwidth_hz)
+            .ok_or_else(|| DriverError::Radio(format!("unsupported bandwidth {} Hz", modulation.bandwidth_hz)))?;
+        let coding_rate = coding_rate_from_denominator(modulation.coding_rate)
+            .ok_or_else(|| DriverError::Radio(format!("unsupported coding rate 4/{}", modulation.coding_rate)))?;
+
+        let modulation_params = lora
+            .create_modulation_params(spreading_factor, bandwidth, coding_rate, modulation.frequency_hz)
+            .map_err(DriverError::radio)?;
+        let rx_pkt_params = lora
+            .create_rx_packet_params(
+                modulation.preamble_length,
+                modulation.implicit_header,
+                max_payload_length,
+                modulation.crc_enabled,
+                iq_inverted,
+                &modulation_params,
+            )
+            .map_err(DriverError::radio)?;
+        let tx_pkt_params = lora
+            .create_tx_packet_params(
+                modulation.preamble_length,
+                modulation.implicit_header,
+                modulation.crc_enabled,
+                iq_inverted,
+                &modulation_params,
+            )
+            .map_err(DriverError::radio)?;
+
+        Ok(Self {
+            modulation,
+            modulation_params,
+            rx_pkt_params,
+            tx_pkt_params,
+            tx_power,
+            tx_boost,
+            max_payload_length,
+            iq_inverted,
+        })
+    }
 }
@@ -27,4 +27,41 @@ macro_rules! define_struct_with_defaults {
             }
         }
     };
-}
\ No newline at end of file
+}
+
+/// Implements `From<&$section>` for the optional init config of a LoRa SPI driver, `$section` being the
+/// [`LoRaSpiSection`](crate::config::LoRaSpiSection) with the pin map of its radio
+#[macro_export]
+macro_rules! impl_from_lora_spi_section {
+    ($section:ty, $config_struct:ident) => {
+        impl From<&$section> for $config_struct {
+            fn from(section: &$section) -> Self {
+                use $crate::utils::lora_utils::{
+                    bandwidth_from_hz, coding_rate_from_denominator, spreading_factor_from_value,
+                };
+
+                Self {
+                    spreading_factor: section.spreading_factor.map(|value| {
+                        spreading_factor_from_value(value).expect("Spreading factor validated at load time")
+                    }),
+                    bandwidth: section
+                        .bandwidth_hz
+                        .map(|hz| bandwidth_from_hz(hz).expect("Bandwidth validated at load time")),
+                    coding_rate: section.coding_rate.map(|value| {
+                        coding_rate_from_denominator(value).expect("Coding rate validated at load time")
+                    }),
+                    frequency: section.frequency_hz,
+                    max_payload_length: section.max_payload_length,
+                    pack_frames: section.pack_frames,
+                    tx_power: section.tx_power,
+                    tx_boost: section.tx_boost,
+                    preamble_length: section.preamble_length,
+                    implicit_header: section.implicit_header,
+                    crc_enabled: section.crc_enabled,
+                    iq_inverted: section.iq_inverted,
+                    pins: Some(section.pins),
+                }
+            }
+        }
+    };
+}
@@ -1,85 +1,681 @@
-use std::io::Cursor;
-use std::sync::atomic::{AtomicUsize, Ordering};
-
-use mavlink::ardupilotmega::MavMessage;
-use mavlink::{read_versioned_msg, MAVLinkV2MessageRaw, MavHeader};
-use tracing::error;
-
-use super::types::{MavFramePacket, NodeType};
-
-pub fn deserialize_frame(buffer: &[u8]) -> Option<MavFramePacket> {
-    let buffer_reader = &mut Cursor::new(buffer);
-    let buffer_reader2 = &mut Cursor::new(buffer);
-    match read_versioned_msg(buffer_reader, mavlink::MavlinkVersion::V2) {
-        Ok(packet) => Some(MavFramePacket {
-            header: packet.0,
-            msg: packet.1,
-            protocol_version: mavlink::MavlinkVersion::V2,
-        }),
-        Err(_) => match read_versioned_msg(buffer_reader2, mavlink::MavlinkVersion::V1) {
-            Ok(packet) => Some(MavFramePacket {
-                header: packet.0,
-                msg: packet.1,
-                protocol_version: mavlink::MavlinkVersion::V2,
-            }),
-            Err(e) => {
-                error!("Failed to deserialize mavlink frame: {:?}, eroor: {}", buffer, e);
-                None
-            }
-        },
-    }
-}
-
-pub fn serialize_frame(packet: MavFramePacket) -> Vec<u8> {
-    let mut message_raw = MAVLinkV2MessageRaw::new();
-    message_raw.serialize_message(packet.header, &packet.msg);
-    message_raw.raw_bytes().to_vec()
-}
-
-/// Create a heartbeat message using 'ardupilotmega' dialect
-pub fn heartbeat_message() -> MavMessage {
-    MavMessage::HEARTBEAT(mavlink::ardupilotmega::HEARTBEAT_DATA {
-        custom_mode: 0,
-        mavtype: mavlink::ardupilotmega::MavType::MAV_TYPE_GCS,
-        autopilot: mavlink::ardupilotmega::MavAutopilot::MAV_AUTOPILOT_INVALID,
-        base_mode: mavlink::ardupilotmega::MavModeFlag::empty(),
-        system_status: mavlink::ardupilotmega::MavState::MAV_STATE_UNINIT,
-        mavlink_version: 0x3,
-    })
-}
-
-pub struct MavlinkHeaderGenerator {
-    sequence: AtomicUsize,
-}
-
-impl MavlinkHeaderGenerator {
-    pub fn new() -> MavlinkHeaderGenerator {
-        MavlinkHeaderGenerator {
-            sequence: AtomicUsize::new(0),
-        }
-    }
-
-    fn create_mavlink_header(&self) -> MavHeader {
-        let node_type = NodeType::from_str(&std::env::var("NODE_TYPE").unwrap()).unwrap();
-        let system_id = match node_type {
-            NodeType::Uav => 201,
-            NodeType::Gateway => 101,
-        };
-
-        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
-
-        MavHeader {
-            sequence: sequence as u8,
-            system_id: system_id,
-            component_id: 1,
-        }
-    }
-
-    pub fn create_mavlink_heartbeat_frame(&self) -> MavFramePacket {
-        MavFramePacket {
-            header: self.create_mavlink_header(),
-            msg: heartbeat_message(),
-            protocol_version: mavlink::MavlinkVersion::V2,
-        }
-    }
-}
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mavlink::ardupilotmega::MavMessage;
+use mavlink::{read_versioned_msg, MAVLinkV2MessageRaw, MavHeader, MavlinkVersion, Message, MAV_STX, MAV_STX_V2};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use super::config::SigningSection;
+use super::logging_utils::log_signature_rejected;
+use super::metrics::metrics;
+use super::types::{MavFramePacket, NodeType};
+use crate::define_struct_with_defaults;
+
+pub const SIGNATURE_REJECTED_METRIC: &str = "mavlink_signature_rejected_total";
+pub const FRAME_DECODE_FAILURES_METRIC: &str = "frame_decode_failures_total";
+
+/// Incompatibility flag of MAVLink v2 frames carrying a 13 byte signature
+const MAVLINK_IFLAG_SIGNED: u8 = 0x01;
+const MAVLINK_V1_FRAME_OVERHEAD: usize = 8;
+const MAVLINK_V2_FRAME_OVERHEAD: usize = 12;
+const MAVLINK_SIGNATURE_LENGTH: usize = 13;
+const MAVLINK_V1_HEADER_LENGTH: usize = 6;
+const MAVLINK_V2_HEADER_LENGTH: usize = 10;
+/// Signature tail of a signed frame after its link ID and 48 bit timestamp
+const MAVLINK_SIGNATURE_HASH_LENGTH: usize = 6;
+pub const MAVLINK_SIGNING_KEY_LENGTH: usize = 32;
+/// Unix time of 2015-01-01 00:00:00 UTC, signing timestamps count 10 microsecond units from it
+const MAVLINK_SIGNING_EPOCH_S: u64 = 1_420_070_400;
+const SIGNING_TIMESTAMP_UNITS_PER_S: u64 = 100_000;
+/// How far the first timestamp of a stream may lag behind the local clock, as the MAVLink specification sets it
+const SIGNING_NEW_STREAM_MAX_LAG_S: u64 = 60;
+
+define_struct_with_defaults! {
+    SigningOptionalConfig, SigningConfig {
+        // Secret shared by the nodes of the link, the all zero default only suits simulations
+        secret_key: [u8; MAVLINK_SIGNING_KEY_LENGTH] = [0; MAVLINK_SIGNING_KEY_LENGTH],
+        // Link ID of the outgoing signatures
+        link_id: u8 = 0,
+        // Drops unsigned frames, otherwise they are accepted and only signed frames are verified
+        require_signatures: bool = true,
+        // How far behind the newest timestamp of a stream a frame not seen before is still accepted, so
+        // retransmitted and reordered frames are not taken for replays
+        replay_window_ms: u64 = 30_000,
+    }
+}
+
+impl From<&SigningSection> for SigningOptionalConfig {
+    fn from(section: &SigningSection) -> Self {
+        Self {
+            secret_key: parse_secret_key(&section.secret_key),
+            link_id: section.link_id,
+            require_signatures: section.require_signatures,
+            replay_window_ms: section.replay_window_ms,
+        }
+    }
+}
+
+/// Parses a 32 byte secret key written as 64 hexadecimal digits
+pub fn parse_secret_key(hex: &str) -> Option<[u8; MAVLINK_SIGNING_KEY_LENGTH]> {
+    if hex.len() != 2 * MAVLINK_SIGNING_KEY_LENGTH || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = [0; MAVLINK_SIGNING_KEY_LENGTH];
+    for (index, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * index..2 * index + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Deserializes the first MAVLink frame of a buffer, V1 or V2
+pub fn deserialize_frame(buffer: &[u8]) -> Option<MavFramePacket> {
+    let mut parser = MavlinkStreamParser::new();
+    parser.push(buffer);
+    let frame = parser.next_frame();
+    if frame.is_none() {
+        metrics().increment_counter(FRAME_DECODE_FAILURES_METRIC, &[]);
+        debug!("No valid mavlink frame in {:?}", buffer);
+    }
+    frame
+}
+
+pub fn serialize_frame(packet: MavFramePacket) -> Vec<u8> {
+    let mut message_raw = MAVLinkV2MessageRaw::new();
+    message_raw.serialize_message(packet.header, &packet.msg);
+    message_raw.raw_bytes().to_vec()
+}
+
+/// Length of the frame starting with the STX at the front of `buffer`, `None` while its header is incomplete
+fn frame_length(buffer: &[u8]) -> Option<usize> {
+    match buffer {
+        [MAV_STX_V2, payload_length, incompat_flags, ..] => {
+            let signature_length = if incompat_flags & MAVLINK_IFLAG_SIGNED != 0 {
+                MAVLINK_SIGNATURE_LENGTH
+            } else {
+                0
+            };
+            Some(MAVLINK_V2_FRAME_OVERHEAD + *payload_length as usize + signature_length)
+        }
+        [MAV_STX, payload_length, ..] => Some(MAVLINK_V1_FRAME_OVERHEAD + *payload_length as usize),
+        _ => None,
+    }
+}
+
+/// Message ID of a serialized frame, `None` when its header is incomplete
+pub fn frame_message_id(frame: &[u8]) -> Option<u32> {
+    match frame {
+        [MAV_STX_V2, _, _, _, _, _, _, id_low, id_middle, id_high, ..] => {
+            Some(u32::from_le_bytes([*id_low, *id_middle, *id_high, 0]))
+        }
+        [MAV_STX, _, _, _, _, id, ..] => Some(*id as u32),
+        _ => None,
+    }
+}
+
+/// Position and expected X.25 checksum of a complete frame, seeded with the CRC extra of its message
+fn frame_checksum(frame: &[u8]) -> Option<(usize, u16)> {
+    let header_length = match frame[0] {
+        MAV_STX_V2 => MAVLINK_V2_HEADER_LENGTH,
+        _ => MAVLINK_V1_HEADER_LENGTH,
+    };
+    let message_id = frame_message_id(frame)?;
+    let checksum_position = header_length + frame[1] as usize;
+    let extra_crc = <MavMessage as Message>::extra_crc(message_id);
+    let checksum = frame[1..checksum_position]
+        .iter()
+        .chain(std::iter::once(&extra_crc))
+        .fold(0xffff, |crc: u16, byte| {
+            let tmp = byte ^ crc as u8;
+            let tmp = tmp ^ (tmp << 4);
+            (crc >> 8) ^ ((tmp as u16) << 8) ^ ((tmp as u16) << 3) ^ ((tmp as u16) >> 4)
+        });
+    Some((checksum_position, checksum))
+}
+
+/// Checks the X.25 checksum of a complete frame
+fn frame_checksum_valid(frame: &[u8]) -> bool {
+    frame_checksum(frame).is_some_and(|(checksum_position, checksum)| {
+        checksum.to_le_bytes() == frame[checksum_position..checksum_position + 2]
+    })
+}
+
+/// Splits a payload into the MAVLink frames it carries, using the STX and length of each frame.
+/// Bytes that do not start a frame are skipped and a truncated frame at the end is dropped.
+pub fn split_frames(buffer: &[u8]) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    let mut position = 0;
+    while position < buffer.len() {
+        let remaining = &buffer[position..];
+        if !matches!(remaining[0], MAV_STX_V2 | MAV_STX) {
+            position += 1;
+            continue;
+        }
+        match frame_length(remaining) {
+            Some(frame_length) if frame_length <= remaining.len() => {
+                frames.push(remaining[..frame_length].to_vec());
+                position += frame_length;
+            }
+            _ => break,
+        }
+    }
+    frames
+}
+
+/// Stateful MAVLink parser for byte streams whose reads do not align with frame boundaries.
+///
+/// Chunks are buffered until they complete a frame. Bytes that do not start a frame count as a
+/// framing error and are skipped up to the next STX. A frame failing its checksum counts as a CRC
+/// error and only its STX is skipped, so the parser resynchronizes even when the length byte was
+/// the corrupted one. With signing, frames failing verification are dropped as a whole.
+#[derive(Debug, Default)]
+pub struct MavlinkStreamParser {
+    buffer: Vec<u8>,
+    crc_errors: u64,
+    framing_errors: u64,
+    signing: Option<Arc<MavlinkSigning>>,
+}
+
+impl MavlinkStreamParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parser handing out only the frames that pass the signature verification of `signing`
+    pub fn with_signing(signing: Arc<MavlinkSigning>) -> Self {
+        Self {
+            signing: Some(signing),
+            ..Self::default()
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Next complete frame of the buffered stream, `None` until more bytes arrive
+    pub fn next_frame(&mut self) -> Option<MavFramePacket> {
+        loop {
+            let start = self
+                .buffer
+                .iter()
+                .position(|byte| matches!(*byte, MAV_STX_V2 | MAV_STX))
+                .unwrap_or(self.buffer.len());
+            if start > 0 {
+                self.framing_errors += 1;
+                self.buffer.drain(..start);
+            }
+
+            let frame_length = frame_length(&self.buffer)?;
+            if frame_length > self.buffer.len() {
+                return None;
+            }
+            if !frame_checksum_valid(&self.buffer[..frame_length]) {
+                self.crc_errors += 1;
+                self.buffer.drain(..1);
+                continue;
+            }
+            if let Some(signing) = &self.signing {
+                if !signing.verify(&self.buffer[..frame_length]) {
+                    self.buffer.drain(..frame_length);
+                    continue;
+                }
+            }
+
+            let frame: Vec<u8> = self.buffer.drain(..frame_length).collect();
+            let protocol_version = match frame[0] {
+                MAV_STX_V2 => MavlinkVersion::V2,
+                _ => MavlinkVersion::V1,
+            };
+            match read_versioned_msg(&mut Cursor::new(&frame), protocol_version) {
+                Ok((header, msg)) => {
+                    return Some(MavFramePacket {
+                        header,
+                        msg,
+                        protocol_version,
+                    })
+                }
+                // A valid checksum over a message this dialect cannot decode
+                Err(_) => self.framing_errors += 1,
+            }
+        }
+    }
+
+    pub fn crc_errors(&self) -> u64 {
+        self.crc_errors
+    }
+
+    pub fn framing_errors(&self) -> u64 {
+        self.framing_errors
+    }
+}
+
+/// Groups frames into payloads of at most `max_payload_length` bytes, in order.
+/// A frame longer than the limit gets a payload of its own.
+pub fn pack_frames(frames: Vec<Vec<u8>>, max_payload_length: usize) -> Vec<Vec<u8>> {
+    let mut payloads: Vec<Vec<u8>> = Vec::new();
+    for frame in frames {
+        match payloads.last_mut() {
+            Some(payload) if payload.len() + frame.len() <= max_payload_length => payload.extend(frame),
+            _ => payloads.push(frame),
+        }
+    }
+    payloads
+}
+
+// The generated messages have no common accessor for their target fields
+macro_rules! match_target {
+    ($msg:expr, system_and_component: [$($both:ident),* $(,)?], system: [$($system:ident),* $(,)?]) => {
+        match $msg {
+            $(MavMessage::$both(data) => Some((data.target_system, data.target_component)),)*
+            $(MavMessage::$system(data) => Some((data.target_system, 0)),)*
+            _ => None,
+        }
+    };
+}
+
+/// Returns the `(target_system, target_component)` of a targeted message, component 0 when it only targets a system
+pub fn message_target(msg: &MavMessage) -> Option<(u8, u8)> {
+    match_target!(
+        msg,
+        system_and_component: [
+            AUTOPILOT_STATE_FOR_GIMBAL_DEVICE, AUTOPILOT_VERSION_REQUEST, CANFD_FRAME, CAN_FILTER_MODIFY,
+            CAN_FRAME, COMMAND_CANCEL, COMMAND_INT, COMMAND_LONG, CUBEPILOT_FIRMWARE_UPDATE_RESP,
+            CUBEPILOT_FIRMWARE_UPDATE_START, DEVICE_OP_READ, DEVICE_OP_WRITE, DIGICAM_CONFIGURE,
+            DIGICAM_CONTROL, FENCE_FETCH_POINT, FENCE_POINT, FILE_TRANSFER_PROTOCOL, GIMBAL_CONTROL,
+            GIMBAL_DEVICE_ATTITUDE_STATUS, GIMBAL_DEVICE_SET_ATTITUDE, GIMBAL_MANAGER_SET_ATTITUDE,
+            GIMBAL_MANAGER_SET_MANUAL_CONTROL, GIMBAL_MANAGER_SET_PITCHYAW, GIMBAL_REPORT,
+            GIMBAL_TORQUE_CMD_REPORT, GOPRO_GET_REQUEST, GOPRO_SET_REQUEST, GPS_INJECT_DATA, LED_CONTROL,
+            LOGGING_ACK, LOGGING_DATA, LOGGING_DATA_ACKED, LOG_ERASE, LOG_REQUEST_DATA, LOG_REQUEST_END,
+            LOG_REQUEST_LIST, MISSION_ACK, MISSION_CLEAR_ALL, MISSION_COUNT, MISSION_ITEM, MISSION_ITEM_INT,
+            MISSION_REQUEST, MISSION_REQUEST_INT, MISSION_REQUEST_LIST, MISSION_REQUEST_PARTIAL_LIST,
+            MISSION_SET_CURRENT, MISSION_WRITE_PARTIAL_LIST, MOUNT_CONFIGURE, MOUNT_CONTROL, MOUNT_STATUS,
+            OPEN_DRONE_ID_AUTHENTICATION, OPEN_DRONE_ID_BASIC_ID, OPEN_DRONE_ID_LOCATION,
+            OPEN_DRONE_ID_MESSAGE_PACK, OPEN_DRONE_ID_OPERATOR_ID, OPEN_DRONE_ID_SELF_ID,
+            OPEN_DRONE_ID_SYSTEM, OPEN_DRONE_ID_SYSTEM_UPDATE, OSD_PARAM_CONFIG, OSD_PARAM_SHOW_CONFIG,
+            PARAM_EXT_REQUEST_LIST, PARAM_EXT_REQUEST_READ, PARAM_EXT_SET, PARAM_MAP_RC, PARAM_REQUEST_LIST,
+            PARAM_REQUEST_READ, PARAM_SET, PING, PLAY_TUNE, PLAY_TUNE_V2, RALLY_FETCH_POINT, RALLY_POINT,
+            RC_CHANNELS_OVERRIDE, REMOTE_LOG_BLOCK_STATUS, REMOTE_LOG_DATA_BLOCK, REQUEST_DATA_STREAM,
+            REQUEST_EVENT, RESPONSE_EVENT_ERROR, SAFETY_SET_ALLOWED_AREA, SETUP_SIGNING,
+            SET_ACTUATOR_CONTROL_TARGET, SET_ATTITUDE_TARGET, SET_MAG_OFFSETS,
+            SET_POSITION_TARGET_GLOBAL_INT, SET_POSITION_TARGET_LOCAL_NED, SUPPORTED_TUNES, TUNNEL,
+            V2_EXTENSION
+        ],
+        system: [
+            CAMERA_FEEDBACK, CAMERA_STATUS, CHANGE_OPERATOR_CONTROL, SET_GPS_GLOBAL_ORIGIN,
+            SET_HOME_POSITION, SET_MODE
+        ]
+    )
+}
+
+/// Create a heartbeat message using 'ardupilotmega' dialect
+pub fn heartbeat_message() -> MavMessage {
+    MavMessage::HEARTBEAT(mavlink::ardupilotmega::HEARTBEAT_DATA {
+        custom_mode: 0,
+        mavtype: mavlink::ardupilotmega::MavType::MAV_TYPE_GCS,
+        autopilot: mavlink::ardupilotmega::MavAutopilot::MAV_AUTOPILOT_INVALID,
+        base_mode: mavlink::ardupilotmega::MavModeFlag::empty(),
+        system_status: mavlink::ardupilotmega::MavState::MAV_STATE_UNINIT,
+        mavlink_version: 0x3,
+    })
+}
+
+/// System ID the node uses in the frames it creates itself
+pub fn node_system_id(node_type: NodeType) -> u8 {
+    match node_type {
+        NodeType::Uav => 201,
+        NodeType::Gateway => 101,
+    }
+}
+
+pub struct MavlinkHeaderGenerator {
+    sequence: AtomicUsize,
+}
+
+impl Default for MavlinkHeaderGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MavlinkHeaderGenerator {
+    pub fn new() -> MavlinkHeaderGenerator {
+        MavlinkHeaderGenerator {
+            sequence: AtomicUsize::new(0),
+        }
+    }
+
+    fn create_mavlink_header(&self) -> MavHeader {
+        let node_type = NodeType::from_str(&std::env::var("NODE_TYPE").unwrap()).unwrap();
+        let system_id = node_system_id(node_type);
+
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+
+        MavHeader {
+            sequence: sequence as u8,
+            system_id,
+            component_id: 1,
+        }
+    }
+
+    pub fn create_mavlink_heartbeat_frame(&self) -> MavFramePacket {
+        MavFramePacket {
+            header: self.create_mavlink_header(),
+            msg: heartbeat_message(),
+            protocol_version: mavlink::MavlinkVersion::V2,
+        }
+    }
+}
+
+/// Current time as a signing timestamp, in 10 microsecond units since 2015
+pub fn signing_timestamp_now() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .saturating_sub(std::time::Duration::from_secs(MAVLINK_SIGNING_EPOCH_S));
+    since_epoch.as_micros() as u64 / (1_000_000 / SIGNING_TIMESTAMP_UNITS_PER_S)
+}
+
+/// Signing timestamps accepted per stream, rejecting replays the way MAVLink signing does.
+///
+/// A timestamp is accepted when it was not accepted before on its stream and is not older than the
+/// replay window behind the newest one. The first timestamp of a stream must not lag more than a
+/// minute behind the local clock.
+pub struct ReplayProtection<K> {
+    window: u64,
+    // Timestamps accepted within the window, older ones are rejected without looking them up
+    streams: HashMap<K, BTreeSet<u64>>,
+}
+
+impl<K: Eq + Hash> ReplayProtection<K> {
+    pub fn new(window_ms: u64) -> Self {
+        Self {
+            window: window_ms * SIGNING_TIMESTAMP_UNITS_PER_S / 1000,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Records the timestamp of a stream, false when it is a replay
+    pub fn record(&mut self, stream: K, timestamp: u64) -> bool {
+        let fresh = match self.streams.get(&stream) {
+            Some(accepted) => {
+                !accepted.contains(&timestamp)
+                    && accepted.last().is_some_and(|newest| timestamp + self.window >= *newest)
+            }
+            None => timestamp + SIGNING_NEW_STREAM_MAX_LAG_S * SIGNING_TIMESTAMP_UNITS_PER_S >= signing_timestamp_now(),
+        };
+        if !fresh {
+            return false;
+        }
+        let accepted = self.streams.entry(stream).or_default();
+        accepted.insert(timestamp);
+        let oldest = accepted.last().map_or(0, |newest| newest.saturating_sub(self.window));
+        *accepted = accepted.split_off(&oldest);
+        true
+    }
+}
+
+struct SigningState {
+    // Last timestamp signed or accepted, outgoing timestamps keep increasing past it
+    timestamp: u64,
+    // Streams keyed by link ID, system ID and component ID
+    replay_protection: ReplayProtection<(u8, u8, u8)>,
+}
+
+/// MAVLink v2 message signing with a secret key shared by the nodes of a link.
+///
+/// Outgoing V2 frames get the link ID, a timestamp in 10 microsecond units since 2015 and the first 6
+/// bytes of the SHA-256 over the key, the frame and both. A received frame is accepted when its
+/// signature matches and [`ReplayProtection`] accepts its timestamp. The timestamps this node signs
+/// advance past every accepted one, so the clocks of the nodes only need to be roughly in sync.
+pub struct MavlinkSigning {
+    config: SigningConfig,
+    state: Mutex<SigningState>,
+}
+
+impl std::fmt::Debug for MavlinkSigning {
+    // Leaves out the secret key
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MavlinkSigning")
+            .field("link_id", &self.config.link_id)
+            .field("require_signatures", &self.config.require_signatures)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MavlinkSigning {
+    pub fn new(config: Option<SigningOptionalConfig>) -> Self {
+        let config = config.unwrap_or_default().build();
+        Self {
+            state: Mutex::new(SigningState {
+                timestamp: 0,
+                replay_protection: ReplayProtection::new(config.replay_window_ms),
+            }),
+            config,
+        }
+    }
+
+    /// Signature hash over the key and the signed frame up to its timestamp
+    fn signature(&self, signed: &[u8]) -> [u8; MAVLINK_SIGNATURE_HASH_LENGTH] {
+        let digest = Sha256::new()
+            .chain_update(self.config.secret_key)
+            .chain_update(signed)
+            .finalize();
+        let mut signature = [0; MAVLINK_SIGNATURE_HASH_LENGTH];
+        signature.copy_from_slice(&digest[..MAVLINK_SIGNATURE_HASH_LENGTH]);
+        signature
+    }
+
+    /// Length of a serialized frame once signed
+    pub fn signed_length(&self, frame: &[u8]) -> usize {
+        if frame.len() < MAVLINK_V2_FRAME_OVERHEAD || frame[0] != MAV_STX_V2 || frame[2] & MAVLINK_IFLAG_SIGNED != 0 {
+            return frame.len();
+        }
+        frame.len() + MAVLINK_SIGNATURE_LENGTH
+    }
+
+    /// Signs a serialized frame, V1 frames cannot carry a signature and are returned unchanged
+    pub fn sign(&self, mut frame: Vec<u8>) -> Vec<u8> {
+        if frame.len() < MAVLINK_V2_FRAME_OVERHEAD || frame[0] != MAV_STX_V2 || frame[2] & MAVLINK_IFLAG_SIGNED != 0 {
+            return frame;
+        }
+        // The flag is covered by the checksum
+        frame[2] |= MAVLINK_IFLAG_SIGNED;
+        let Some((checksum_position, checksum)) = frame_checksum(&frame) else {
+            return frame;
+        };
+        frame.truncate(checksum_position);
+        frame.extend_from_slice(&checksum.to_le_bytes());
+
+        let timestamp = {
+            let mut state = self.state.lock().unwrap();
+            state.timestamp = signing_timestamp_now().max(state.timestamp + 1);
+            state.timestamp
+        };
+        frame.push(self.config.link_id);
+        frame.extend_from_slice(&timestamp.to_le_bytes()[..6]);
+        let signature = self.signature(&frame);
+        frame.extend_from_slice(&signature);
+        frame
+    }
+
+    /// Whether a received frame may be handed out, records its timestamp when it may. A frame failing its
+    /// checksum is left for the deserialization to reject.
+    pub fn verify(&self, frame: &[u8]) -> bool {
+        if !frame_checksum_valid(frame) {
+            return true;
+        }
+        let signed = frame[0] == MAV_STX_V2 && frame[2] & MAVLINK_IFLAG_SIGNED != 0;
+        if !signed {
+            return !self.config.require_signatures || self.reject(frame, "unsigned");
+        }
+        if frame.len() < MAVLINK_V2_FRAME_OVERHEAD + MAVLINK_SIGNATURE_LENGTH {
+            return self.reject(frame, "invalid_signature");
+        }
+        let (signed_part, signature) = frame.split_at(frame.len() - MAVLINK_SIGNATURE_HASH_LENGTH);
+        if self.signature(signed_part) != signature {
+            return self.reject(frame, "invalid_signature");
+        }
+
+        let signature_start = frame.len() - MAVLINK_SIGNATURE_LENGTH;
+        let link_id = frame[signature_start];
+        let mut timestamp_bytes = [0; 8];
+        timestamp_bytes[..6].copy_from_slice(&frame[signature_start + 1..signature_start + 7]);
+        let timestamp = u64::from_le_bytes(timestamp_bytes);
+
+        let mut state = self.state.lock().unwrap();
+        if !state.replay_protection.record((link_id, frame[5], frame[6]), timestamp) {
+            return self.reject(frame, "replayed");
+        }
+        state.timestamp = state.timestamp.max(timestamp);
+        true
+    }
+
+    /// Counts a rejected frame, always false
+    fn reject(&self, frame: &[u8], reason: &str) -> bool {
+        let (system_id, component_id) = match frame[0] {
+            MAV_STX_V2 => (frame[5], frame[6]),
+            _ => (frame[3], frame[4]),
+        };
+        metrics().increment_counter(SIGNATURE_REJECTED_METRIC, &[("reason", reason)]);
+        log_signature_rejected(system_id, component_id, reason);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat_frame(version: MavlinkVersion, sequence: u8) -> Vec<u8> {
+        let header = MavHeader {
+            system_id: 201,
+            component_id: 1,
+            sequence,
+        };
+        let mut frame = Vec::new();
+        mavlink::write_versioned_msg(&mut frame, version, header, &heartbeat_message()).unwrap();
+        frame
+    }
+
+    fn signing(secret_key: [u8; MAVLINK_SIGNING_KEY_LENGTH]) -> MavlinkSigning {
+        MavlinkSigning::new(Some(SigningOptionalConfig {
+            secret_key: Some(secret_key),
+            ..Default::default()
+        }))
+    }
+
+    fn next_sequence(parser: &mut MavlinkStreamParser) -> Option<u8> {
+        parser.next_frame().map(|packet| packet.header.sequence)
+    }
+
+    #[test]
+    fn reassembles_frame_split_across_chunks() {
+        let frame = heartbeat_frame(MavlinkVersion::V2, 1);
+        let mut parser = MavlinkStreamParser::new();
+
+        parser.push(&frame[..4]);
+        assert_eq!(next_sequence(&mut parser), None);
+        parser.push(&frame[4..]);
+        assert_eq!(next_sequence(&mut parser), Some(1));
+        assert_eq!((parser.crc_errors(), parser.framing_errors()), (0, 0));
+    }
+
+    #[test]
+    fn resynchronizes_after_garbage() {
+        let mut parser = MavlinkStreamParser::new();
+        parser.push(&[0x00, 0x42, 0x13]);
+        parser.push(&heartbeat_frame(MavlinkVersion::V2, 1));
+
+        assert_eq!(next_sequence(&mut parser), Some(1));
+        assert_eq!((parser.crc_errors(), parser.framing_errors()), (0, 1));
+        assert_eq!(next_sequence(&mut parser), None);
+    }
+
+    #[test]
+    fn skips_frame_failing_its_checksum() {
+        let mut corrupted = heartbeat_frame(MavlinkVersion::V2, 1);
+        corrupted[MAVLINK_V2_HEADER_LENGTH] ^= 0xFF;
+        let mut parser = MavlinkStreamParser::new();
+        parser.push(&corrupted);
+        parser.push(&heartbeat_frame(MavlinkVersion::V2, 2));
+
+        assert_eq!(next_sequence(&mut parser), Some(2));
+        assert_eq!(parser.crc_errors(), 1);
+    }
+
+    #[test]
+    fn labels_protocol_version() {
+        let mut parser = MavlinkStreamParser::new();
+        parser.push(&heartbeat_frame(MavlinkVersion::V1, 1));
+        parser.push(&heartbeat_frame(MavlinkVersion::V2, 2));
+
+        let versions: Vec<MavlinkVersion> = std::iter::from_fn(|| parser.next_frame())
+            .map(|packet| packet.protocol_version)
+            .collect();
+        assert_eq!(versions, [MavlinkVersion::V1, MavlinkVersion::V2]);
+    }
+
+    #[test]
+    fn verifies_frame_signed_with_same_key() {
+        let frame = heartbeat_frame(MavlinkVersion::V2, 1);
+        let signed = signing([1; MAVLINK_SIGNING_KEY_LENGTH]).sign(frame.clone());
+        let mut parser = MavlinkStreamParser::with_signing(Arc::new(signing([1; MAVLINK_SIGNING_KEY_LENGTH])));
+        parser.push(&signed);
+
+        assert_eq!(signed.len(), frame.len() + MAVLINK_SIGNATURE_LENGTH);
+        assert_eq!(next_sequence(&mut parser), Some(1));
+    }
+
+    #[test]
+    fn rejects_frame_signed_with_other_key() {
+        let signed = signing([1; MAVLINK_SIGNING_KEY_LENGTH]).sign(heartbeat_frame(MavlinkVersion::V2, 1));
+
+        assert!(!signing([2; MAVLINK_SIGNING_KEY_LENGTH]).verify(&signed));
+    }
+
+    #[test]
+    fn rejects_unsigned_frame_when_signatures_are_required() {
+        assert!(!signing([1; MAVLINK_SIGNING_KEY_LENGTH]).verify(&heartbeat_frame(MavlinkVersion::V2, 1)));
+    }
+
+    #[test]
+    fn rejects_replayed_frame() {
+        let signed = signing([1; MAVLINK_SIGNING_KEY_LENGTH]).sign(heartbeat_frame(MavlinkVersion::V2, 1));
+        let receiver = signing([1; MAVLINK_SIGNING_KEY_LENGTH]);
+
+        assert!(receiver.verify(&signed));
+        assert!(!receiver.verify(&signed));
+    }
+
+    #[test]
+    fn replay_protection_keeps_streams_apart() {
+        let now = signing_timestamp_now();
+        let mut replay_protection = ReplayProtection::new(1000);
+
+        assert!(replay_protection.record((0, 201, 1), now));
+        assert!(!replay_protection.record((0, 201, 1), now));
+        // Same timestamp on another link, system or component
+        assert!(replay_protection.record((1, 201, 1), now));
+        assert!(replay_protection.record((0, 202, 1), now));
+        assert!(replay_protection.record((0, 201, 2), now));
+    }
+
+    #[test]
+    fn replay_protection_rejects_old_timestamps() {
+        let now = signing_timestamp_now();
+        let mut replay_protection = ReplayProtection::new(1000);
+
+        assert!(replay_protection.record((0, 201, 1), now));
+        // Within the window behind the newest timestamp, then beyond it
+        assert!(replay_protection.record((0, 201, 1), now - SIGNING_TIMESTAMP_UNITS_PER_S / 2));
+        assert!(!replay_protection.record((0, 201, 1), now - 2 * SIGNING_TIMESTAMP_UNITS_PER_S));
+        // A new stream lagging more than a minute behind the local clock
+        let lag = (SIGNING_NEW_STREAM_MAX_LAG_S + 1) * SIGNING_TIMESTAMP_UNITS_PER_S;
+        assert!(!replay_protection.record((0, 202, 1), now - lag));
+    }
+}
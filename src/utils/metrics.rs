@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use super::config::MetricsSection;
+use super::logging_utils::{log_debug_metrics_request_failed, log_metrics_server_running};
+use crate::define_struct_with_defaults;
+
+pub const PACKETS_SENT_METRIC: &str = "packets_sent_total";
+pub const PACKETS_RECEIVED_METRIC: &str = "packets_received_total";
+pub const RECEIVE_RSSI_METRIC: &str = "receive_rssi_dbm";
+pub const RECEIVE_SNR_METRIC: &str = "receive_snr_db";
+
+/// Upper bounds of the RSSI histogram buckets, from below the SX127x sensitivity to a nearby transmitter
+pub const RSSI_BUCKETS_DBM: [f64; 10] = [-130.0, -120.0, -110.0, -100.0, -90.0, -80.0, -70.0, -60.0, -50.0, -40.0];
+/// Upper bounds of the SNR histogram buckets, LoRa demodulates down to -20 dB at SF12
+pub const SNR_BUCKETS_DB: [f64; 9] = [-20.0, -15.0, -10.0, -5.0, 0.0, 5.0, 10.0, 15.0, 20.0];
+
+const MAX_REQUEST_LENGTH: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Labels = Vec<(String, String)>;
+
+/// Observations counted into cumulative buckets, as Prometheus histograms expose them
+struct Histogram {
+    bounds: Vec<f64>,
+    bucket_counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Process wide registry of counters, gauges and histograms, keyed by metric name and label set
+#[derive(Default)]
+pub struct MetricsRegistry {
+    counters: Mutex<BTreeMap<String, BTreeMap<Labels, u64>>>,
+    gauges: Mutex<BTreeMap<String, BTreeMap<Labels, f64>>>,
+    histograms: Mutex<BTreeMap<String, BTreeMap<Labels, Histogram>>>,
+}
+
+fn to_labels(labels: &[(&str, &str)]) -> Labels {
+    let mut labels: Labels = labels
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    labels.sort();
+    labels
+}
+
+impl MetricsRegistry {
+    pub fn increment_counter(&self, name: &str, labels: &[(&str, &str)]) {
+        self.add_counter(name, labels, 1);
+    }
+
+    pub fn add_counter(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters
+            .entry(name.to_string())
+            .or_default()
+            .entry(to_labels(labels))
+            .or_default() += value;
+    }
+
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        let counters = self.counters.lock().unwrap();
+        counters
+            .get(name)
+            .and_then(|series| series.get(&to_labels(labels)))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let mut gauges = self.gauges.lock().unwrap();
+        gauges
+            .entry(name.to_string())
+            .or_default()
+            .insert(to_labels(labels), value);
+    }
+
+    pub fn gauge(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        let gauges = self.gauges.lock().unwrap();
+        gauges
+            .get(name)
+            .and_then(|series| series.get(&to_labels(labels)))
+            .copied()
+    }
+
+    /// Counts a value into the histogram of a series, `bounds` are the bucket upper bounds the
+    /// series is created with
+    pub fn observe_histogram(&self, name: &str, labels: &[(&str, &str)], bounds: &[f64], value: f64) {
+        let mut histograms = self.histograms.lock().unwrap();
+        let histogram = histograms
+            .entry(name.to_string())
+            .or_default()
+            .entry(to_labels(labels))
+            .or_insert_with(|| Histogram {
+                bounds: bounds.to_vec(),
+                bucket_counts: vec![0; bounds.len()],
+                sum: 0.0,
+                count: 0,
+            });
+        for (bound, bucket_count) in histogram.bounds.iter().zip(histogram.bucket_counts.iter_mut()) {
+            if value <= *bound {
+                *bucket_count += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    /// Number of observations of a histogram series
+    pub fn histogram_count(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        let histograms = self.histograms.lock().unwrap();
+        histograms
+            .get(name)
+            .and_then(|series| series.get(&to_labels(labels)))
+            .map_or(0, |histogram| histogram.count)
+    }
+
+    /// All series in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        for (name, series) in self.counters.lock().unwrap().iter() {
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            for (labels, value) in series {
+                let _ = writeln!(output, "{}{} {}", name, format_labels(labels, None), value);
+            }
+        }
+        for (name, series) in self.gauges.lock().unwrap().iter() {
+            let _ = writeln!(output, "# TYPE {} gauge", name);
+            for (labels, value) in series {
+                let _ = writeln!(
+                    output,
+                    "{}{} {}",
+                    name,
+                    format_labels(labels, None),
+                    format_value(*value)
+                );
+            }
+        }
+        for (name, series) in self.histograms.lock().unwrap().iter() {
+            let _ = writeln!(output, "# TYPE {} histogram", name);
+            for (labels, histogram) in series {
+                for (bound, bucket_count) in histogram.bounds.iter().zip(&histogram.bucket_counts) {
+                    let bound = format_value(*bound);
+                    let _ = writeln!(
+                        output,
+                        "{}_bucket{} {}",
+                        name,
+                        format_labels(labels, Some(&bound)),
+                        bucket_count
+                    );
+                }
+                let _ = writeln!(
+                    output,
+                    "{}_bucket{} {}",
+                    name,
+                    format_labels(labels, Some("+Inf")),
+                    histogram.count
+                );
+                let _ = writeln!(
+                    output,
+                    "{}_sum{} {}",
+                    name,
+                    format_labels(labels, None),
+                    format_value(histogram.sum)
+                );
+                let _ = writeln!(
+                    output,
+                    "{}_count{} {}",
+                    name,
+                    format_labels(labels, None),
+                    histogram.count
+                );
+            }
+        }
+        output
+    }
+}
+
+/// Label set of a sample, with the `le` label of a histogram bucket when given
+fn format_labels(labels: &Labels, bucket_bound: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect();
+    if let Some(bound) = bucket_bound {
+        pairs.push(format!("le=\"{}\"", bound));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Global metrics registry shared by drivers and network interfaces
+pub fn metrics() -> &'static MetricsRegistry {
+    static METRICS: OnceLock<MetricsRegistry> = OnceLock::new();
+    METRICS.get_or_init(MetricsRegistry::default)
+}
+
+/// Counts a packet a driver sent
+pub fn record_packet_sent(driver: &str) {
+    metrics().increment_counter(PACKETS_SENT_METRIC, &[("driver", driver)]);
+}
+
+/// Counts a packet a driver received, with the signal quality the radio reported for it
+pub fn record_packet_received(driver: &str, rssi: Option<i16>, snr: Option<i16>) {
+    let metrics = metrics();
+    metrics.increment_counter(PACKETS_RECEIVED_METRIC, &[("driver", driver)]);
+    if let Some(rssi) = rssi {
+        metrics.observe_histogram(
+            RECEIVE_RSSI_METRIC,
+            &[("driver", driver)],
+            &RSSI_BUCKETS_DBM,
+            rssi as f64,
+        );
+    }
+    if let Some(snr) = snr {
+        metrics.observe_histogram(RECEIVE_SNR_METRIC, &[("driver", driver)], &SNR_BUCKETS_DB, snr as f64);
+    }
+}
+
+define_struct_with_defaults! {
+    MetricsServerOptionalConfig, MetricsServerConfig {
+        addr: String = "0.0.0.0:9464".to_string(),
+    }
+}
+
+impl From<&MetricsSection> for MetricsServerOptionalConfig {
+    fn from(section: &MetricsSection) -> Self {
+        Self {
+            addr: section.addr.clone(),
+        }
+    }
+}
+
+/// HTTP server exposing the global registry on `/metrics` for Prometheus to scrape
+pub struct MetricsServer {
+    listener: TcpListener,
+}
+
+impl MetricsServer {
+    pub async fn new(config: Option<MetricsServerOptionalConfig>) -> Self {
+        let config = config.unwrap_or_default().build();
+        let listener = TcpListener::bind(&config.addr)
+            .await
+            .expect("Failed to bind metrics listener");
+        Self { listener }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener
+            .local_addr()
+            .expect("Metrics listener without local address")
+    }
+
+    /// Serves scrapes until the task is aborted, each connection on a task of its own
+    pub async fn run(self) -> JoinHandle<()> {
+        log_metrics_server_running(&self.local_addr().to_string());
+        tokio::spawn(async move {
+            loop {
+                match self.listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(async move {
+                            if let Err(err) = serve_request(stream).await {
+                                log_debug_metrics_request_failed(&err.to_string());
+                            }
+                        });
+                    }
+                    Err(err) => log_debug_metrics_request_failed(&err.to_string()),
+                }
+            }
+        })
+    }
+}
+
+/// Answers one HTTP request, the connection is closed afterwards
+async fn serve_request(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_LENGTH {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "request too long"));
+        }
+        let read = timeout(REQUEST_TIMEOUT, stream.read(&mut buffer))
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "request timed out"))??;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request_line = String::from_utf8_lossy(&request);
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) if path == "/metrics" || path.starts_with("/metrics?") => {
+            ("200 OK", metrics().render())
+        }
+        (Some("GET"), Some(_)) => (
+            "404 Not Found",
+            "Not found, metrics are served on /metrics\n".to_string(),
+        ),
+        _ => ("405 Method Not Allowed", "Only GET is supported\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
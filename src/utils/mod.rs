@@ -11,9 +11,24 @@ pub mod lora_types;
 #[cfg(feature = "embedded")]
 pub mod lora_utils;
 
+pub mod adr;
+pub mod arq;
+pub mod channel_access;
+pub mod config;
 pub mod discover;
+pub mod duty_cycle;
+pub mod encryption;
+pub mod filter;
+pub mod fragmentation;
+pub mod link_layer;
 pub mod logging_utils;
+pub mod lora_airtime;
 pub mod macros;
 pub mod mavlink_utils;
+pub mod metrics;
+pub mod radio_status;
+pub mod simulated_air;
+pub mod transmit_guard;
 pub mod types;
+pub mod watchdog;
 pub mod websocket_layer;
@@ -0,0 +1,260 @@
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mavlink::ardupilotmega::{MavMessage, RADIO_STATUS_DATA};
+use mavlink::{MavHeader, MavlinkVersion};
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Instant};
+
+use super::config::RadioStatusSection;
+use super::logging_utils::{log_debug_peer_radio_status, log_debug_send_to_network};
+use super::types::MavFramePacket;
+use crate::define_struct_with_defaults;
+use crate::driver::{Driver, DriverError, DriverWrapper, SignalQuality};
+use crate::network::QueueLength;
+
+/// System and component ID of SiK telemetry radios, the ones flight stacks and ground stations
+/// take RADIO_STATUS from
+pub const RADIO_STATUS_SYSTEM_ID: u8 = 51;
+pub const RADIO_STATUS_COMPONENT_ID: u8 = 68;
+/// Value of the RADIO_STATUS fields without a measurement
+const UNKNOWN: u8 = u8::MAX;
+
+define_struct_with_defaults! {
+    RadioStatusOptionalConfig, RadioStatusConfig {
+        // Interval of the reports to the autopilot and ground station links
+        interval_ms: u64 = 1000,
+        // Interval of the reports to the node on the other end of the LoRa link, which fill its
+        // remote fields, 0 to not send any
+        peer_interval_ms: u64 = 5000,
+        // Age after which a measurement is reported as unknown, as when the link is lost
+        stale_timeout_ms: u64 = 15_000,
+    }
+}
+
+impl From<&RadioStatusSection> for RadioStatusOptionalConfig {
+    fn from(section: &RadioStatusSection) -> Self {
+        Self {
+            interval_ms: section.interval_ms,
+            peer_interval_ms: section.peer_interval_ms,
+            stale_timeout_ms: section.stale_timeout_ms,
+        }
+    }
+}
+
+/// RSSI in the units of SiK radios, which ground stations convert back with `rssi / 1.9 - 127`
+fn sik_rssi(dbm: i16) -> u8 {
+    ((dbm as f64 + 127.0) * 1.9).round().clamp(0.0, 254.0) as u8
+}
+
+#[derive(Default)]
+struct RadioStatisticsState {
+    // Local signal and noise in SiK units, with when they were measured
+    local: Option<(u8, u8, Instant)>,
+    // Signal and noise the peer reported for the packets it received from this node
+    remote: Option<(u8, u8, Instant)>,
+    receive_errors: u16,
+}
+
+/// Measurements of a LoRa link gathered by [`RadioStatusDriver`] for the RADIO_STATUS reports
+#[derive(Default)]
+pub struct RadioStatistics {
+    state: Mutex<RadioStatisticsState>,
+}
+
+impl RadioStatistics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Noise is estimated as the RSSI less the SNR of the packet
+    pub fn record_signal(&self, signal: SignalQuality) {
+        self.state.lock().unwrap().local = Some((
+            sik_rssi(signal.rssi_dbm),
+            sik_rssi(signal.rssi_dbm.saturating_sub(signal.snr_db)),
+            Instant::now(),
+        ));
+    }
+
+    pub fn record_receive_error(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.receive_errors = state.receive_errors.wrapping_add(1);
+    }
+
+    pub fn record_peer_status(&self, status: &RADIO_STATUS_DATA) {
+        self.state.lock().unwrap().remote = Some((status.rssi, status.noise, Instant::now()));
+    }
+
+    /// Report of the current measurements, `txbuf` is the free share of the transmit queue in percent
+    pub fn radio_status(&self, txbuf: u8, stale_timeout: Duration) -> RADIO_STATUS_DATA {
+        let state = self.state.lock().unwrap();
+        let fresh = |measurement: Option<(u8, u8, Instant)>| {
+            measurement
+                .filter(|(_, _, measured_at)| measured_at.elapsed() < stale_timeout)
+                .map_or((UNKNOWN, UNKNOWN), |(rssi, noise, _)| (rssi, noise))
+        };
+        let (rssi, noise) = fresh(state.local);
+        let (remrssi, remnoise) = fresh(state.remote);
+        RADIO_STATUS_DATA {
+            rxerrors: state.receive_errors,
+            fixed: 0,
+            rssi,
+            remrssi,
+            txbuf,
+            noise,
+            remnoise,
+        }
+    }
+}
+
+/// Whether a packet is a RADIO_STATUS report of a node, rather than of a radio of the flight stack
+fn peer_radio_status(packet: &MavFramePacket) -> Option<&RADIO_STATUS_DATA> {
+    match &packet.msg {
+        MavMessage::RADIO_STATUS(status)
+            if packet.header.system_id == RADIO_STATUS_SYSTEM_ID
+                && packet.header.component_id == RADIO_STATUS_COMPONENT_ID =>
+        {
+            Some(status)
+        }
+        _ => None,
+    }
+}
+
+/// Driver wrapper gathering the measurements of a LoRa link into [`RadioStatistics`].
+///
+/// Records the signal quality of each packet received and the received data holding no valid frame.
+/// The RADIO_STATUS reports of the peer are consumed here, their signal and noise become the remote
+/// fields of the own reports.
+pub struct RadioStatusDriver {
+    driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+    statistics: Arc<RadioStatistics>,
+}
+
+impl RadioStatusDriver {
+    pub fn new(driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>, statistics: Arc<RadioStatistics>) -> Self {
+        Self { driver, statistics }
+    }
+}
+
+impl Display for RadioStatusDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.driver)
+    }
+}
+
+#[async_trait::async_trait]
+impl DriverWrapper<MavFramePacket> for RadioStatusDriver {
+    fn inner(&self) -> &Arc<dyn Driver<MavFramePacket> + Send + Sync> {
+        &self.driver
+    }
+
+    async fn receive(&self) -> Result<Option<MavFramePacket>, DriverError> {
+        let packet = match self.driver.receive().await {
+            Ok(packet) => packet,
+            Err(err) => {
+                if matches!(err, DriverError::Decode(_)) {
+                    self.statistics.record_receive_error();
+                }
+                return Err(err);
+            }
+        };
+        let Some(packet) = packet else {
+            return Ok(None);
+        };
+        if let Some(signal) = self.driver.signal_quality() {
+            self.statistics.record_signal(signal);
+        }
+        if let Some(status) = peer_radio_status(&packet) {
+            self.statistics.record_peer_status(status);
+            log_debug_peer_radio_status(&self.driver.to_string(), status.rssi, status.noise);
+            return Ok(None);
+        }
+        Ok(Some(packet))
+    }
+}
+
+/// Periodically sends RADIO_STATUS to the autopilot and ground station links, so flight stacks
+/// throttle their streams to what the LoRa link carries, and to the peer for its remote fields.
+///
+/// `txbuf` is the free share of the LoRa transmit queue, the packets waiting in the channel to the
+/// link and in the network interface, of a queue as long as that channel. ArduPilot slows its streams down
+/// below 50% and speeds them up again above 90%.
+pub struct RadioStatusReporter {
+    statistics: Arc<RadioStatistics>,
+    link_name: String,
+    link: Sender<MavFramePacket>,
+    queue_length: QueueLength,
+    outputs: Vec<(String, Sender<MavFramePacket>)>,
+    interval: Duration,
+    peer_interval: Option<Duration>,
+    stale_timeout: Duration,
+}
+
+impl RadioStatusReporter {
+    /// `link` is the sender returned by `NetworkInterface::new` for the LoRa link named `link_name`, and
+    /// `queue_length` the one of the interface
+    pub fn new(
+        statistics: Arc<RadioStatistics>,
+        link_name: &str,
+        link: Sender<MavFramePacket>,
+        queue_length: QueueLength,
+        config: Option<RadioStatusOptionalConfig>,
+    ) -> Self {
+        let config = config.unwrap_or_default().build();
+        Self {
+            statistics,
+            link_name: link_name.to_string(),
+            link,
+            queue_length,
+            outputs: Vec::new(),
+            interval: Duration::from_millis(config.interval_ms),
+            peer_interval: (config.peer_interval_ms > 0).then(|| Duration::from_millis(config.peer_interval_ms)),
+            stale_timeout: Duration::from_millis(config.stale_timeout_ms),
+        }
+    }
+
+    /// Adds a link the reports are sent to, such as the autopilot or a ground station
+    pub fn add_output(&mut self, name: &str, output: Sender<MavFramePacket>) {
+        self.outputs.push((name.to_string(), output));
+    }
+
+    fn txbuf(&self) -> u8 {
+        let capacity = self.link.max_capacity();
+        let queued = capacity - self.link.capacity() + self.queue_length.get();
+        (100 - (100 * queued / capacity).min(100)) as u8
+    }
+
+    /// Sends the reports until the task is aborted
+    pub async fn run(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = interval(self.interval);
+            let mut next_peer_report = Instant::now();
+            for sequence in (0..=u8::MAX).cycle() {
+                interval.tick().await;
+                let packet = MavFramePacket {
+                    header: MavHeader {
+                        system_id: RADIO_STATUS_SYSTEM_ID,
+                        component_id: RADIO_STATUS_COMPONENT_ID,
+                        sequence,
+                    },
+                    msg: MavMessage::RADIO_STATUS(self.statistics.radio_status(self.txbuf(), self.stale_timeout)),
+                    protocol_version: MavlinkVersion::V2,
+                };
+                // A report that does not fit a full channel is left out, the next one replaces it
+                for (name, output) in &self.outputs {
+                    if output.try_send(packet.clone()).is_ok() {
+                        log_debug_send_to_network(name);
+                    }
+                }
+                if let Some(peer_interval) = self.peer_interval {
+                    if Instant::now() >= next_peer_report && self.link.try_send(packet).is_ok() {
+                        next_peer_report = Instant::now() + peer_interval;
+                        log_debug_send_to_network(&self.link_name);
+                    }
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use super::lora_airtime::LoRaModulation;
+
+/// How long finished transmissions are remembered for collision checks
+const TRANSMISSION_HISTORY: Duration = Duration::from_secs(10);
+
+struct Transmission {
+    node_id: usize,
+    modulation: LoRaModulation,
+    start: Instant,
+    end: Instant,
+}
+
+struct SimulatedNode {
+    listening_since: Option<Instant>,
+    modulation: LoRaModulation,
+    packet_loss: f64,
+    inbox: VecDeque<Vec<u8>>,
+    notify: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct AirState {
+    next_node_id: usize,
+    nodes: HashMap<usize, SimulatedNode>,
+    transmissions: Vec<Transmission>,
+}
+
+/// Shared in-process radio medium connecting simulated LoRa nodes.
+///
+/// A packet reaches a node only if the node was listening on the same channel for the whole time on air,
+/// no other node transmitted on that channel at the same time and it survived the node's packet loss.
+#[derive(Default)]
+pub struct SimulatedAir {
+    state: Mutex<AirState>,
+}
+
+impl SimulatedAir {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Attaches a new node to the medium, returning its id and the notifier signaled on packet arrival
+    pub fn register(&self, modulation: LoRaModulation, packet_loss: f64) -> (usize, Arc<Notify>) {
+        let mut state = self.state.lock().unwrap();
+        let node_id = state.next_node_id;
+        state.next_node_id += 1;
+
+        let notify = Arc::new(Notify::new());
+        state.nodes.insert(
+            node_id,
+            SimulatedNode {
+                listening_since: None,
+                modulation,
+                packet_loss,
+                inbox: VecDeque::new(),
+                notify: notify.clone(),
+            },
+        );
+        (node_id, notify)
+    }
+
+    /// Puts a node in receive mode, keeping an ongoing reception intact if it is already listening
+    pub fn start_listening(&self, node_id: usize) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(node) = state.nodes.get_mut(&node_id) {
+            node.listening_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    pub fn stop_listening(&self, node_id: usize) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(node) = state.nodes.get_mut(&node_id) {
+            node.listening_since = None;
+        }
+    }
+
+    /// Moves a node to another modulation, it only exchanges packets with the nodes on the same channel
+    pub fn set_modulation(&self, node_id: usize, modulation: LoRaModulation) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(node) = state.nodes.get_mut(&node_id) {
+            node.modulation = modulation;
+        }
+    }
+
+    /// Transmits a payload, resolving once it has left the antenna after its time on air
+    pub async fn transmit(&self, node_id: usize, payload: Vec<u8>) {
+        let (start, end) = {
+            let mut state = self.state.lock().unwrap();
+            let Some(node) = state.nodes.get_mut(&node_id) else {
+                return;
+            };
+            // A half-duplex radio cannot listen while transmitting
+            node.listening_since = None;
+            let modulation = node.modulation;
+
+            let start = Instant::now();
+            let end = start + modulation.time_on_air(payload.len());
+            state
+                .transmissions
+                .retain(|transmission| transmission.end + TRANSMISSION_HISTORY > start);
+            state.transmissions.push(Transmission {
+                node_id,
+                modulation,
+                start,
+                end,
+            });
+            (start, end)
+        };
+
+        tokio::time::sleep_until(end).await;
+        self.deliver(node_id, start, end, payload);
+    }
+
+    /// Whether another node is transmitting on the channel of a node, as channel activity detection senses it
+    pub fn channel_active(&self, node_id: usize) -> bool {
+        let state = self.state.lock().unwrap();
+        let Some(modulation) = state.nodes.get(&node_id).map(|node| node.modulation) else {
+            return false;
+        };
+        let now = Instant::now();
+        state.transmissions.iter().any(|transmission| {
+            transmission.node_id != node_id
+                && same_channel(&transmission.modulation, &modulation)
+                && transmission.start <= now
+                && transmission.end > now
+        })
+    }
+
+    pub fn has_pending(&self, node_id: usize) -> bool {
+        let state = self.state.lock().unwrap();
+        state.nodes.get(&node_id).is_some_and(|node| !node.inbox.is_empty())
+    }
+
+    pub fn take_pending(&self, node_id: usize) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        state.nodes.get_mut(&node_id).and_then(|node| node.inbox.pop_front())
+    }
+
+    fn deliver(&self, sender_id: usize, start: Instant, end: Instant, payload: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        let Some(modulation) = state.nodes.get(&sender_id).map(|node| node.modulation) else {
+            return;
+        };
+
+        let collided = state.transmissions.iter().any(|transmission| {
+            transmission.node_id != sender_id
+                && same_channel(&transmission.modulation, &modulation)
+                && transmission.start < end
+                && transmission.end > start
+        });
+        if collided {
+            return;
+        }
+
+        for (node_id, node) in state.nodes.iter_mut() {
+            if *node_id == sender_id || !same_channel(&node.modulation, &modulation) {
+                continue;
+            }
+            let listened_throughout = node.listening_since.is_some_and(|since| since <= start);
+            if !listened_throughout || rand::random::<f64>() < node.packet_loss {
+                continue;
+            }
+            node.inbox.push_back(payload.clone());
+            node.notify.notify_one();
+        }
+    }
+}
+
+fn same_channel(a: &LoRaModulation, b: &LoRaModulation) -> bool {
+    a.frequency_hz == b.frequency_hz && a.spreading_factor == b.spreading_factor && a.bandwidth_hz == b.bandwidth_hz
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::link_layer::LinkLayerStack;
+use super::lora_airtime::LoRaModulation;
+use super::mavlink_utils::serialize_frame;
+use super::types::MavFramePacket;
+
+/// Rule a LoRa radio driver applies to what it transmits, once the link layers encoded the payloads
+/// and their time on air is known, such as a duty cycle or a transmit slot
+pub trait TransmitGuard: Send + Sync {
+    /// Rewrites a frame whose content depends on when it goes on air, right before it is encoded
+    fn stamp(&self, _packet: &mut MavFramePacket) {}
+
+    /// Whether payloads of `airtime` in total may go on air back to back from `at` on, they are dropped otherwise
+    fn allows(&self, driver: &str, modulation: &LoRaModulation, at: Instant, airtime: Duration) -> bool;
+
+    /// Books a payload of `airtime` that went on air at `at`
+    fn book(&self, _driver: &str, _modulation: &LoRaModulation, _at: Instant, _airtime: Duration) {}
+}
+
+/// Payloads a batch goes on air with once the guards decided on them
+#[derive(Default)]
+pub struct TransmitPlan {
+    pub payloads: Vec<Vec<u8>>,
+    /// Whether a guard refused payloads, the ones of a frame are dropped while the ones the link layers
+    /// transmit on their own stay due
+    pub dropped: bool,
+}
+
+/// Guards of a radio driver, a payload goes on air when all of them allow it
+#[derive(Default)]
+pub struct TransmitGuards {
+    guards: Vec<Box<dyn TransmitGuard>>,
+}
+
+impl TransmitGuards {
+    pub fn push(&mut self, guard: impl TransmitGuard + 'static) {
+        self.guards.push(Box::new(guard));
+    }
+
+    /// Payloads to transmit back to back for a batch, followed by the ones the link layers transmit on their
+    /// own that are due. The payloads of a frame, such as its fragments, are allowed or dropped together from
+    /// their encoded lengths, nothing is booked until [`TransmitGuards::book`] is called for a payload that
+    /// went on air.
+    pub fn encode_batch(
+        &self,
+        driver: &str,
+        modulation: &LoRaModulation,
+        link_layers: &LinkLayerStack,
+        packets: &[MavFramePacket],
+    ) -> TransmitPlan {
+        let frames = packets
+            .iter()
+            .map(|packet| {
+                let mut packet = packet.clone();
+                for guard in &self.guards {
+                    guard.stamp(&mut packet);
+                }
+                serialize_frame(packet)
+            })
+            .collect();
+        // The guards decide before the link layers encode anything, so a refused group leaves their state as is
+        let at = Instant::now();
+        let mut planned = Duration::ZERO;
+        let mut dropped = false;
+        let mut allows = |airtime: Duration| {
+            let allowed = self
+                .guards
+                .iter()
+                .all(|guard| guard.allows(driver, modulation, at, planned + airtime));
+            if allowed {
+                planned += airtime;
+            } else {
+                dropped = true;
+            }
+            allowed
+        };
+        let mut groups = link_layers.encode_batch(modulation, frames, &mut allows);
+        groups.extend(link_layers.poll_transmit(modulation, &mut allows));
+        TransmitPlan {
+            payloads: groups.into_iter().flatten().collect(),
+            dropped,
+        }
+    }
+
+    /// Books a payload of the plan once it went on air, started at `at`
+    pub fn book(&self, driver: &str, modulation: &LoRaModulation, at: Instant, payload: &[u8]) {
+        let airtime = modulation.time_on_air(payload.len());
+        for guard in &self.guards {
+            guard.book(driver, modulation, at, airtime);
+        }
+    }
+}
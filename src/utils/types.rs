@@ -1,20 +1,92 @@
 use mavlink::ardupilotmega::MavMessage;
 use mavlink::MavFrame;
+use serde::Deserialize;
 
 pub type MavFramePacket = MavFrame<MavMessage>;
 
-#[derive(Debug)]
+/// Direction of a packet relative to the link or driver handling it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+/// Where a UDP driver sends, following the mavlink-router UDP endpoint modes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum UdpMode {
+    /// Sends to `dest_addr` and every address of `unicast_addrs`
+    #[default]
+    Client,
+    /// Sends to every peer heard from on `addr` within the peer timeout, like a GCS port
+    Server,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SerialFlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SerialParity {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum WebSocketRole {
+    /// Connects to `url`, reconnecting with backoff
+    Client,
+    /// Listens on `addr` for any number of peers, such as browser dashboards
+    Server,
+}
+
+/// Format of the frames the driver sends, both are accepted on receive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum WebSocketEncoding {
+    /// Binary messages carrying serialized MAVLink frames
+    Binary,
+    /// Text messages carrying the serde JSON representation of the frames
+    Json,
+}
+
+/// Time base of the TDMA slots, every node of the fleet needs the same one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum TdmaTimeSource {
+    /// System clock of the gateway, which it broadcasts in SYSTEM_TIME beacons
+    #[default]
+    Beacon,
+    /// GPS time in the SYSTEM_TIME of the flight controller of each node, the gateway included
+    Gps,
+}
+
+/// Class of a message type on a scheduled link, higher classes are sent first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
 pub enum NodeType {
     Uav,
     Gateway,
 }
 
 impl NodeType {
+    #[allow(clippy::should_implement_trait, clippy::result_unit_err)]
     pub fn from_str(s: &str) -> Result<NodeType, ()> {
         match s {
             "Uav" => Ok(NodeType::Uav),
             "Gateway" => Ok(NodeType::Gateway),
-            _ => Err(println!("Invalid node type")),
+            _ => {
+                println!("Invalid node type");
+                Err(())
+            }
         }
     }
 }
@@ -0,0 +1,178 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::{timeout, timeout_at, Instant};
+
+use super::config::WatchdogSection;
+use super::logging_utils::{log_radio_recovery, log_radio_recovery_failed};
+use super::lora_airtime::MAX_LORA_PAYLOAD_LENGTH;
+use super::metrics::metrics;
+use super::types::MavFramePacket;
+use crate::define_struct_with_defaults;
+use crate::driver::{Driver, DriverError, DriverWrapper};
+
+pub const RADIO_RECOVERIES_METRIC: &str = "radio_recoveries_total";
+
+const RX_STALL_REASON: &str = "no RX IRQ";
+const TX_STALL_REASON: &str = "no TX done";
+
+define_struct_with_defaults! {
+    WatchdogOptionalConfig, WatchdogConfig {
+        // Time in RX without any IRQ after which the radio is considered deaf, keep it above the
+        // longest quiet period of the link as a quiet channel looks the same
+        rx_stall_timeout_ms: u64 = 30_000,
+        // Time allowed for TX done beyond the time on air of the packets
+        tx_done_margin_ms: u64 = 2000,
+    }
+}
+
+impl From<&WatchdogSection> for WatchdogOptionalConfig {
+    fn from(section: &WatchdogSection) -> Self {
+        Self {
+            rx_stall_timeout_ms: section.rx_stall_timeout_ms,
+            tx_done_margin_ms: section.tx_done_margin_ms,
+        }
+    }
+}
+
+/// Driver wrapper re-initializing a LoRa radio that stopped raising IRQs.
+///
+/// The radio is considered stalled when no IRQ arrives for `rx_stall_timeout_ms` while receiving,
+/// or when TX done does not fire within the time on air of a batch plus `tx_done_margin_ms`. The
+/// stalled operation then fails with `DriverError::Timeout`. A stalled TX is recovered right away,
+/// while a stalled RX is recovered by the next prepare call, as the wait for an IRQ may be cancelled
+/// at any point and the reset must run to completion.
+pub struct WatchdogDriver {
+    driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>,
+    rx_stall_timeout: Duration,
+    tx_done_margin: Duration,
+    // Last RX IRQ, or the last recovery when none came since
+    last_rx_irq: Mutex<Instant>,
+    rx_stalled: AtomicBool,
+    recoveries: AtomicU64,
+}
+
+impl WatchdogDriver {
+    pub fn new(driver: Arc<dyn Driver<MavFramePacket> + Send + Sync>, config: Option<WatchdogOptionalConfig>) -> Self {
+        let config = config.unwrap_or_default().build();
+
+        Self {
+            driver,
+            rx_stall_timeout: Duration::from_millis(config.rx_stall_timeout_ms),
+            tx_done_margin: Duration::from_millis(config.tx_done_margin_ms),
+            last_rx_irq: Mutex::new(Instant::now()),
+            rx_stalled: AtomicBool::new(false),
+            recoveries: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of times the radio was re-initialized
+    pub fn recoveries(&self) -> u64 {
+        self.recoveries.load(Ordering::Relaxed)
+    }
+
+    fn tx_timeout(&self, packets: &[MavFramePacket]) -> Duration {
+        let airtime = match self.driver.modulation() {
+            Some(modulation) => {
+                // Payloads the link layers transmit on their own go out with the batch, one full payload is
+                // allowed for them
+                let link_airtime = match self.driver.next_link_transmit() {
+                    Some(due) if due <= Instant::now() => modulation.time_on_air(MAX_LORA_PAYLOAD_LENGTH),
+                    _ => Duration::ZERO,
+                };
+                packets
+                    .iter()
+                    .filter_map(|packet| self.driver.time_on_air(packet))
+                    .sum::<Duration>()
+                    + link_airtime
+            }
+            None => Duration::ZERO,
+        };
+        airtime + self.tx_done_margin
+    }
+
+    async fn recover_if_rx_stalled(&self) -> Result<(), DriverError> {
+        if self.rx_stalled.swap(false, Ordering::Relaxed) {
+            self.recover(RX_STALL_REASON).await?;
+        }
+        Ok(())
+    }
+
+    async fn recover(&self, reason: &str) -> Result<(), DriverError> {
+        let driver = self.driver.to_string();
+        let count = self.recoveries.fetch_add(1, Ordering::Relaxed) + 1;
+        metrics().increment_counter(RADIO_RECOVERIES_METRIC, &[("driver", &driver), ("reason", reason)]);
+        *self.last_rx_irq.lock().unwrap() = Instant::now();
+        match self.driver.reinitialize().await {
+            Ok(()) => {
+                log_radio_recovery(&driver, reason, count);
+                Ok(())
+            }
+            Err(err) => {
+                log_radio_recovery_failed(&driver, reason, &err.to_string());
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Display for WatchdogDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.driver)
+    }
+}
+
+#[async_trait::async_trait]
+impl DriverWrapper<MavFramePacket> for WatchdogDriver {
+    fn inner(&self) -> &Arc<dyn Driver<MavFramePacket> + Send + Sync> {
+        &self.driver
+    }
+
+    async fn send(&self, packet_to_send: &MavFramePacket) -> Result<(), DriverError> {
+        DriverWrapper::send_batch(self, std::slice::from_ref(packet_to_send)).await
+    }
+
+    async fn send_batch(&self, packets_to_send: &[MavFramePacket]) -> Result<(), DriverError> {
+        match timeout(
+            self.tx_timeout(packets_to_send),
+            self.driver.send_batch(packets_to_send),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(_) => {
+                self.recover(TX_STALL_REASON).await?;
+                Err(DriverError::Timeout)
+            }
+        }
+    }
+
+    async fn prepare_to_receive(&self) -> Result<(), DriverError> {
+        self.recover_if_rx_stalled().await?;
+        self.driver.prepare_to_receive().await
+    }
+
+    async fn prepare_to_send(&self) -> Result<(), DriverError> {
+        self.recover_if_rx_stalled().await?;
+        self.driver.prepare_to_send().await
+    }
+
+    async fn ready_to_receive(&self) -> Result<(), DriverError> {
+        // The deadline is kept across calls, as the wait is cancelled whenever a packet is transmitted
+        let deadline = *self.last_rx_irq.lock().unwrap() + self.rx_stall_timeout;
+        match timeout_at(deadline, self.driver.ready_to_receive()).await {
+            Ok(result) => {
+                *self.last_rx_irq.lock().unwrap() = Instant::now();
+                result
+            }
+            Err(_) => {
+                // Only flagged here, as this wait is cancelled whenever a packet is transmitted
+                self.rx_stalled.store(true, Ordering::Relaxed);
+                *self.last_rx_irq.lock().unwrap() = Instant::now();
+                Err(DriverError::Timeout)
+            }
+        }
+    }
+}
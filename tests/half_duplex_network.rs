@@ -0,0 +1,127 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mavlink_network_node::half_duplex_network::HalfDuplexNetwork;
+use mavlink_network_node::{Driver, DriverError, NetworkInterface};
+use tokio::sync::{mpsc, watch};
+use tokio::time::timeout;
+
+const ROUNDS: u32 = 1000;
+const DELIVERY_TIMEOUT_MS: u64 = 500;
+
+/// Radio holding one received packet until it is read, which switching to TX throws away like the
+/// FIFO of an SX127x or SX126x
+struct MockRadio {
+    fifo: Mutex<Option<u32>>,
+    irq_tx: watch::Sender<u64>,
+    irq_events: tokio::sync::Mutex<watch::Receiver<u64>>,
+    sent: mpsc::UnboundedSender<u32>,
+    dropped_by_tx: AtomicU64,
+}
+
+impl MockRadio {
+    fn new(sent: mpsc::UnboundedSender<u32>) -> Self {
+        let (irq_tx, irq_events) = watch::channel(0);
+        Self {
+            fifo: Mutex::new(None),
+            irq_tx,
+            irq_events: tokio::sync::Mutex::new(irq_events),
+            sent,
+            dropped_by_tx: AtomicU64::new(0),
+        }
+    }
+
+    /// A packet from the air lands in the FIFO and raises RX done
+    fn land(&self, packet: u32) {
+        *self.fifo.lock().unwrap() = Some(packet);
+        self.irq_tx.send_modify(|count| *count += 1);
+    }
+}
+
+impl Display for MockRadio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "mock_radio")
+    }
+}
+
+#[async_trait::async_trait]
+impl Driver<u32> for MockRadio {
+    async fn send(&self, packet: &u32) -> Result<(), DriverError> {
+        // Time on air
+        tokio::time::sleep(Duration::from_micros(200)).await;
+        self.sent.send(*packet).unwrap();
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Option<u32>, DriverError> {
+        Ok(self.fifo.lock().unwrap().take())
+    }
+
+    async fn ready_to_receive(&self) -> Result<(), DriverError> {
+        let mut irq_events = self.irq_events.lock().await;
+        irq_events.changed().await.map_err(DriverError::radio)
+    }
+
+    async fn prepare_to_receive(&self) -> Result<(), DriverError> {
+        self.irq_events.lock().await.borrow_and_update();
+        Ok(())
+    }
+
+    async fn prepare_to_send(&self) -> Result<(), DriverError> {
+        if self.fifo.lock().unwrap().take().is_some() {
+            self.dropped_by_tx.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// Lands a packet in the radio while a packet is queued for transmission, in varying order, and
+/// checks that the half-duplex network hands out every received packet and transmits every queued one
+#[tokio::test]
+async fn tx_does_not_drop_pending_reception() {
+    let (sent_tx, mut sent_rx) = mpsc::unbounded_channel();
+    let radio = Arc::new(MockRadio::new(sent_tx));
+    let (network, to_radio, mut from_radio) = HalfDuplexNetwork::new(radio.clone(), 16);
+    let _run_handle = network.run().await;
+
+    // Lets the network prepare the radio for receiving
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    for round in 0..ROUNDS {
+        let received = round * 2;
+        let transmitted = round * 2 + 1;
+        match round % 3 {
+            0 => {
+                radio.land(received);
+                to_radio.send(transmitted).await.unwrap();
+            }
+            1 => {
+                to_radio.send(transmitted).await.unwrap();
+                radio.land(received);
+            }
+            _ => {
+                to_radio.send(transmitted).await.unwrap();
+                tokio::task::yield_now().await;
+                radio.land(received);
+            }
+        }
+
+        let delivery = Duration::from_millis(DELIVERY_TIMEOUT_MS);
+        assert_eq!(
+            timeout(delivery, from_radio.recv()).await.ok().flatten(),
+            Some(received),
+            "Received packet lost in round {}",
+            round
+        );
+        assert_eq!(
+            timeout(delivery, sent_rx.recv()).await.ok().flatten(),
+            Some(transmitted),
+            "Queued packet not transmitted in round {}",
+            round
+        );
+    }
+
+    assert_eq!(radio.dropped_by_tx.load(Ordering::Relaxed), 0);
+}
@@ -0,0 +1,111 @@
+//! Serial driver against a pseudo-terminal pair, the master end standing in for the flight controller.
+//! Linux only, no hardware needed.
+#![cfg(target_os = "linux")]
+
+use std::path::Path;
+use std::time::Duration;
+
+use mavlink::{MavHeader, MavlinkVersion};
+use mavlink_network_node::mavlink_utils::{heartbeat_message, serialize_frame, MavlinkStreamParser};
+use mavlink_network_node::serial_driver::{SerialDriver, SerialOptionalInitConfig};
+use mavlink_network_node::types::MavFramePacket;
+use mavlink_network_node::Driver;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+use tokio_serial::{SerialPort, SerialStream};
+
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn heartbeat(sequence: u8) -> MavFramePacket {
+    MavFramePacket {
+        header: MavHeader {
+            system_id: 1,
+            component_id: 1,
+            sequence,
+        },
+        msg: heartbeat_message(),
+        protocol_version: MavlinkVersion::V2,
+    }
+}
+
+/// New pseudo-terminal pair whose device is reached through `link`, like a udev symlink to a TELEM port
+fn autopilot(link: &Path) -> SerialStream {
+    let (autopilot, device) = SerialStream::pair().expect("Failed to create pseudo-terminal pair");
+    let path = device.name().expect("Pseudo-terminal without a device path");
+    let _ = std::fs::remove_file(link);
+    std::os::unix::fs::symlink(path, link).unwrap();
+    // The driver opens the device by path, and a pseudo-terminal drops what is written while its slave end
+    // is closed, so the driver has to open it before the autopilot writes
+    autopilot
+}
+
+/// Sends until the driver has the device open, it opens it lazily and retries after a failure
+async fn connect(driver: &SerialDriver, sequence: u8) {
+    timeout(FRAME_TIMEOUT, async {
+        while driver.send(&heartbeat(sequence)).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Driver did not open the device");
+}
+
+async fn receive(driver: &SerialDriver) -> MavFramePacket {
+    timeout(FRAME_TIMEOUT, driver.receive())
+        .await
+        .expect("Frame lost on the serial link")
+        .expect("Serial link failed")
+        .expect("No frame decoded")
+}
+
+#[tokio::test]
+async fn reassembles_frames_and_reconnects() {
+    let link = std::env::temp_dir().join(format!("serial_pty_{}", std::process::id()));
+    let mut autopilot_port = autopilot(&link);
+    let driver = SerialDriver::new(Some(SerialOptionalInitConfig {
+        path: Some(link.to_string_lossy().into_owned()),
+        baud_rate: Some(115_200),
+        reconnect_interval_ms: Some(10),
+        ..Default::default()
+    }));
+    connect(&driver, 100).await;
+
+    // Frames written in chunks that do not align with their boundaries, with noise in between
+    let mut stream = Vec::new();
+    for sequence in 0..3 {
+        stream.extend_from_slice(&[0x00, 0x55]);
+        stream.extend(serialize_frame(heartbeat(sequence)));
+    }
+    for chunk in stream.chunks(7) {
+        autopilot_port.write_all(chunk).await.unwrap();
+    }
+    for sequence in 0..3 {
+        assert_eq!(receive(&driver).await.header.sequence, sequence);
+    }
+
+    // The autopilot got the frame the driver sent
+    let mut parser = MavlinkStreamParser::new();
+    let mut buffer = [0u8; 64];
+    let frame = timeout(FRAME_TIMEOUT, async {
+        loop {
+            let length = autopilot_port.read(&mut buffer).await.unwrap();
+            parser.push(&buffer[..length]);
+            if let Some(frame) = parser.next_frame() {
+                break frame;
+            }
+        }
+    })
+    .await
+    .expect("Frame lost on the way to the autopilot");
+    assert_eq!(frame.header.sequence, 100);
+
+    // The device disappears, as when the autopilot reboots, and comes back under the same path
+    drop(autopilot_port);
+    assert!(timeout(FRAME_TIMEOUT, driver.receive()).await.unwrap().is_err());
+    let mut autopilot_port = autopilot(&link);
+    connect(&driver, 101).await;
+    autopilot_port.write_all(&serialize_frame(heartbeat(3))).await.unwrap();
+    assert_eq!(receive(&driver).await.header.sequence, 3);
+
+    std::fs::remove_file(&link).unwrap();
+}
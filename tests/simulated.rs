@@ -0,0 +1,174 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::FutureExt;
+use mavlink::{MavHeader, MavlinkVersion};
+use mavlink_network_node::filter::FilterChain;
+use mavlink_network_node::full_duplex_network::FullDuplexNetwork;
+use mavlink_network_node::half_duplex_network::HalfDuplexNetwork;
+use mavlink_network_node::mavlink_utils::{deserialize_frame, heartbeat_message, serialize_frame};
+use mavlink_network_node::router::Router;
+use mavlink_network_node::simulated_air::SimulatedAir;
+use mavlink_network_node::simulated_lora_driver::{
+    SimulatedLoRaDriver, SimulatedLoRaOptionalInitConfig, SIMULATED_LORA_DRIVER,
+};
+use mavlink_network_node::types::{MavFramePacket, UdpMode};
+use mavlink_network_node::udp_driver::{
+    default_udp_filter, UDPConfig, UDPDriver, DEFAULT_UDP_PEER_TIMEOUT_MS, UDP_DRIVER,
+};
+use mavlink_network_node::{Driver, NetworkInterface};
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+/// Longer than the time on air of a heartbeat, so a packet still on its way would have arrived
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Longer than a heartbeat takes from the UDP side of one node to the UDP side of the other
+const ROUTE_TIMEOUT: Duration = Duration::from_secs(5);
+const CHANNEL_SIZE: usize = 100;
+
+fn node(air: &Arc<SimulatedAir>, packet_loss: f64) -> SimulatedLoRaDriver {
+    SimulatedLoRaDriver::new(
+        air.clone(),
+        Some(SimulatedLoRaOptionalInitConfig {
+            packet_loss: Some(packet_loss),
+            ..Default::default()
+        }),
+    )
+}
+
+fn heartbeat(sequence: u8) -> MavFramePacket {
+    MavFramePacket {
+        header: MavHeader {
+            system_id: 201,
+            component_id: 1,
+            sequence,
+        },
+        msg: heartbeat_message(),
+        protocol_version: MavlinkVersion::V2,
+    }
+}
+
+/// Starts a node as `full.rs` sets it up, its UDP link to the autopilot at `autopilot` and its LoRa link on
+/// `air` joined by a router. Returns the address its UDP link listens on.
+async fn full_node(air: &Arc<SimulatedAir>, autopilot: SocketAddr) -> SocketAddr {
+    let udp_driver = Arc::new(
+        UDPDriver::new(UDPConfig {
+            addr: "127.0.0.1:0".to_string(),
+            dest_addr: Some(autopilot.to_string()),
+            broadcast: false,
+            mode: UdpMode::Client,
+            unicast_addrs: Vec::new(),
+            peer_timeout_ms: DEFAULT_UDP_PEER_TIMEOUT_MS,
+        })
+        .await,
+    );
+    let udp_addr = udp_driver.device.local_addr().unwrap();
+    let (udp_network, udp_tx, udp_rx) = FullDuplexNetwork::new(udp_driver, CHANNEL_SIZE);
+    udp_network.run().await;
+
+    let (lora_network, lora_tx, lora_rx) = HalfDuplexNetwork::new(Arc::new(node(air, 0.0)), CHANNEL_SIZE);
+    lora_network.run().await;
+
+    let mut router = Router::new();
+    let udp_filter = FilterChain::new(UDP_DRIVER).with_filter(default_udp_filter());
+    router.add_link_with_filter(UDP_DRIVER, udp_tx, udp_rx, udp_filter);
+    router.add_link(SIMULATED_LORA_DRIVER, lora_tx, lora_rx);
+    router.run().await;
+    udp_addr
+}
+
+/// Sequence of the packet a node received, None when nothing reached it
+async fn received(driver: &SimulatedLoRaDriver) -> Option<u8> {
+    timeout(RECEIVE_TIMEOUT, driver.ready_to_receive()).await.ok()?.unwrap();
+    driver.receive().await.unwrap().map(|packet| packet.header.sequence)
+}
+
+#[tokio::test(start_paused = true)]
+async fn delivers_to_listening_node() {
+    let air = SimulatedAir::new();
+    let uav = node(&air, 0.0);
+    let gateway = node(&air, 0.0);
+
+    gateway.prepare_to_receive().await.unwrap();
+    uav.prepare_to_send().await.unwrap();
+    uav.send(&heartbeat(7)).await.unwrap();
+
+    assert_eq!(received(&gateway).await, Some(7));
+}
+
+#[tokio::test(start_paused = true)]
+async fn overlapping_transmissions_collide() {
+    let air = SimulatedAir::new();
+    let uav = node(&air, 0.0);
+    let other_uav = node(&air, 0.0);
+    let gateway = node(&air, 0.0);
+
+    gateway.prepare_to_receive().await.unwrap();
+    let (first, second) = (heartbeat(1), heartbeat(2));
+    let (first, second) = tokio::join!(uav.send(&first), other_uav.send(&second));
+    first.unwrap();
+    second.unwrap();
+
+    assert_eq!(received(&gateway).await, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn deaf_while_transmitting() {
+    let air = SimulatedAir::new();
+    let uav = node(&air, 0.0);
+    let gateway = node(&air, 0.0);
+
+    // Transmitting takes the gateway out of receive mode, it does not hear the UAV right after, although
+    // the two transmissions do not overlap
+    gateway.prepare_to_receive().await.unwrap();
+    gateway.send(&heartbeat(1)).await.unwrap();
+    uav.send(&heartbeat(2)).await.unwrap();
+    assert_eq!(received(&gateway).await, None);
+
+    // Back in receive mode, it hears the UAV again
+    gateway.prepare_to_receive().await.unwrap();
+    uav.send(&heartbeat(3)).await.unwrap();
+    assert_eq!(received(&gateway).await, Some(3));
+}
+
+#[tokio::test(start_paused = true)]
+async fn loses_configured_share_of_packets() {
+    const PACKETS: usize = 2000;
+    const PACKET_LOSS: f64 = 0.3;
+
+    let air = SimulatedAir::new();
+    let uav = node(&air, 0.0);
+    let gateway = node(&air, PACKET_LOSS);
+
+    gateway.prepare_to_receive().await.unwrap();
+    let mut delivered = 0;
+    for sequence in 0..PACKETS {
+        uav.send(&heartbeat(sequence as u8)).await.unwrap();
+        if gateway.ready_to_receive().now_or_never().is_some() && gateway.receive().await.unwrap().is_some() {
+            delivered += 1;
+        }
+    }
+
+    let lost = 1.0 - delivered as f64 / PACKETS as f64;
+    assert!((lost - PACKET_LOSS).abs() < 0.05, "lost {} of the packets", lost);
+}
+
+#[tokio::test]
+async fn routes_udp_heartbeat_to_the_other_node() {
+    let air = SimulatedAir::new();
+    let autopilot = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let ground_station = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let uav = full_node(&air, autopilot.local_addr().unwrap()).await;
+    full_node(&air, ground_station.local_addr().unwrap()).await;
+
+    autopilot.send_to(&serialize_frame(heartbeat(9)), uav).await.unwrap();
+
+    let mut buffer = [0u8; 512];
+    let size = timeout(ROUTE_TIMEOUT, ground_station.recv(&mut buffer))
+        .await
+        .expect("the heartbeat did not reach the ground station")
+        .unwrap();
+    let packet = deserialize_frame(&buffer[..size]).unwrap();
+    assert_eq!((packet.header.system_id, packet.header.sequence), (201, 9));
+}